@@ -1,8 +1,16 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 
+layout(location = 0) in vec2 v_uv;
+
 layout(location = 0) out vec4 outColor;
 
+layout(set = 1, binding = 0) uniform texture2D t_BaseColor;
+layout(set = 1, binding = 1) uniform sampler s_BaseColor;
+layout(set = 1, binding = 2) uniform Material {
+    vec4 u_BaseColor;
+};
+
 void main() {
-    outColor = vec4(1.0, 0.0, 0.0, 1.0);
-}
\ No newline at end of file
+    outColor = texture(sampler2D(t_BaseColor, s_BaseColor), v_uv) * u_BaseColor;
+}
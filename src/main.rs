@@ -7,10 +7,13 @@ use winit::{
 use futures::executor::block_on;
 use nalgebra::{Vector3, Vector4, Vector, Point3};
 use wgpu::{read_spirv, PipelineLayout, PowerPreference, PresentMode, PrimitiveTopology, ProgrammableStageDescriptor, RasterizationStateDescriptor, RenderPipelineDescriptor, RequestAdapterOptions, Surface, SwapChainDescriptor, VertexStateDescriptor, VertexBufferDescriptor, BindGroupDescriptor, BufferUsage, Buffer};
-use rustgraphics::renderer::vertex::Vertex;
+use rustgraphics::renderer::batch::Batch;
+use rustgraphics::renderer::layout::{Packing, Semantic, VertexAttribute, VertexLayout};
 use rustgraphics::renderer::camera::Camera;
 use rustgraphics::renderer::gltfimporter::GLTFImporter;
 use rustgraphics::renderer::Primitive;
+use rustgraphics::renderer::instance::InstanceRaw;
+use rustgraphics::renderer::texture::{TextureLoader, TextureSlot};
 
 async fn run(event_loop: EventLoop<()>, window: Window) {
     let size = window.inner_size();
@@ -51,7 +54,15 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
     let fs_module =
         device.create_shader_module(&wgpu::read_spirv(std::io::Cursor::new(&fs[..])).unwrap());
 
-    let (mesh, materials, samplers) = GLTFImporter::import_single_mesh("cube.gltf".to_string()).unwrap();
+    let (mesh, materials, samplers, nodes) = GLTFImporter::import("cube.gltf".to_string()).unwrap();
+
+    // every node pointing to the mesh becomes one or more instances of a single draw
+    let instances: Vec<InstanceRaw> = nodes
+        .iter()
+        .filter(|x| x.mesh_index == Some(0))
+        .flat_map(|x| x.instance_data())
+        .collect();
+    let instance_buf = InstanceRaw::get_buffer(&instances, &device);
 
     let camera = Camera::new(Point3::new(10.0, 5.0, 10.0), Point3::new(0.0, 0.0, 0.0), sc_desc.width as f32 / sc_desc.height as f32, 45f32, 1.0, 100.0);
     let view = camera.build_projection_matrix();
@@ -84,12 +95,94 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
         label: None,
     });
 
-    let primitives_content : Vec<(Buffer, Buffer)> = mesh.primitives
+    let material_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: None,
+        bindings: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::SampledTexture {
+                    multisampled: false,
+                    component_type: wgpu::TextureComponentType::Float,
+                    dimension: wgpu::TextureViewDimension::D2,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Sampler { comparison: false },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+            }
+        ],
+    });
+
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::Repeat,
+        address_mode_w: wgpu::AddressMode::Repeat,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        lod_min_clamp: 0.0,
+        lod_max_clamp: 100.0,
+        compare: wgpu::CompareFunction::Undefined,
+    });
+
+    let mut texture_loader = TextureLoader::for_gltf("cube.gltf");
+    let mut init_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+    let material_content: Vec<(wgpu::TextureView, Buffer)> = materials
         .iter()
-        .map(|x| (x.get_index_buffer(&device), x.get_vertex_buffer(&device))).collect();
+        .map(|x| {
+            let texture = texture_loader.load_or_default(&device, &mut init_encoder, x.texture(), TextureSlot::BaseColor);
+            let color_buf = device.create_buffer_with_data(bytemuck::cast_slice(x.color().as_ref()), wgpu::BufferUsage::UNIFORM);
+
+            (texture.create_default_view(), color_buf)
+        }).collect();
+
+    queue.submit(&[init_encoder.finish()]);
+
+    let material_bind_groups: Vec<wgpu::BindGroup> = material_content
+        .iter()
+        .map(|(view, color_buf)| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &material_bind_group_layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: color_buf,
+                        range: 0..16
+                    }
+                }
+            ],
+            label: None,
+        })).collect();
+
+    // the shaders only read positions and uvs
+    let vertex_layout = VertexLayout::new(vec![VertexAttribute::new(Semantic::Position, wgpu::VertexFormat::Float3),
+                                               VertexAttribute::new(Semantic::TexCoord0, wgpu::VertexFormat::Float2)],
+                                          Packing::Interleaved);
+    let instance_slot = vertex_layout.stream_count() as u32;
+
+    // every primitive of the mesh shares one set of buffers
+    let batch = Batch::new(&mesh.primitives.iter().collect::<Vec<_>>(), &vertex_layout).unwrap();
+    let batch_vertex_buffers = batch.get_vertex_buffers(&device);
+    let batch_index_buffer = batch.get_index_buffer(&device);
 
     let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        bind_group_layouts: &[&bind_group_layout],
+        bind_group_layouts: &[&bind_group_layout, &material_bind_group_layout],
     });
 
     let uniform_buf = device.create_buffer_with_data(
@@ -97,36 +190,45 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
         wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
     );
 
-    let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        layout: &pipeline_layout,
-        vertex_stage: wgpu::ProgrammableStageDescriptor {
-            module: &vs_module,
-            entry_point: "main",
-        },
-        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-            module: &fs_module,
-            entry_point: "main",
-        }),
-        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: wgpu::CullMode::None,
-            depth_bias: 0,
-            depth_bias_slope_scale: 0.0,
-            depth_bias_clamp: 0.0,
-        }),
-        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-        color_states: &[wgpu::ColorStateDescriptor {
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            color_blend: wgpu::BlendDescriptor::REPLACE,
-            alpha_blend: wgpu::BlendDescriptor::REPLACE,
-            write_mask: wgpu::ColorWrite::ALL,
-        }],
-        depth_stencil_state: None,
-        vertex_state: Vertex::get_state_descriptor(),
-        sample_count: 1,
-        sample_mask: !0,
-        alpha_to_coverage_enabled: false,
-    });
+    let create_pipeline = |index_format: wgpu::IndexFormat| {
+        let mut vertex_buffers = vertex_layout.buffer_descriptors();
+        vertex_buffers.push(InstanceRaw::get_buffer_descriptor());
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: wgpu::TextureFormat::Bgra8UnormSrgb,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format,
+                vertex_buffers: &vertex_buffers
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        })
+    };
+
+    let render_pipeline = create_pipeline(batch.index_format());
 
 
     let mut swap_chain = device.create_swap_chain(&surface, &sc_desc);
@@ -165,13 +267,17 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
                         }],
                         depth_stencil_attachment: None,
                     });
-                    rpass.set_pipeline(&render_pipeline);
                     rpass.set_bind_group(0, &bind_group, &[]);
+                    rpass.set_pipeline(&render_pipeline);
+                    rpass.set_vertex_buffer(instance_slot, &instance_buf, 0, 0);
+                    rpass.set_index_buffer(&batch_index_buffer, 0, 0);
+                    for (slot, buffer) in batch_vertex_buffers.iter().enumerate() {
+                        rpass.set_vertex_buffer(slot as u32, buffer, 0, 0);
+                    }
 
-                    for i in 0..primitives_content.len() {
-                        rpass.set_index_buffer(&primitives_content[i].0, 0, 0);
-                        rpass.set_vertex_buffer(0, &primitives_content[i].1, 0, 0);
-                        rpass.draw_indexed(0..mesh.primitives[i].indices.len() as u32, 0, 0..1);
+                    for (primitive, range) in mesh.primitives.iter().zip(batch.ranges().iter()) {
+                        rpass.set_bind_group(1, &material_bind_groups[primitive.material_index], &[]);
+                        rpass.draw_indexed(range.indices(), range.base_vertex, 0..instances.len() as u32);
                     }
                 }
 
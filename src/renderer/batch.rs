@@ -0,0 +1,179 @@
+use std::ops::Range;
+use wgpu::{Buffer, BufferUsage, Device, IndexFormat, PrimitiveTopology};
+use crate::renderer::{indices::Indices, layout::VertexLayout, Primitive, RenderError};
+
+// where one primitive lives inside the shared buffers, indices stay relative to base_vertex
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrawRange {
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub index_count: u32
+}
+
+// many primitives packed into one buffer per layout stream and a single index buffer
+pub struct Batch {
+    layout: VertexLayout,
+    mode: PrimitiveTopology,
+    streams: Vec<Vec<u8>>,
+    indices: Indices,
+    ranges: Vec<DrawRange>,
+    vertex_count: usize
+}
+
+impl DrawRange {
+    // ready for RenderPass::draw_indexed
+    pub fn indices(&self) -> Range<u32> {
+        self.first_index..self.first_index + self.index_count
+    }
+}
+
+impl Batch {
+    // ranges come back in the order of primitives, primitives without indices draw every vertex in order
+    pub fn new(primitives: &[&Primitive], layout: &VertexLayout) -> Result<Self, RenderError> {
+        let mode = primitives.first().map_or(PrimitiveTopology::TriangleList, |x| x.mode);
+
+        if let Some(primitive) = primitives.iter().find(|x| x.mode != mode) {
+            return Err(RenderError::Geometry(format!("cannot batch {:?} with {:?}, primitives must share a topology", primitive.mode, mode)));
+        }
+
+        let mut streams: Vec<Vec<u8>> = vec![Vec::new(); layout.stream_count()];
+        let mut indices: Vec<u32> = Vec::new();
+        let mut ranges: Vec<DrawRange> = Vec::with_capacity(primitives.len());
+        let mut vertex_count = 0usize;
+        // base_vertex keeps the indices local, so 16 bits are enough while every primitive fits them
+        let mut largest_primitive = 0usize;
+
+        for primitive in primitives {
+            for (stream, data) in streams.iter_mut().zip(layout.pack(primitive)) {
+                stream.extend_from_slice(&data);
+            }
+
+            let first_index = indices.len() as u32;
+
+            if primitive.indices().is_empty() {
+                indices.extend(0..primitive.vertex().len() as u32);
+            } else {
+                indices.extend(primitive.indices().iter());
+            }
+
+            ranges.push(DrawRange {
+                first_index,
+                base_vertex: vertex_count as i32,
+                index_count: indices.len() as u32 - first_index
+            });
+
+            vertex_count += primitive.vertex().len();
+            largest_primitive = largest_primitive.max(primitive.vertex().len());
+        }
+
+        Ok(Self {
+            layout: layout.clone(),
+            mode,
+            streams,
+            indices: Indices::new(indices, largest_primitive),
+            ranges,
+            vertex_count
+        })
+    }
+
+    pub fn layout(&self) -> &VertexLayout {
+        &self.layout
+    }
+
+    pub fn mode(&self) -> PrimitiveTopology {
+        self.mode
+    }
+
+    pub fn ranges(&self) -> &Vec<DrawRange> {
+        &self.ranges
+    }
+
+    pub fn range(&self, primitive: usize) -> DrawRange {
+        self.ranges[primitive]
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertex_count
+    }
+
+    pub fn indices(&self) -> &Indices {
+        &self.indices
+    }
+
+    pub fn index_format(&self) -> IndexFormat {
+        self.indices.format()
+    }
+
+    // one buffer per stream of the layout, bound in the same order
+    pub fn get_vertex_buffers(&self, device: &Device) -> Vec<Buffer> {
+        self.streams
+            .iter()
+            .map(|x| device.create_buffer_with_data(x, BufferUsage::VERTEX))
+            .collect()
+    }
+
+    pub fn get_index_buffer(&self, device: &Device) -> Buffer {
+        self.indices.get_buffer(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{shapes, vertex::Vertex};
+
+    fn triangle() -> Primitive {
+        Primitive::new(vec![Vertex::default(); 3], Vec::new(), 0, PrimitiveTopology::TriangleList)
+    }
+
+    #[test]
+    fn ranges_follow_the_primitives() {
+        let cube = shapes::cube(1.0, 1).primitives.remove(0);
+        let plane = shapes::plane(1.0, 1.0, 2, 2).primitives.remove(0);
+        let triangle = triangle();
+        let layout = VertexLayout::standard();
+        let batch = Batch::new(&[&cube, &triangle, &plane], &layout).unwrap();
+
+        let cube_indices = cube.indices().len() as u32;
+        assert_eq!(batch.ranges(), &vec![
+            DrawRange { first_index: 0, base_vertex: 0, index_count: cube_indices },
+            DrawRange { first_index: cube_indices, base_vertex: 24, index_count: 3 },
+            DrawRange { first_index: cube_indices + 3, base_vertex: 27, index_count: plane.indices().len() as u32 }
+        ]);
+        assert_eq!(batch.range(1).indices(), cube_indices..cube_indices + 3);
+        assert_eq!(batch.vertex_count(), 24 + 3 + 9);
+
+        // indices stay local to their primitive, the unindexed one draws its vertices in order
+        let indices = batch.indices().to_vec();
+        assert_eq!(&indices[..cube_indices as usize], &cube.indices().to_vec()[..]);
+        assert_eq!(&indices[batch.range(1).indices().start as usize..batch.range(1).indices().end as usize], &[0, 1, 2]);
+        assert_eq!(&indices[batch.range(2).indices().start as usize..], &plane.indices().to_vec()[..]);
+
+        for stream in 0..layout.stream_count() {
+            assert_eq!(batch.streams[stream].len() as u64, batch.vertex_count() as u64 * layout.stride(stream));
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_indices_while_every_primitive_fits() {
+        let layout = VertexLayout::standard();
+        // more than 0xffff vertices in total, but never in a single primitive
+        let grids: Vec<Primitive> = (0..2).map(|_| shapes::plane(1.0, 1.0, 200, 200).primitives.remove(0)).collect();
+        let batch = Batch::new(&grids.iter().collect::<Vec<_>>(), &layout).unwrap();
+
+        assert!(batch.vertex_count() > 0xffff);
+        assert_eq!(batch.index_format(), IndexFormat::Uint16);
+        assert_eq!(batch.range(1).base_vertex, 201 * 201);
+
+        let large = shapes::plane(1.0, 1.0, 300, 300).primitives.remove(0);
+        assert_eq!(Batch::new(&[&large], &layout).unwrap().index_format(), IndexFormat::Uint32);
+    }
+
+    #[test]
+    fn mixed_topologies_are_rejected() {
+        let mut lines = triangle();
+        lines.mode = PrimitiveTopology::LineList;
+
+        assert!(Batch::new(&[&triangle(), &lines], &VertexLayout::standard()).is_err());
+    }
+}
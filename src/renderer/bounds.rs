@@ -0,0 +1,365 @@
+use nalgebra::{Point3, Vector3, Matrix4};
+
+// an empty box has min above max, so growing it by any point gives that point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Self {
+            min,
+            max
+        }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            min: Point3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Point3::new(f32::MIN, f32::MIN, f32::MIN)
+        }
+    }
+
+    pub fn from_points<'a, I>(points: I) -> Self
+        where I: IntoIterator<Item = &'a Point3<f32>> {
+        let mut aabb = Self::empty();
+
+        for point in points {
+            aabb.grow(point);
+        }
+
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn grow(&mut self, point: &Point3<f32>) {
+        self.min = self.min.inf(point);
+        self.max = self.max.sup(point);
+    }
+
+    pub fn merge(&self, other: &Aabb) -> Aabb {
+        Aabb::new(self.min.inf(&other.min), self.max.sup(&other.max))
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    pub fn half_extents(&self) -> Vector3<f32> {
+        self.size() * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+
+        let size = self.size();
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    pub fn contains(&self, point: &Point3<f32>) -> bool {
+        point.x >= self.min.x && point.x <= self.max.x &&
+            point.y >= self.min.y && point.y <= self.max.y &&
+            point.z >= self.min.z && point.z <= self.max.z
+    }
+
+    // zero inside the box
+    pub fn distance(&self, point: &Point3<f32>) -> f32 {
+        let outside = (self.min - point).sup(&(point - self.max)).sup(&Vector3::zeros());
+        outside.norm()
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && self.max.x >= other.min.x &&
+            self.min.y <= other.max.y && self.max.y >= other.min.y &&
+            self.min.z <= other.max.z && self.max.z >= other.min.z
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        [Point3::new(self.min.x, self.min.y, self.min.z),
+         Point3::new(self.max.x, self.min.y, self.min.z),
+         Point3::new(self.min.x, self.max.y, self.min.z),
+         Point3::new(self.max.x, self.max.y, self.min.z),
+         Point3::new(self.min.x, self.min.y, self.max.z),
+         Point3::new(self.max.x, self.min.y, self.max.z),
+         Point3::new(self.min.x, self.max.y, self.max.z),
+         Point3::new(self.max.x, self.max.y, self.max.z)]
+    }
+
+    // Arvo's method, the result encloses the transformed box without touching its eight corners
+    pub fn transform(&self, model: &Matrix4<f32>) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+
+        let translation = Vector3::new(model[(0, 3)], model[(1, 3)], model[(2, 3)]);
+        let mut min = translation;
+        let mut max = translation;
+
+        for row in 0..3 {
+            for column in 0..3 {
+                let a = model[(row, column)] * self.min[column];
+                let b = model[(row, column)] * self.max[column];
+                min[row] += a.min(b);
+                max[row] += a.max(b);
+            }
+        }
+
+        Aabb::new(Point3::from(min), Point3::from(max))
+    }
+}
+
+impl Default for BoundingSphere {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl BoundingSphere {
+    pub fn new(center: Point3<f32>, radius: f32) -> Self {
+        Self {
+            center,
+            radius
+        }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            center: Point3::origin(),
+            radius: -1.0
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.radius < 0.0
+    }
+
+    // Ritter's approximation, within a few percent of the minimal sphere
+    pub fn from_points(points: &[Point3<f32>]) -> Self {
+        if points.is_empty() {
+            return Self::empty();
+        }
+
+        let farthest = |from: &Point3<f32>| points
+            .iter()
+            .max_by(|a, b| nalgebra::distance_squared(from, a).total_cmp(&nalgebra::distance_squared(from, b)))
+            .unwrap();
+
+        let a = farthest(&points[0]);
+        let b = farthest(a);
+
+        let mut sphere = Self::new(nalgebra::center(a, b), nalgebra::distance(a, b) * 0.5);
+
+        for point in points {
+            sphere.grow(point);
+        }
+
+        sphere
+    }
+
+    pub fn grow(&mut self, point: &Point3<f32>) {
+        if self.is_empty() {
+            self.center = *point;
+            self.radius = 0.0;
+            return;
+        }
+
+        let distance = nalgebra::distance(&self.center, point);
+
+        if distance > self.radius {
+            let radius = (self.radius + distance) * 0.5;
+            self.center += (point - self.center) * ((radius - self.radius) / distance);
+            self.radius = radius;
+        }
+    }
+
+    pub fn merge(&self, other: &BoundingSphere) -> BoundingSphere {
+        if self.is_empty() {
+            return *other;
+        }
+
+        if other.is_empty() {
+            return *self;
+        }
+
+        let offset = other.center - self.center;
+        let distance = offset.norm();
+
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        let center = self.center + offset * ((radius - self.radius) / distance);
+
+        BoundingSphere::new(center, radius)
+    }
+
+    pub fn contains(&self, point: &Point3<f32>) -> bool {
+        nalgebra::distance(&self.center, point) <= self.radius
+    }
+
+    // a non uniform scale grows the radius by the largest axis scale
+    pub fn transform(&self, model: &Matrix4<f32>) -> BoundingSphere {
+        if self.is_empty() {
+            return *self;
+        }
+
+        let scale = (0..3)
+            .map(|column| Vector3::new(model[(0, column)], model[(1, column)], model[(2, column)]).norm())
+            .fold(0.0f32, f32::max);
+
+        BoundingSphere::new(model.transform_point(&self.center), self.radius * scale)
+    }
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Bounds {
+    pub fn new(aabb: Aabb, sphere: BoundingSphere) -> Self {
+        Self {
+            aabb,
+            sphere
+        }
+    }
+
+    pub fn empty() -> Self {
+        Self::new(Aabb::empty(), BoundingSphere::empty())
+    }
+
+    pub fn from_points(points: &[Point3<f32>]) -> Self {
+        Self::new(Aabb::from_points(points), BoundingSphere::from_points(points))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aabb.is_empty()
+    }
+
+    pub fn merge(&self, other: &Bounds) -> Bounds {
+        Bounds::new(self.aabb.merge(&other.aabb), self.sphere.merge(&other.sphere))
+    }
+
+    pub fn transform(&self, model: &Matrix4<f32>) -> Bounds {
+        Bounds::new(self.aabb.transform(model), self.sphere.transform(model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Rotation3, Vector2, Vector4};
+    use wgpu::PrimitiveTopology;
+    use super::*;
+    use crate::renderer::{vertex::Vertex, Primitive};
+
+    fn close(a: &Point3<f32>, b: &Point3<f32>) -> bool {
+        nalgebra::distance(a, b) < 1e-5
+    }
+
+    #[test]
+    fn bounds_of_known_points() {
+        let axes = [Point3::new(1.0, 0.0, 0.0), Point3::new(-1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0),
+                    Point3::new(0.0, -1.0, 0.0), Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, -1.0)];
+        let bounds = Bounds::from_points(&axes);
+
+        assert_eq!(bounds.aabb, Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)));
+        assert_eq!(bounds.sphere, BoundingSphere::new(Point3::origin(), 1.0));
+
+        let points = [Point3::new(0.0, 0.0, 0.0), Point3::new(4.0, 1.0, 0.0), Point3::new(1.0, -2.0, 3.0), Point3::new(2.0, 2.0, 2.0)];
+        let bounds = Bounds::from_points(&points);
+
+        assert_eq!(bounds.aabb, Aabb::new(Point3::new(0.0, -2.0, 0.0), Point3::new(4.0, 2.0, 3.0)));
+        assert!(points.iter().all(|x| nalgebra::distance(&bounds.sphere.center, x) <= bounds.sphere.radius + 1e-5));
+        assert!(Bounds::from_points(&[]).is_empty());
+    }
+
+    #[test]
+    fn transform_under_rotation_and_scale() {
+        let aabb = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 3.0));
+        let model = Matrix4::new_translation(&Vector3::new(1.0, 0.0, 0.0))
+            * Rotation3::from_axis_angle(&Vector3::z_axis(), std::f32::consts::FRAC_PI_2).to_homogeneous()
+            * Matrix4::new_scaling(2.0);
+
+        // x becomes y and y becomes -x
+        let transformed = aabb.transform(&model);
+        assert!(close(&transformed.min, &Point3::new(-3.0, 0.0, 0.0)));
+        assert!(close(&transformed.max, &Point3::new(1.0, 2.0, 6.0)));
+
+        let corners = Aabb::from_points(aabb.corners().iter().map(|x| model.transform_point(x)).collect::<Vec<_>>().iter());
+        assert!(close(&transformed.min, &corners.min) && close(&transformed.max, &corners.max));
+
+        let sphere = BoundingSphere::new(Point3::new(1.0, 0.0, 0.0), 0.5).transform(&model);
+        assert!(close(&sphere.center, &Point3::new(1.0, 2.0, 0.0)));
+        assert!((sphere.radius - 1.0).abs() < 1e-5);
+
+        let stretched = BoundingSphere::new(Point3::origin(), 1.0).transform(&Matrix4::new_nonuniform_scaling(&Vector3::new(1.0, 3.0, 0.5)));
+        assert_eq!(stretched.radius, 3.0);
+    }
+
+    #[test]
+    fn merging_with_empty_changes_nothing() {
+        let bounds = Bounds::from_points(&[Point3::new(1.0, 2.0, 3.0), Point3::new(-1.0, 0.0, 1.0)]);
+
+        assert_eq!(Bounds::empty().merge(&bounds), bounds);
+        assert_eq!(bounds.merge(&Bounds::empty()), bounds);
+        assert!(Bounds::empty().merge(&Bounds::empty()).is_empty());
+        assert!(Bounds::empty().transform(&Matrix4::new_scaling(2.0)).is_empty());
+
+        let other = Bounds::from_points(&[Point3::new(5.0, 0.0, 0.0)]);
+        let merged = bounds.merge(&other);
+        assert_eq!(merged.aabb, Aabb::new(Point3::new(-1.0, 0.0, 0.0), Point3::new(5.0, 2.0, 3.0)));
+        assert!(merged.sphere.contains(&Point3::new(5.0, 0.0, 0.0)) && merged.sphere.contains(&Point3::new(1.0, 2.0, 3.0)));
+    }
+
+    #[test]
+    fn primitive_bounds_follow_vertex_changes() {
+        let vertex = |x: f32| Vertex::new(Vector3::new(x, 0.0, 0.0), Vector3::y(), Vector4::zeros(), Vector2::zeros());
+        let mut primitive = Primitive::new(vec![vertex(0.0), vertex(1.0)], Vec::new(), 0, PrimitiveTopology::PointList);
+
+        assert_eq!(primitive.bounds().aabb.max.x, 1.0);
+
+        primitive.vertex_mut()[1].set_position(Vector3::new(4.0, 0.0, 0.0));
+        assert_eq!(primitive.bounds().aabb.max.x, 4.0);
+
+        primitive.push_vertex(vertex(-2.0));
+        assert_eq!(primitive.bounds().aabb.min.x, -2.0);
+
+        let model = Matrix4::new_translation(&Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(primitive.transformed_bounds(&model).aabb.max, Point3::new(4.0, 1.0, 0.0));
+    }
+}
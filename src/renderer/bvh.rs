@@ -0,0 +1,798 @@
+use nalgebra::{Matrix4, Point3, Vector3};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{bounds::Aabb, node::Node, Mesh};
+
+const BINS: usize = 16;
+const MAX_LEAF_SIZE: usize = 4;
+// cost of visiting a node relative to testing one item
+const TRAVERSAL_COST: f32 = 1.0;
+// lets rays through shared edges and vertices hit despite rounding
+const BARYCENTRIC_EPSILON: f32 = 1e-6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    // distances are measured in lengths of the direction
+    pub direction: Vector3<f32>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub primitive: usize,
+    pub triangle: usize,
+    // weights of the triangle's three corners, in index order
+    pub barycentric: Vector3<f32>,
+    pub distance: f32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClosestPoint {
+    pub primitive: usize,
+    pub triangle: usize,
+    pub point: Point3<f32>,
+    pub barycentric: Vector3<f32>,
+    pub distance: f32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SceneHit {
+    pub node: usize,
+    // index into the node's instances when it is instanced
+    pub instance: Option<usize>,
+    pub hit: Hit
+}
+
+// leaves have a count, inner nodes keep their children next to each other from first
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    first: u32,
+    count: u32
+}
+
+// built over item bounds only, what an item is belongs to the owner
+struct Bvh {
+    nodes: Vec<BvhNode>,
+    order: Vec<u32>
+}
+
+struct Triangle {
+    primitive: u32,
+    index: u32,
+    corners: [Point3<f32>; 3]
+}
+
+pub struct MeshBvh {
+    bvh: Bvh,
+    triangles: Vec<Triangle>
+}
+
+struct SceneEntry {
+    node: usize,
+    instance: Option<usize>,
+    mesh: usize,
+    inverse: Matrix4<f32>
+}
+
+pub struct SceneBvh {
+    bvh: Bvh,
+    meshes: Vec<MeshBvh>,
+    entries: Vec<SceneEntry>
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self {
+        Self {
+            origin,
+            direction
+        }
+    }
+
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    // the direction is not renormalized, so distances keep meaning the same point on both sides
+    pub fn transform(&self, model: &Matrix4<f32>) -> Ray {
+        Ray::new(model.transform_point(&self.origin), model.transform_vector(&self.direction))
+    }
+
+    // entry distance into the box, clamped to the origin
+    pub fn intersect_aabb(&self, aabb: &Aabb, max_distance: f32) -> Option<f32> {
+        let mut near = 0f32;
+        let mut far = max_distance;
+
+        for axis in 0..3 {
+            // parallel to the slab, a ray lying in its plane would turn into NaN below
+            if self.direction[axis] == 0.0 {
+                if self.origin[axis] < aabb.min[axis] || self.origin[axis] > aabb.max[axis] {
+                    return None;
+                }
+
+                continue;
+            }
+
+            let inverse = 1.0 / self.direction[axis];
+            let a = (aabb.min[axis] - self.origin[axis]) * inverse;
+            let b = (aabb.max[axis] - self.origin[axis]) * inverse;
+
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+
+        if near <= far { Some(near) } else { None }
+    }
+}
+
+// Möller and Trumbore, both sides hit, returns the distance and the weights of b and c
+pub fn intersect_triangle(ray: &Ray, a: &Point3<f32>, b: &Point3<f32>, c: &Point3<f32>) -> Option<(f32, f32, f32)> {
+    let edge_1 = b - a;
+    let edge_2 = c - a;
+    let p = ray.direction.cross(&edge_2);
+    let determinant = edge_1.dot(&p);
+
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    let s = ray.origin - a;
+    let u = s.dot(&p) * inverse;
+
+    if !(-BARYCENTRIC_EPSILON..=1.0 + BARYCENTRIC_EPSILON).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(&edge_1);
+    let v = ray.direction.dot(&q) * inverse;
+
+    if v < -BARYCENTRIC_EPSILON || u + v > 1.0 + BARYCENTRIC_EPSILON {
+        return None;
+    }
+
+    let distance = edge_2.dot(&q) * inverse;
+
+    if distance < 0.0 {
+        return None;
+    }
+
+    // keep the weights inside the triangle
+    let u = u.max(0.0);
+    let v = v.max(0.0);
+    let sum = u + v;
+
+    if sum > 1.0 {
+        return Some((distance, u / sum, v / sum));
+    }
+
+    Some((distance, u, v))
+}
+
+// Ericson's region tests, returns the point and its weights of a, b and c
+pub fn closest_point_on_triangle(point: &Point3<f32>,
+                                 a: &Point3<f32>,
+                                 b: &Point3<f32>,
+                                 c: &Point3<f32>) -> (Point3<f32>, Vector3<f32>) {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = point - a;
+    let d1 = ab.dot(&ap);
+    let d2 = ac.dot(&ap);
+
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return (*a, Vector3::x());
+    }
+
+    let bp = point - b;
+    let d3 = ab.dot(&bp);
+    let d4 = ac.dot(&bp);
+
+    if d3 >= 0.0 && d4 <= d3 {
+        return (*b, Vector3::y());
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return (a + ab * v, Vector3::new(1.0 - v, v, 0.0));
+    }
+
+    let cp = point - c;
+    let d5 = ab.dot(&cp);
+    let d6 = ac.dot(&cp);
+
+    if d6 >= 0.0 && d5 <= d6 {
+        return (*c, Vector3::z());
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return (a + ac * w, Vector3::new(1.0 - w, 0.0, w));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return (b + (c - b) * w, Vector3::new(0.0, 1.0 - w, w));
+    }
+
+    let denominator = 1.0 / (va + vb + vc);
+    let v = vb * denominator;
+    let w = vc * denominator;
+
+    (a + ab * v + ac * w, Vector3::new(1.0 - v - w, v, w))
+}
+
+impl Hit {
+    pub fn position(&self, ray: &Ray) -> Point3<f32> {
+        ray.at(self.distance)
+    }
+}
+
+impl Bvh {
+    fn new(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(bounds.len() * 2),
+            order: (0..bounds.len() as u32).collect()
+        };
+
+        let centroids: Vec<Point3<f32>> = bounds.iter().map(Aabb::center).collect();
+        bvh.nodes.push(BvhNode { bounds: Aabb::empty(), first: 0, count: 0 });
+        bvh.build(0, 0, bounds.len(), bounds, &centroids);
+
+        bvh
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.nodes[0].bounds
+    }
+
+    fn build(&mut self, slot: usize, first: usize, count: usize, bounds: &[Aabb], centroids: &[Point3<f32>]) {
+        let items = &self.order[first..first + count];
+        let node_bounds = items.iter().fold(Aabb::empty(), |x, item| x.merge(&bounds[*item as usize]));
+        let centroid_bounds = Aabb::from_points(items.iter().map(|x| &centroids[*x as usize]));
+
+        self.nodes[slot] = BvhNode { bounds: node_bounds, first: first as u32, count: count as u32 };
+
+        if count <= 1 {
+            return;
+        }
+
+        let split = match self.find_split(first, count, &node_bounds, &centroid_bounds, bounds, centroids) {
+            Some(split) => split,
+            None => return
+        };
+
+        // partition the order around the chosen plane
+        let (axis, plane) = split;
+        let items = &mut self.order[first..first + count];
+        let mut left = 0;
+
+        for i in 0..items.len() {
+            if centroids[items[i] as usize][axis] < plane {
+                items.swap(i, left);
+                left += 1;
+            }
+        }
+
+        if left == 0 || left == count {
+            return;
+        }
+
+        let children = self.nodes.len();
+        self.nodes[slot] = BvhNode { bounds: node_bounds, first: children as u32, count: 0 };
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first: 0, count: 0 });
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first: 0, count: 0 });
+
+        self.build(children, first, left, bounds, centroids);
+        self.build(children + 1, first + left, count - left, bounds, centroids);
+    }
+
+    // binned surface area heuristic, None when a leaf is cheaper
+    fn find_split(&self,
+                  first: usize,
+                  count: usize,
+                  node_bounds: &Aabb,
+                  centroid_bounds: &Aabb,
+                  bounds: &[Aabb],
+                  centroids: &[Point3<f32>]) -> Option<(usize, f32)> {
+        let items = &self.order[first..first + count];
+        let parent_area = node_bounds.surface_area().max(f32::MIN_POSITIVE);
+        let mut best = (f32::INFINITY, 0, 0f32);
+
+        for (axis, (min, max)) in centroid_bounds.min.iter().zip(centroid_bounds.max.iter()).enumerate() {
+            let min = *min;
+            let extent = max - min;
+
+            if extent <= 0.0 {
+                continue;
+            }
+
+            let bin_of = |item: u32| (((centroids[item as usize][axis] - min) / extent * BINS as f32) as usize).min(BINS - 1);
+
+            let mut bin_bounds = [Aabb::empty(); BINS];
+            let mut bin_counts = [0usize; BINS];
+
+            for item in items {
+                let bin = bin_of(*item);
+                bin_bounds[bin] = bin_bounds[bin].merge(&bounds[*item as usize]);
+                bin_counts[bin] += 1;
+            }
+
+            // sweep from the right so every left sweep step can read the cost of the right side
+            let mut right_areas = [0f32; BINS];
+            let mut right_counts = [0usize; BINS];
+            let mut accumulated = Aabb::empty();
+            let mut accumulated_count = 0;
+
+            for bin in (1..BINS).rev() {
+                accumulated = accumulated.merge(&bin_bounds[bin]);
+                accumulated_count += bin_counts[bin];
+                right_areas[bin] = accumulated.surface_area();
+                right_counts[bin] = accumulated_count;
+            }
+
+            let mut left_bounds = Aabb::empty();
+            let mut left_count = 0;
+
+            for bin in 1..BINS {
+                left_bounds = left_bounds.merge(&bin_bounds[bin - 1]);
+                left_count += bin_counts[bin - 1];
+
+                if left_count == 0 || right_counts[bin] == 0 {
+                    continue;
+                }
+
+                let cost = TRAVERSAL_COST +
+                    (left_bounds.surface_area() * left_count as f32 + right_areas[bin] * right_counts[bin] as f32) / parent_area;
+
+                if cost < best.0 {
+                    best = (cost, axis, min + extent * bin as f32 / BINS as f32);
+                }
+            }
+        }
+
+        let (cost, axis, plane) = best;
+
+        // every centroid in one spot leaves nothing to split
+        if cost == f32::INFINITY || (cost >= count as f32 && count <= MAX_LEAF_SIZE) {
+            return None;
+        }
+
+        Some((axis, plane))
+    }
+
+    // visit returns the new closest distance when an item was hit, children are entered nearest first
+    fn traverse<F>(&self, ray: &Ray, max_distance: f32, mut visit: F)
+        where F: FnMut(u32, f32) -> Option<f32> {
+        if self.order.is_empty() {
+            return;
+        }
+
+        let mut closest = max_distance;
+        let mut stack: Vec<u32> = vec![0];
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+
+            if ray.intersect_aabb(&node.bounds, closest).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                for item in &self.order[node.first as usize..(node.first + node.count) as usize] {
+                    if let Some(distance) = visit(*item, closest) {
+                        closest = closest.min(distance);
+                    }
+                }
+
+                continue;
+            }
+
+            let left = node.first;
+            let right = node.first + 1;
+            let left_distance = ray.intersect_aabb(&self.nodes[left as usize].bounds, closest);
+            let right_distance = ray.intersect_aabb(&self.nodes[right as usize].bounds, closest);
+
+            match (left_distance, right_distance) {
+                (Some(l), Some(r)) if l <= r => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+                (Some(_), Some(_)) => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+                (Some(_), None) => stack.push(left),
+                (None, Some(_)) => stack.push(right),
+                (None, None) => {}
+            }
+        }
+    }
+
+    // visit returns the new closest distance when an item was closer, children are entered nearest first
+    fn nearest<F>(&self, point: &Point3<f32>, max_distance: f32, mut visit: F)
+        where F: FnMut(u32, f32) -> Option<f32> {
+        if self.order.is_empty() {
+            return;
+        }
+
+        let mut closest = max_distance;
+        let mut stack: Vec<(u32, f32)> = vec![(0, self.nodes[0].bounds.distance(point))];
+
+        while let Some((index, distance)) = stack.pop() {
+            if distance > closest {
+                continue;
+            }
+
+            let node = &self.nodes[index as usize];
+
+            if node.count > 0 {
+                for item in &self.order[node.first as usize..(node.first + node.count) as usize] {
+                    if let Some(distance) = visit(*item, closest) {
+                        closest = closest.min(distance);
+                    }
+                }
+
+                continue;
+            }
+
+            let left = (node.first, self.nodes[node.first as usize].bounds.distance(point));
+            let right = (node.first + 1, self.nodes[node.first as usize + 1].bounds.distance(point));
+
+            if left.1 <= right.1 {
+                stack.push(right);
+                stack.push(left);
+            } else {
+                stack.push(left);
+                stack.push(right);
+            }
+        }
+    }
+}
+
+impl MeshBvh {
+    // triangle lists only, other topologies have no surface to hit
+    pub fn new(mesh: &Mesh) -> Self {
+        let mut triangles: Vec<Triangle> = Vec::new();
+
+        for (primitive_index, primitive) in mesh.primitives.iter().enumerate() {
+            if primitive.mode != PrimitiveTopology::TriangleList {
+                continue;
+            }
+
+            let indices: Vec<u32> = if primitive.indices().is_empty() {
+                (0..primitive.vertex().len() as u32).collect()
+            } else {
+                primitive.indices().to_vec()
+            };
+
+            for (index, triangle) in indices.chunks_exact(3).enumerate() {
+                let corner = |x: usize| Point3::from(*primitive.vertex()[triangle[x] as usize].position());
+
+                triangles.push(Triangle {
+                    primitive: primitive_index as u32,
+                    index: index as u32,
+                    corners: [corner(0), corner(1), corner(2)]
+                });
+            }
+        }
+
+        let bounds: Vec<Aabb> = triangles.iter().map(|x| Aabb::from_points(x.corners.iter())).collect();
+
+        Self {
+            bvh: Bvh::new(&bounds),
+            triangles
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        if self.triangles.is_empty() {
+            return Aabb::empty();
+        }
+
+        self.bvh.bounds()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.bvh.nodes.len()
+    }
+
+    // nearest hit no farther than max_distance
+    pub fn intersect(&self, ray: &Ray, max_distance: f32) -> Option<Hit> {
+        let mut nearest: Option<Hit> = None;
+
+        self.bvh.traverse(ray, max_distance, |item, closest| {
+            let triangle = &self.triangles[item as usize];
+            let [a, b, c] = &triangle.corners;
+            let (distance, u, v) = intersect_triangle(ray, a, b, c)?;
+
+            if distance > closest {
+                return None;
+            }
+
+            nearest = Some(Hit {
+                primitive: triangle.primitive as usize,
+                triangle: triangle.index as usize,
+                barycentric: Vector3::new(1.0 - u - v, u, v),
+                distance
+            });
+
+            Some(distance)
+        });
+
+        nearest
+    }
+
+    pub fn closest_point(&self, point: &Point3<f32>, max_distance: f32) -> Option<ClosestPoint> {
+        let mut nearest: Option<ClosestPoint> = None;
+
+        self.bvh.nearest(point, max_distance, |item, closest| {
+            let triangle = &self.triangles[item as usize];
+            let [a, b, c] = &triangle.corners;
+            let (closest_point, barycentric) = closest_point_on_triangle(point, a, b, c);
+            let distance = nalgebra::distance(point, &closest_point);
+
+            if distance > closest {
+                return None;
+            }
+
+            nearest = Some(ClosestPoint {
+                primitive: triangle.primitive as usize,
+                triangle: triangle.index as usize,
+                point: closest_point,
+                barycentric,
+                distance
+            });
+
+            Some(distance)
+        });
+
+        nearest
+    }
+
+    // surfaces crossed along the whole ray, hits on a shared edge or vertex count once
+    pub fn crossings(&self, ray: &Ray) -> usize {
+        let mut distances: Vec<f32> = Vec::new();
+
+        self.bvh.traverse(ray, f32::INFINITY, |item, _| {
+            let [a, b, c] = &self.triangles[item as usize].corners;
+
+            if let Some((distance, _, _)) = intersect_triangle(ray, a, b, c) {
+                distances.push(distance);
+            }
+
+            None
+        });
+
+        count_crossings(ray, &self.bounds(), distances)
+    }
+}
+
+// distinct hit distances along a ray, hits closer than a millionth of the bounds belong to one shared edge or vertex
+pub fn count_crossings(ray: &Ray, bounds: &Aabb, mut distances: Vec<f32>) -> usize {
+    distances.sort_by(|a, b| a.total_cmp(b));
+
+    let tolerance = bounds.size().norm() * 1e-6 / ray.direction.norm();
+    let mut count = 0;
+    let mut last = f32::NEG_INFINITY;
+
+    for distance in distances {
+        if distance - last > tolerance {
+            count += 1;
+        }
+
+        last = distance;
+    }
+
+    count
+}
+
+impl SceneBvh {
+    // meshes are indexed by Node::mesh_index, every instance of a node gets its own entry
+    pub fn new(nodes: &[Node], meshes: Vec<MeshBvh>) -> Self {
+        let mut entries: Vec<SceneEntry> = Vec::new();
+        let mut bounds: Vec<Aabb> = Vec::new();
+
+        for (node_index, node) in nodes.iter().enumerate() {
+            let mesh = match node.mesh_index {
+                Some(mesh) if mesh < meshes.len() => mesh,
+                _ => continue
+            };
+
+            let transforms: Vec<(Option<usize>, Matrix4<f32>)> = if node.is_instanced() {
+                node.instances.iter().enumerate().map(|(i, x)| (Some(i), node.transform * x.to_matrix())).collect()
+            } else {
+                vec![(None, node.transform)]
+            };
+
+            for (instance, transform) in transforms {
+                // a collapsed transform has nothing to hit
+                if let Some(inverse) = transform.try_inverse() {
+                    bounds.push(meshes[mesh].bounds().transform(&transform));
+                    entries.push(SceneEntry { node: node_index, instance, mesh, inverse });
+                }
+            }
+        }
+
+        Self {
+            bvh: Bvh::new(&bounds),
+            meshes,
+            entries
+        }
+    }
+
+    pub fn meshes(&self) -> &Vec<MeshBvh> {
+        &self.meshes
+    }
+
+    // world space ray, the distance of the hit is in world units of the ray direction
+    pub fn intersect(&self, ray: &Ray, max_distance: f32) -> Option<SceneHit> {
+        let mut nearest: Option<SceneHit> = None;
+
+        self.bvh.traverse(ray, max_distance, |item, closest| {
+            let entry = &self.entries[item as usize];
+            let hit = self.meshes[entry.mesh].intersect(&ray.transform(&entry.inverse), closest)?;
+
+            nearest = Some(SceneHit { node: entry.node, instance: entry.instance, hit });
+            Some(hit.distance)
+        });
+
+        nearest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{UnitQuaternion, Vector3};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use crate::renderer::{instance::Instance, shapes};
+
+    fn test_mesh() -> Mesh {
+        let sphere = shapes::uv_sphere(1.0, 16, 8).primitives.remove(0);
+        let torus = shapes::torus(1.2, 0.3, 24, 12).primitives.remove(0);
+        // triangles without indices go through the same path as indexed ones
+        let cube = shapes::cube(0.8, 2).primitives.remove(0);
+        let unindexed = cube.select_vertices(&cube.indices().to_vec(), Vec::new());
+
+        Mesh::new(vec![sphere, torus, unindexed])
+    }
+
+    // nearest hit of every triangle, tested one by one
+    fn brute_force(mesh: &Mesh, ray: &Ray, max_distance: f32) -> Option<(usize, usize, f32)> {
+        let mut nearest: Option<(usize, usize, f32)> = None;
+
+        for (primitive_index, primitive) in mesh.primitives.iter().enumerate() {
+            let indices: Vec<u32> = if primitive.indices().is_empty() {
+                (0..primitive.vertex().len() as u32).collect()
+            } else {
+                primitive.indices().to_vec()
+            };
+
+            for (index, triangle) in indices.chunks_exact(3).enumerate() {
+                let corner = |x: usize| Point3::from(*primitive.vertex()[triangle[x] as usize].position());
+
+                if let Some((distance, _, _)) = intersect_triangle(ray, &corner(0), &corner(1), &corner(2)) {
+                    if distance <= max_distance && nearest.is_none_or(|x| distance < x.2) {
+                        nearest = Some((primitive_index, index, distance));
+                    }
+                }
+            }
+        }
+
+        nearest
+    }
+
+    fn random_ray(rng: &mut StdRng) -> Ray {
+        let mut point = |scale: f32| Point3::new(rng.gen_range(-scale, scale), rng.gen_range(-scale, scale), rng.gen_range(-scale, scale));
+        let origin = point(4.0);
+        let target = point(1.5);
+
+        Ray::new(origin, target - origin)
+    }
+
+    // the reported triangle has to be hit at the reported distance, ties between neighbours can go either way
+    fn assert_same_hit(mesh: &Mesh, ray: &Ray, hit: Option<Hit>, expected: Option<(usize, usize, f32)>) {
+        match (hit, expected) {
+            (None, None) => {}
+            (Some(hit), Some((_, _, distance))) => {
+                assert_eq!(hit.distance, distance);
+
+                let primitive = &mesh.primitives[hit.primitive];
+                let indices = if primitive.indices().is_empty() {
+                    (0..primitive.vertex().len() as u32).collect()
+                } else {
+                    primitive.indices().to_vec()
+                };
+                let corner = |x: usize| Point3::from(*primitive.vertex()[indices[hit.triangle * 3 + x] as usize].position());
+                let point = corner(0) * hit.barycentric.x + corner(1).coords * hit.barycentric.y + corner(2).coords * hit.barycentric.z;
+
+                assert!((point - ray.at(hit.distance)).norm() < 1e-4);
+            }
+            (hit, expected) => panic!("bvh {:?} against brute force {:?}", hit, expected)
+        }
+    }
+
+    #[test]
+    fn mesh_hits_match_brute_force() {
+        let mesh = test_mesh();
+        let bvh = MeshBvh::new(&mesh);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut hits = 0;
+
+        assert_eq!(bvh.triangle_count(), mesh.primitives.iter().map(|x| x.indices().len().max(x.vertex().len()) / 3).sum::<usize>());
+
+        for attempt in 0..400 {
+            let ray = random_ray(&mut rng);
+            let max_distance = if attempt % 3 == 0 { 3.0 } else { f32::MAX };
+            let expected = brute_force(&mesh, &ray, max_distance);
+
+            hits += expected.is_some() as usize;
+            assert_same_hit(&mesh, &ray, bvh.intersect(&ray, max_distance), expected);
+        }
+
+        assert!(hits > 100, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn scene_hits_match_brute_force() {
+        let mesh = test_mesh();
+        let translation = Matrix4::new_translation(&Vector3::new(3.0, 0.0, 0.0));
+        let instances = vec![Instance::new(Vector3::new(0.0, 0.0, 3.0), UnitQuaternion::identity(), Vector3::repeat(0.5)),
+                             Instance::new(Vector3::new(0.0, 2.0, -3.0), UnitQuaternion::from_euler_angles(0.3, 1.1, 0.0), Vector3::new(1.0, 2.0, 1.0)),
+                             Instance::new(Vector3::zeros(), UnitQuaternion::identity(), Vector3::zeros())];
+        let nodes = vec![Node::new(Some(0), Matrix4::identity(), Vec::new()),
+                         Node::new(Some(0), translation, Vec::new()),
+                         Node::new(None, Matrix4::identity(), Vec::new()),
+                         Node::new(Some(0), translation, instances)];
+
+        // world transforms of everything a ray can hit, the collapsed instance has none
+        let mut entries: Vec<(usize, Option<usize>, Matrix4<f32>)> = Vec::new();
+
+        for (index, node) in nodes.iter().enumerate().filter(|(_, x)| x.mesh_index.is_some()) {
+            if node.is_instanced() {
+                entries.extend(node.instances.iter().enumerate().map(|(i, x)| (index, Some(i), node.transform * x.to_matrix())));
+            } else {
+                entries.push((index, None, node.transform));
+            }
+        }
+
+        let scene = SceneBvh::new(&nodes, vec![MeshBvh::new(&mesh)]);
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut hits = 0;
+
+        for _ in 0..400 {
+            let mut ray = random_ray(&mut rng);
+            ray.origin.x += 1.5;
+
+            let expected = entries
+                .iter()
+                .filter_map(|(node, instance, transform)| {
+                    let local = ray.transform(&transform.try_inverse()?);
+                    brute_force(&mesh, &local, f32::MAX).map(|x| (*node, *instance, x.2))
+                })
+                .min_by(|a, b| a.2.total_cmp(&b.2));
+
+            match (scene.intersect(&ray, f32::MAX), expected) {
+                (None, None) => {}
+                (Some(hit), Some((node, instance, distance))) => {
+                    hits += 1;
+                    assert_eq!(hit.hit.distance, distance);
+                    assert_eq!((hit.node, hit.instance), (node, instance));
+                }
+                (hit, expected) => panic!("scene {:?} against brute force {:?}", hit, expected)
+            }
+        }
+
+        assert!(hits > 100, "only {} rays hit", hits);
+    }
+}
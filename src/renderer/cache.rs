@@ -0,0 +1,608 @@
+use std::{fs, path::Path};
+use bytemuck::{Pod, Zeroable};
+use nalgebra::Vector4;
+use wgpu::PrimitiveTopology;
+use crate::renderer::{gltfimporter::decode_uri, indices::Indices, layout::Semantic, material::Material, vertex::Vertex, Lod, Mesh, Primitive, RenderError};
+
+const MAGIC: [u8; 4] = *b"RGMC";
+// bump whenever the layout below or Vertex changes
+const VERSION: u32 = 1;
+// every section starts on this boundary so the records can be read in place
+const ALIGNMENT: usize = 8;
+const NO_STRING: u32 = u32::MAX;
+
+// little endian throughout, the file is only meant for the machine that wrote it
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    magic: [u8; 4],
+    version: u32,
+    vertex_size: u64,
+    source_size: u64,
+    source_hash: u64,
+    // over everything that follows the header
+    checksum: u64,
+    file_size: u64,
+    primitive_count: u64,
+    lod_count: u64,
+    material_offset: u64,
+    material_count: u64
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct LodRecord {
+    error: f32,
+    primitive_count: u32
+}
+
+// offsets are from the start of the file
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PrimitiveRecord {
+    material_index: u64,
+    mode: u64,
+    index_format: u64,
+    // one bit per position in Semantic::ALL
+    attribute_mask: u64,
+    vertex_offset: u64,
+    vertex_count: u64,
+    index_offset: u64,
+    index_count: u64,
+    attribute_offset: u64
+}
+
+unsafe impl Zeroable for Header {}
+unsafe impl Pod for Header {}
+unsafe impl Zeroable for LodRecord {}
+unsafe impl Pod for LodRecord {}
+unsafe impl Zeroable for PrimitiveRecord {}
+unsafe impl Pod for PrimitiveRecord {}
+
+enum Storage {
+    // u64 keeps every section aligned without mapping
+    #[cfg(not(feature = "mmap"))]
+    Owned(Vec<u64>, usize),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap::Mmap)
+}
+
+// a validated cache file, vertex and index data are read in place
+pub struct MeshCache {
+    storage: Storage
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize
+}
+
+// FNV-1a, enough to notice a changed source or a damaged file
+fn hash(bytes: &[u8]) -> u64 {
+    continue_hash(0xcbf2_9ce4_8422_2325, bytes)
+}
+
+fn continue_hash(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |x, byte| (x ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn mode_to_u64(mode: PrimitiveTopology) -> u64 {
+    match mode {
+        PrimitiveTopology::PointList => 0,
+        PrimitiveTopology::LineList => 1,
+        PrimitiveTopology::LineStrip => 2,
+        PrimitiveTopology::TriangleList => 3,
+        PrimitiveTopology::TriangleStrip => 4
+    }
+}
+
+fn mode_from_u64(mode: u64) -> Result<PrimitiveTopology, RenderError> {
+    match mode {
+        0 => Ok(PrimitiveTopology::PointList),
+        1 => Ok(PrimitiveTopology::LineList),
+        2 => Ok(PrimitiveTopology::LineStrip),
+        3 => Ok(PrimitiveTopology::TriangleList),
+        4 => Ok(PrimitiveTopology::TriangleStrip),
+        _ => Err(RenderError::Cache(format!("unknown topology {}", mode)))
+    }
+}
+
+// a gltf source also covers the external buffers it points at, in the order they are declared
+fn source_fingerprint<P: AsRef<Path>>(source: P) -> Result<(u64, u64), RenderError> {
+    let bytes = fs::read(&source)?;
+    let mut size = bytes.len() as u64;
+    let mut fingerprint = hash(&bytes);
+
+    if let Ok(document) = gltf::Gltf::from_slice(&bytes) {
+        let directory = source.as_ref().parent().unwrap_or_else(|| Path::new(""));
+
+        for buffer in document.buffers() {
+            match buffer.source() {
+                // embedded data is already part of the source bytes
+                gltf::buffer::Source::Uri(uri) if !uri.starts_with("data:") => {
+                    let buffer = fs::read(directory.join(decode_uri(uri)))?;
+                    size += buffer.len() as u64;
+                    fingerprint = continue_hash(fingerprint, &buffer);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok((size, fingerprint))
+}
+
+fn align(size: usize) -> usize {
+    (size + ALIGNMENT - 1) & !(ALIGNMENT - 1)
+}
+
+fn pad(data: &mut Vec<u8>) {
+    data.resize(align(data.len()), 0);
+}
+
+fn push_string(data: &mut Vec<u8>, string: Option<&str>) {
+    match string {
+        Some(string) => {
+            data.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            data.extend_from_slice(string.as_bytes());
+        }
+        None => data.extend_from_slice(&NO_STRING.to_le_bytes())
+    }
+}
+
+// the source file is fingerprinted so a later load can tell whether it changed
+pub fn write_cache<P, Q>(path: P, source: Q, mesh: &Mesh, materials: &[Material]) -> Result<(), RenderError>
+    where P: AsRef<Path>, Q: AsRef<Path> {
+    let (source_size, source_hash) = source_fingerprint(source)?;
+    let primitives: Vec<&Primitive> = mesh.primitives
+        .iter()
+        .chain(mesh.lods.iter().flat_map(|x| x.primitives.iter()))
+        .collect();
+
+    let header_size = std::mem::size_of::<Header>();
+    let lods_size = mesh.lods.len() * std::mem::size_of::<LodRecord>();
+    let records_offset = align(header_size + lods_size);
+    let records_size = primitives.len() * std::mem::size_of::<PrimitiveRecord>();
+
+    let mut data: Vec<u8> = vec![0; records_offset + records_size];
+    let mut records: Vec<PrimitiveRecord> = Vec::with_capacity(primitives.len());
+
+    for primitive in primitives.iter() {
+        let vertex_offset = data.len();
+        data.extend_from_slice(bytemuck::cast_slice(primitive.vertex()));
+        pad(&mut data);
+
+        let index_offset = data.len();
+        data.extend_from_slice(primitive.indices().as_bytes());
+        pad(&mut data);
+
+        let attribute_offset = data.len();
+        let mut attribute_mask = 0u64;
+
+        for (bit, semantic) in Semantic::ALL.iter().enumerate() {
+            if let Some(values) = primitive.attributes.get(semantic) {
+                attribute_mask |= 1 << bit;
+                data.extend_from_slice(bytemuck::cast_slice(values));
+            }
+        }
+
+        records.push(PrimitiveRecord {
+            material_index: primitive.material_index as u64,
+            mode: mode_to_u64(primitive.mode),
+            index_format: match primitive.indices() { Indices::U16(_) => 0, Indices::U32(_) => 1 },
+            attribute_mask,
+            vertex_offset: vertex_offset as u64,
+            vertex_count: primitive.vertex().len() as u64,
+            index_offset: index_offset as u64,
+            index_count: primitive.indices().len() as u64,
+            attribute_offset: attribute_offset as u64
+        });
+    }
+
+    // materials only keep their texture references, the images stay where they are
+    let material_offset = data.len();
+
+    for material in materials {
+        data.extend_from_slice(bytemuck::cast_slice(material.color().as_slice()));
+        push_string(&mut data, material.texture());
+        push_string(&mut data, material.normal());
+        push_string(&mut data, material.roughness());
+    }
+
+    pad(&mut data);
+
+    let lods: Vec<LodRecord> = mesh.lods
+        .iter()
+        .map(|x| LodRecord { error: x.error, primitive_count: x.primitives.len() as u32 })
+        .collect();
+
+    data[header_size..header_size + lods_size].copy_from_slice(bytemuck::cast_slice(&lods));
+    data[records_offset..records_offset + records_size].copy_from_slice(bytemuck::cast_slice(&records));
+
+    let header = Header {
+        magic: MAGIC,
+        version: VERSION,
+        vertex_size: std::mem::size_of::<Vertex>() as u64,
+        source_size,
+        source_hash,
+        checksum: hash(&data[header_size..]),
+        file_size: data.len() as u64,
+        primitive_count: mesh.primitives.len() as u64,
+        lod_count: mesh.lods.len() as u64,
+        material_offset: material_offset as u64,
+        material_count: materials.len() as u64
+    };
+
+    data[..header_size].copy_from_slice(bytemuck::bytes_of(&header));
+
+    // written next to the cache and renamed over it, a reader that mapped the old file keeps its pages
+    let mut temporary = path.as_ref().as_os_str().to_owned();
+    temporary.push(format!(".{}.tmp", std::process::id()));
+
+    if let Err(error) = fs::write(&temporary, data).and_then(|_| fs::rename(&temporary, &path)) {
+        let _ = fs::remove_file(&temporary);
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+// the cached mesh when it is valid and still matches the source, otherwise build and cache a new one
+pub fn load_or_build<P, Q, F>(path: P, source: Q, build: F) -> Result<(Mesh, Vec<Material>), RenderError>
+    where P: AsRef<Path>, Q: AsRef<Path>, F: FnOnce() -> Result<(Mesh, Vec<Material>), RenderError> {
+    if let Ok(cache) = MeshCache::open(&path, &source) {
+        if let Ok(loaded) = cache.to_mesh().and_then(|mesh| Ok((mesh, cache.materials()?))) {
+            return Ok(loaded);
+        }
+    }
+
+    let (mesh, materials) = build()?;
+    write_cache(path, source, &mesh, &materials)?;
+
+    Ok((mesh, materials))
+}
+
+impl Storage {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            #[cfg(not(feature = "mmap"))]
+            Storage::Owned(words, size) => &bytemuck::cast_slice(words)[..*size],
+            #[cfg(feature = "mmap")]
+            Storage::Mapped(map) => &map[..]
+        }
+    }
+}
+
+impl MeshCache {
+    // rejects a foreign, damaged or outdated file and one written against a different source
+    pub fn open<P, Q>(path: P, source: Q) -> Result<Self, RenderError>
+        where P: AsRef<Path>, Q: AsRef<Path> {
+        let cache = Self { storage: Self::load(path.as_ref())? };
+        let header = cache.header()?;
+
+        if header.magic != MAGIC {
+            return Err(RenderError::Cache("not a mesh cache".to_string()));
+        }
+
+        if header.version != VERSION || header.vertex_size != std::mem::size_of::<Vertex>() as u64 {
+            return Err(RenderError::Cache(format!("cache version {} is not supported", header.version)));
+        }
+
+        let bytes = cache.storage.bytes();
+
+        if header.file_size != bytes.len() as u64 || header.checksum != hash(&bytes[std::mem::size_of::<Header>()..]) {
+            return Err(RenderError::Cache("checksum mismatch".to_string()));
+        }
+
+        if source_fingerprint(source)? != (header.source_size, header.source_hash) {
+            return Err(RenderError::Cache("source changed since the cache was written".to_string()));
+        }
+
+        cache.records()?;
+
+        Ok(cache)
+    }
+
+    #[cfg(feature = "mmap")]
+    fn load(path: &Path) -> Result<Storage, RenderError> {
+        let file = fs::File::open(path)?;
+        // the file is validated before anything is read from it, writers are expected to replace and not edit it
+        let map = unsafe { memmap::Mmap::map(&file)? };
+
+        Ok(Storage::Mapped(map))
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    fn load(path: &Path) -> Result<Storage, RenderError> {
+        use std::io::Read;
+
+        let mut file = fs::File::open(path)?;
+        let size = file.metadata()?.len() as usize;
+        let mut words = vec![0u64; align(size) / ALIGNMENT];
+        file.read_exact(&mut bytemuck::cast_slice_mut(&mut words)[..size])?;
+
+        Ok(Storage::Owned(words, size))
+    }
+
+    fn header(&self) -> Result<Header, RenderError> {
+        let bytes = self.storage.bytes();
+        let size = std::mem::size_of::<Header>();
+
+        if bytes.len() < size {
+            return Err(RenderError::Cache("file too short".to_string()));
+        }
+
+        Ok(*bytemuck::from_bytes::<Header>(&bytes[..size]))
+    }
+
+    fn section<T: Pod>(&self, offset: u64, count: u64) -> Result<&[T], RenderError> {
+        let bytes = self.storage.bytes();
+        let start = offset as usize;
+        let end = count as usize * std::mem::size_of::<T>() + start;
+
+        if end > bytes.len() {
+            return Err(RenderError::Cache("section out of bounds".to_string()));
+        }
+
+        bytemuck::try_cast_slice(&bytes[start..end]).map_err(|_| RenderError::Cache("misaligned section".to_string()))
+    }
+
+    fn lod_records(&self) -> Result<&[LodRecord], RenderError> {
+        let header = self.header()?;
+
+        self.section(std::mem::size_of::<Header>() as u64, header.lod_count)
+    }
+
+    fn records(&self) -> Result<&[PrimitiveRecord], RenderError> {
+        let header = self.header()?;
+        let lods = self.lod_records()?;
+        let offset = align(std::mem::size_of::<Header>() + std::mem::size_of_val(lods));
+        let count = header.primitive_count + lods.iter().map(|x| x.primitive_count as u64).sum::<u64>();
+
+        self.section(offset as u64, count)
+    }
+
+    pub fn primitive_count(&self) -> usize {
+        self.header().map_or(0, |x| x.primitive_count as usize)
+    }
+
+    // straight from the file without copying, lod primitives follow the base ones
+    pub fn vertices(&self, primitive: usize) -> Result<&[Vertex], RenderError> {
+        let record = self.record(primitive)?;
+
+        self.section(record.vertex_offset, record.vertex_count)
+    }
+
+    pub fn indices(&self, primitive: usize) -> Result<Indices, RenderError> {
+        let record = self.record(primitive)?;
+
+        match record.index_format {
+            0 => Ok(Indices::U16(self.section::<u16>(record.index_offset, record.index_count)?.to_vec())),
+            1 => Ok(Indices::U32(self.section::<u32>(record.index_offset, record.index_count)?.to_vec())),
+            x => Err(RenderError::Cache(format!("unknown index format {}", x)))
+        }
+    }
+
+    fn record(&self, primitive: usize) -> Result<PrimitiveRecord, RenderError> {
+        self.records()?
+            .get(primitive)
+            .copied()
+            .ok_or_else(|| RenderError::Cache(format!("no primitive {}", primitive)))
+    }
+
+    fn primitive(&self, index: usize) -> Result<Primitive, RenderError> {
+        let record = self.record(index)?;
+        let mut primitive = Primitive::new(self.vertices(index)?.to_vec(),
+                                           Vec::new(),
+                                           record.material_index as usize,
+                                           mode_from_u64(record.mode)?);
+        primitive.indices = self.indices(index)?;
+
+        let mut offset = record.attribute_offset;
+
+        for (bit, semantic) in Semantic::ALL.iter().enumerate() {
+            if record.attribute_mask & (1 << bit) != 0 {
+                let values: &[[f32; 4]] = self.section(offset, record.vertex_count)?;
+                primitive.attributes.insert(*semantic, values.to_vec());
+                offset += std::mem::size_of_val(values) as u64;
+            }
+        }
+
+        Ok(primitive)
+    }
+
+    pub fn to_mesh(&self) -> Result<Mesh, RenderError> {
+        let header = self.header()?;
+        let mut next = header.primitive_count as usize;
+        let mut mesh = Mesh::new((0..next).map(|x| self.primitive(x)).collect::<Result<_, _>>()?);
+
+        for lod in self.lod_records()? {
+            let count = lod.primitive_count as usize;
+            let primitives = (next..next + count).map(|x| self.primitive(x)).collect::<Result<_, _>>()?;
+            mesh.lods.push(Lod::new(primitives, lod.error));
+            next += count;
+        }
+
+        Ok(mesh)
+    }
+
+    pub fn materials(&self) -> Result<Vec<Material>, RenderError> {
+        let header = self.header()?;
+        let mut reader = Reader { bytes: self.storage.bytes(), offset: header.material_offset as usize };
+        let mut materials = Vec::with_capacity(header.material_count as usize);
+
+        for _ in 0..header.material_count {
+            let color = Vector4::new(reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?);
+            let texture = reader.string()?;
+            let normal = reader.string()?;
+            let roughness = reader.string()?;
+
+            materials.push(Material::new(texture, normal, roughness, color));
+        }
+
+        Ok(materials)
+    }
+}
+
+impl Reader<'_> {
+    fn take(&mut self, size: usize) -> Result<&[u8], RenderError> {
+        let taken = self.bytes
+            .get(self.offset..self.offset + size)
+            .ok_or_else(|| RenderError::Cache("material out of bounds".to_string()))?;
+        self.offset += size;
+
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, RenderError> {
+        let mut word = [0u8; 4];
+        word.copy_from_slice(self.take(4)?);
+
+        Ok(u32::from_le_bytes(word))
+    }
+
+    fn f32(&mut self) -> Result<f32, RenderError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn string(&mut self) -> Result<Option<String>, RenderError> {
+        let length = self.u32()?;
+
+        if length == NO_STRING {
+            return Ok(None);
+        }
+
+        std::str::from_utf8(self.take(length as usize)?)
+            .map(|x| Some(x.to_string()))
+            .map_err(|x| RenderError::Cache(x.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::renderer::shapes;
+
+    // a directory of its own per test, with a source file to fingerprint
+    fn directory(name: &str) -> (PathBuf, PathBuf) {
+        let directory = std::env::temp_dir().join(format!("mesh-cache-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&directory).unwrap();
+
+        let source = directory.join("source.bin");
+        fs::write(&source, b"not a gltf file").unwrap();
+
+        (directory.join("mesh.cache"), source)
+    }
+
+    fn test_mesh() -> (Mesh, Vec<Material>) {
+        let mut cube = shapes::cube(1.0, 1).primitives.remove(0);
+        let colors = (0..cube.vertex().len()).map(|x| [x as f32, 0.5, 0.25, 1.0]).collect();
+        cube.set_attribute(Semantic::Color, colors);
+
+        // too many vertices for u16 indices
+        let mut grid = shapes::plane(1.0, 1.0, 300, 300).primitives.remove(0);
+        grid.material_index = 1;
+
+        let mut mesh = Mesh::new(vec![cube, grid]);
+        mesh.lods.push(Lod::new(vec![shapes::cube(1.0, 1).primitives.remove(0)], 0.25));
+
+        let materials = vec![Material::new(Some("albedo.png".to_string()), None, Some("roughness.png".to_string()), Vector4::new(1.0, 0.5, 0.25, 1.0)),
+                             Material::new(None, None, None, Vector4::repeat(1.0))];
+
+        (mesh, materials)
+    }
+
+    fn assert_same_primitive(a: &Primitive, b: &Primitive) {
+        let bytes = |x: &Primitive| bytemuck::cast_slice::<Vertex, u8>(x.vertex()).to_vec();
+
+        assert_eq!(bytes(a), bytes(b));
+        assert_eq!(a.indices(), b.indices());
+        assert_eq!(a.attributes, b.attributes);
+        assert_eq!(a.material_index, b.material_index);
+        assert_eq!(a.mode, b.mode);
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let (path, source) = directory("round-trip");
+        let (mesh, materials) = test_mesh();
+
+        write_cache(&path, &source, &mesh, &materials).unwrap();
+
+        // nothing is left behind next to the cache
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 2);
+
+        let cache = MeshCache::open(&path, &source).unwrap();
+        let loaded = cache.to_mesh().unwrap();
+
+        assert_eq!(cache.primitive_count(), 2);
+        assert!(matches!(cache.indices(0).unwrap(), Indices::U16(_)));
+        assert!(matches!(cache.indices(1).unwrap(), Indices::U32(_)));
+
+        for (a, b) in mesh.primitives.iter().zip(loaded.primitives.iter()) {
+            assert_same_primitive(a, b);
+        }
+
+        assert_eq!(loaded.lods.len(), 1);
+        assert_eq!(loaded.lods[0].error, 0.25);
+        assert_same_primitive(&mesh.lods[0].primitives[0], &loaded.lods[0].primitives[0]);
+
+        for (a, b) in materials.iter().zip(cache.materials().unwrap().iter()) {
+            assert_eq!((a.color(), a.texture(), a.normal(), a.roughness()), (b.color(), b.texture(), b.normal(), b.roughness()));
+        }
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn corrupted_checksum_is_rejected() {
+        let (path, source) = directory("checksum");
+        let (mesh, materials) = test_mesh();
+        write_cache(&path, &source, &mesh, &materials).unwrap();
+
+        let mut bytes = fs::read(&path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 1;
+        fs::write(&path, bytes).unwrap();
+
+        match MeshCache::open(&path, &source) {
+            Err(RenderError::Cache(message)) => assert_eq!(message, "checksum mismatch"),
+            _ => panic!("a damaged cache was accepted")
+        }
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn version_mismatch_is_rejected() {
+        let (path, source) = directory("version");
+        let (mesh, materials) = test_mesh();
+        write_cache(&path, &source, &mesh, &materials).unwrap();
+
+        // the version follows the four magic bytes
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+
+        match MeshCache::open(&path, &source) {
+            Err(RenderError::Cache(message)) => assert!(message.contains("version"), "{}", message),
+            _ => panic!("a cache of another version was accepted")
+        }
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn changed_source_is_rejected() {
+        let (path, source) = directory("source");
+        let (mesh, materials) = test_mesh();
+        write_cache(&path, &source, &mesh, &materials).unwrap();
+
+        fs::write(&source, b"not a gltf file either").unwrap();
+
+        assert!(MeshCache::open(&path, &source).is_err());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
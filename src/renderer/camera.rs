@@ -1,4 +1,5 @@
 use nalgebra::{Vector3, Point3, Matrix4, Isometry3, Perspective3};
+use crate::renderer::bvh::Ray;
 
 pub struct Camera {
     eye: Point3<f32>,
@@ -38,4 +39,17 @@ impl Camera {
         // Combine everything.
         projection.as_matrix() * mat_model_view
     }
+
+    // ray through normalized device coordinates, y up, starting on the near plane, undoing the same model
+    // offset as the projection matrix so it lands in the space the scene is drawn from
+    pub fn ray(&self, x: f32, y: f32) -> Ray {
+        let inverse = self.build_projection_matrix()
+            .try_inverse()
+            .unwrap_or_else(Matrix4::identity);
+
+        let near = inverse.transform_point(&Point3::new(x, y, -1.0));
+        let far = inverse.transform_point(&Point3::new(x, y, 1.0));
+
+        Ray::new(near, (far - near).normalize())
+    }
 }
\ No newline at end of file
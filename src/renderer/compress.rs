@@ -0,0 +1,282 @@
+use nalgebra::{Matrix4, Vector2, Vector3};
+use wgpu::{Buffer, BufferUsage, Device, VertexFormat};
+use crate::renderer::{bounds::Aabb, layout::{Packing, Semantic, VertexAttribute, VertexLayout}, vertex::Vertex, Primitive};
+
+// maps the unorm16 position range back into model space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantization {
+    pub offset: Vector3<f32>,
+    pub scale: Vector3<f32>
+}
+
+impl Quantization {
+    pub fn new(offset: Vector3<f32>, scale: Vector3<f32>) -> Self {
+        Self {
+            offset,
+            scale
+        }
+    }
+
+    pub fn from_aabb(aabb: &Aabb) -> Self {
+        if aabb.is_empty() {
+            return Self::new(Vector3::zeros(), Vector3::zeros());
+        }
+
+        Self::new(aabb.min.coords, aabb.size())
+    }
+
+    // into the zero to one box the unorm16 format covers
+    pub fn normalize(&self, position: &Vector3<f32>) -> Vector3<f32> {
+        let mut normalized = Vector3::zeros();
+
+        for axis in 0..3 {
+            // a flat axis keeps every vertex on the offset
+            if self.scale[axis] > 0.0 {
+                normalized[axis] = (position[axis] - self.offset[axis]) / self.scale[axis];
+            }
+        }
+
+        normalized
+    }
+
+    pub fn denormalize(&self, normalized: &Vector3<f32>) -> Vector3<f32> {
+        self.offset + normalized.component_mul(&self.scale)
+    }
+
+    // multiply into the model transform so shaders can use the fetched position as is
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.offset) * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+}
+
+impl VertexLayout {
+    // 24 bytes a vertex against the 48 of standard(), unorm16 positions inside the primitive's box with w at one,
+    // snorm16 octahedral normals and tangents with the handedness in z, half float uvs, decoded by the vertex
+    // fetch except for the octahedral directions
+    pub fn compressed(packing: Packing) -> Self {
+        Self::new(vec![VertexAttribute::new(Semantic::Position, VertexFormat::Ushort4Norm),
+                       VertexAttribute::new(Semantic::Normal, VertexFormat::Short2Norm),
+                       VertexAttribute::new(Semantic::Tangent, VertexFormat::Short4Norm),
+                       VertexAttribute::new(Semantic::TexCoord0, VertexFormat::Half2)],
+                  packing)
+    }
+}
+
+impl Primitive {
+    // streams of VertexLayout::compressed, quantized against the cached bounds
+    pub fn compress(&self, packing: Packing) -> (Vec<Vec<u8>>, Quantization) {
+        let quantization = Quantization::from_aabb(&self.bounds().aabb);
+
+        (self.compress_with(packing, &quantization), quantization)
+    }
+
+    // quantized against a box shared with other primitives, positions outside it are clamped
+    pub fn compress_with(&self, packing: Packing, quantization: &Quantization) -> Vec<Vec<u8>> {
+        VertexLayout::compressed(packing).pack_values(self.vertex().len(), |semantic, index| {
+            let vertex = &self.vertex()[index];
+
+            match semantic {
+                Semantic::Position => {
+                    let position = quantization.normalize(vertex.position());
+                    [position.x, position.y, position.z, 1.0]
+                }
+                Semantic::Normal => {
+                    let normal = encode_octahedral(vertex.normal());
+                    [normal.x, normal.y, 0.0, 0.0]
+                }
+                Semantic::Tangent => {
+                    let direction = encode_octahedral(&vertex.tangent().xyz());
+                    [direction.x, direction.y, sign_not_zero(vertex.tangent().w), 0.0]
+                }
+                _ => self.attribute(semantic, index)
+            }
+        })
+    }
+
+    pub fn get_compressed_vertex_buffers(&self, device: &Device, packing: Packing) -> (Vec<Buffer>, Quantization) {
+        let (streams, quantization) = self.compress(packing);
+        let buffers = streams.iter().map(|x| device.create_buffer_with_data(x, BufferUsage::VERTEX)).collect();
+
+        (buffers, quantization)
+    }
+}
+
+// the vertices the shaders see, decoded the same way as the vertex fetch and the octahedral unfolding
+pub fn decompress(streams: &[Vec<u8>], packing: Packing, quantization: &Quantization) -> Vec<Vertex> {
+    let layout = VertexLayout::compressed(packing);
+    let count = streams.first().map_or(0, |x| x.len() / layout.stride(0) as usize);
+
+    (0..count)
+        .map(|index| {
+            let read = |semantic: Semantic| layout.read(streams, semantic, index).unwrap_or_else(|| semantic.default_value());
+            let [x, y, z, _] = read(Semantic::Position);
+            let normal = read(Semantic::Normal);
+            let tangent = read(Semantic::Tangent);
+            let uv = read(Semantic::TexCoord0);
+
+            Vertex::new(quantization.denormalize(&Vector3::new(x, y, z)),
+                        decode_octahedral(&Vector2::new(normal[0], normal[1])),
+                        decode_octahedral(&Vector2::new(tangent[0], tangent[1])).push(sign_not_zero(tangent[2])),
+                        Vector2::new(uv[0], uv[1]))
+        })
+        .collect()
+}
+
+fn sign_not_zero(value: f32) -> f32 {
+    if value >= 0.0 { 1.0 } else { -1.0 }
+}
+
+// projects the unit sphere onto an octahedron unfolded into the [-1, 1] square
+pub fn encode_octahedral(normal: &Vector3<f32>) -> Vector2<f32> {
+    let length = normal.x.abs() + normal.y.abs() + normal.z.abs();
+
+    if length == 0.0 {
+        return Vector2::zeros();
+    }
+
+    let x = normal.x / length;
+    let y = normal.y / length;
+
+    if normal.z < 0.0 {
+        return Vector2::new((1.0 - y.abs()) * sign_not_zero(x), (1.0 - x.abs()) * sign_not_zero(y));
+    }
+
+    Vector2::new(x, y)
+}
+
+pub fn decode_octahedral(encoded: &Vector2<f32>) -> Vector3<f32> {
+    let z = 1.0 - encoded.x.abs() - encoded.y.abs();
+    let fold = (-z).max(0.0);
+
+    let normal = Vector3::new(encoded.x - fold * sign_not_zero(encoded.x), encoded.y - fold * sign_not_zero(encoded.y), z);
+    normal.normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector4;
+    use wgpu::PrimitiveTopology;
+    use crate::renderer::layout;
+
+    fn primitive(vertices: Vec<Vertex>) -> Primitive {
+        Primitive::new(vertices, Vec::new(), 0, PrimitiveTopology::PointList)
+    }
+
+    fn vertex(position: Vector3<f32>, normal: Vector3<f32>, tangent: Vector4<f32>, uv: Vector2<f32>) -> Vertex {
+        Vertex::new(position, normal, tangent, uv)
+    }
+
+    // encoding what came out of decoding has to give the same bits again
+    fn assert_stable(vertices: Vec<Vertex>, packing: Packing) -> Vec<Vertex> {
+        let original = primitive(vertices);
+        let (streams, quantization) = original.compress(packing);
+        let decoded = decompress(&streams, packing, &quantization);
+
+        assert_eq!(decoded.len(), original.vertex().len());
+        assert_eq!(primitive(decoded.clone()).compress_with(packing, &quantization), streams);
+
+        decoded
+    }
+
+    fn directions() -> Vec<Vector3<f32>> {
+        let mut directions = vec![Vector3::x(), -Vector3::x(), Vector3::y(), -Vector3::y(), Vector3::z(), -Vector3::z(),
+                                  Vector3::new(-0.0, -0.0, -1.0), Vector3::new(0.0, -0.0, -1.0), Vector3::new(-0.0, 0.0, 1.0),
+                                  Vector3::new(1.0, 1.0, 1.0).normalize(), Vector3::new(-1.0, 1.0, -1.0).normalize(),
+                                  Vector3::new(1.0, -0.0, -1.0).normalize(), Vector3::new(-0.0, 1.0, -1.0).normalize()];
+
+        for i in 0..200 {
+            let angle = i as f32 * 2.399_963;
+            let z = 1.0 - 2.0 * (i as f32 + 0.5) / 200.0;
+            let radius = (1.0 - z * z).sqrt();
+            directions.push(Vector3::new(radius * angle.cos(), radius * angle.sin(), z));
+        }
+
+        directions
+    }
+
+    #[test]
+    fn layout_matches_the_old_vertex_size() {
+        let interleaved = VertexLayout::compressed(Packing::Interleaved);
+        let separate = VertexLayout::compressed(Packing::Separate);
+
+        assert_eq!(interleaved.stream_count(), 1);
+        assert_eq!(interleaved.stride(0), 24);
+        assert_eq!((0..separate.stream_count()).map(|x| separate.stride(x)).collect::<Vec<u64>>(), vec![8, 4, 8, 4]);
+    }
+
+    #[test]
+    fn positions_round_trip_through_unorm16() {
+        let positions = [Vector3::new(-2.0, 0.5, 3.0), Vector3::new(4.0, 0.5, -1.0), Vector3::new(0.123, 0.5, 0.456), Vector3::new(1.0, 0.5, 2.0)];
+        let vertices: Vec<Vertex> = positions.iter().map(|x| vertex(*x, Vector3::z(), Vector4::new(1.0, 0.0, 0.0, 1.0), Vector2::zeros())).collect();
+
+        for packing in [Packing::Interleaved, Packing::Separate].iter() {
+            let decoded = assert_stable(vertices.clone(), *packing);
+            let step = Vector3::new(6.0, 0.0, 4.0) / 65535.0;
+
+            for (original, decoded) in positions.iter().zip(decoded.iter()) {
+                let error = (original - decoded.position()).abs();
+                assert!(error.x <= step.x * 0.5 + 1e-6 && error.z <= step.z * 0.5 + 1e-6, "{} {}", original, decoded.position());
+                // the flat axis sits exactly on the offset
+                assert_eq!(decoded.position().y, 0.5);
+            }
+
+            assert_eq!(decoded[0].position().x, -2.0);
+            assert_eq!(decoded[1].position().z, -1.0);
+        }
+
+        let (streams, _) = primitive(vertices).compress(Packing::Interleaved);
+        assert_eq!(layout::decode(VertexFormat::Ushort4Norm, &streams[0][..8]), [0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn normals_and_tangents_round_trip_through_octahedral_snorm16() {
+        let directions = directions();
+        let vertices = directions
+            .iter()
+            .enumerate()
+            .map(|(i, x)| vertex(Vector3::new(i as f32, 0.0, 0.0), *x, x.push(if i % 2 == 0 { 1.0 } else { -1.0 }), Vector2::zeros()))
+            .collect();
+        let decoded = assert_stable(vertices, Packing::Interleaved);
+
+        for (i, (direction, decoded)) in directions.iter().zip(decoded.iter()).enumerate() {
+            assert!((direction - decoded.normal()).norm() < 1e-4, "{} became {}", direction, decoded.normal());
+            assert_eq!(decoded.normal(), &decoded.tangent().xyz());
+            assert_eq!(decoded.tangent().w, if i % 2 == 0 { 1.0 } else { -1.0 });
+        }
+
+        // axes and poles, signed zeros included, come back exactly
+        for (direction, decoded) in directions.iter().zip(decoded.iter()).take(9) {
+            assert_eq!(decoded.normal(), &direction.map(|x| x + 0.0), "{}", direction);
+        }
+    }
+
+    #[test]
+    fn signed_zeros_fold_onto_the_same_bits() {
+        let encode = |direction: Vector3<f32>| {
+            let mut bytes = Vec::new();
+            let encoded = encode_octahedral(&direction);
+            layout::encode(VertexFormat::Short2Norm, &[encoded.x, encoded.y, 0.0, 0.0], &mut bytes);
+            bytes
+        };
+
+        assert_eq!(encode(Vector3::new(0.0, 0.0, -1.0)), encode(Vector3::new(-0.0, -0.0, -1.0)));
+        assert_eq!(encode(Vector3::new(0.0, 0.0, 1.0)), encode(Vector3::new(-0.0, -0.0, 1.0)));
+        assert_eq!(encode(Vector3::new(0.0, 0.0, -1.0)), vec![0xff, 0x7f, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn uvs_round_trip_through_half_floats() {
+        let uvs = [Vector2::new(0.0, 1.0), Vector2::new(0.5, 0.25), Vector2::new(-2.0, 1024.0), Vector2::new(0.1, 0.3333), Vector2::new(-0.0, 7.123)];
+        let vertices = uvs.iter().map(|x| vertex(Vector3::zeros(), Vector3::z(), Vector4::new(1.0, 0.0, 0.0, 1.0), *x)).collect();
+        let decoded = assert_stable(vertices, Packing::Separate);
+
+        for (i, (uv, decoded)) in uvs.iter().zip(decoded.iter()).enumerate() {
+            if i < 3 {
+                assert_eq!(decoded.uv(), uv);
+            } else {
+                assert!((decoded.uv() - uv).abs().max() <= uv.abs().max() / 1024.0, "{} became {}", uv, decoded.uv());
+            }
+        }
+    }
+}
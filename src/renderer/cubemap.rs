@@ -0,0 +1,156 @@
+use std::f32::consts::PI;
+use nalgebra::Vector3;
+use wgpu::TextureFormat;
+use crate::renderer::{hdr::HdrImage, texture::TextureData};
+
+// same order as the array layers of a wgpu cube texture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CubeFace {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ
+}
+
+pub struct Cubemap {
+    size: u32,
+    faces: Vec<HdrImage>
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [CubeFace::PositiveX,
+                                    CubeFace::NegativeX,
+                                    CubeFace::PositiveY,
+                                    CubeFace::NegativeY,
+                                    CubeFace::PositiveZ,
+                                    CubeFace::NegativeZ];
+
+    pub fn layer(self) -> usize {
+        self as usize
+    }
+
+    // s and t go from -1 to 1, left to right and top to bottom of the face
+    pub fn direction(self, s: f32, t: f32) -> Vector3<f32> {
+        let direction = match self {
+            CubeFace::PositiveX => Vector3::new(1.0, -t, -s),
+            CubeFace::NegativeX => Vector3::new(-1.0, -t, s),
+            CubeFace::PositiveY => Vector3::new(s, 1.0, t),
+            CubeFace::NegativeY => Vector3::new(s, -1.0, -t),
+            CubeFace::PositiveZ => Vector3::new(s, -t, 1.0),
+            CubeFace::NegativeZ => Vector3::new(-s, -t, -1.0)
+        };
+
+        direction.normalize()
+    }
+}
+
+impl Cubemap {
+    pub fn new(faces: Vec<HdrImage>) -> Self {
+        assert_eq!(faces.len(), 6);
+        let size = faces[0].width();
+
+        Self {
+            size,
+            faces
+        }
+    }
+
+    pub fn from_equirectangular(image: &HdrImage, size: u32) -> Self {
+        let faces = CubeFace::ALL
+            .iter()
+            .map(|face| {
+                let mut data: Vec<Vector3<f32>> = Vec::with_capacity((size * size) as usize);
+
+                for y in 0..size {
+                    for x in 0..size {
+                        let s = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+                        let t = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+                        let (u, v) = equirectangular_uv(&face.direction(s, t));
+
+                        data.push(image.sample_bilinear(u, v));
+                    }
+                }
+
+                HdrImage::from_parts(size, size, data)
+            })
+            .collect();
+
+        Self::new(faces)
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn face(&self, face: CubeFace) -> &HdrImage {
+        &self.faces[face.layer()]
+    }
+
+    pub fn faces(&self) -> &Vec<HdrImage> {
+        &self.faces
+    }
+
+    // create the view with TextureViewDimension::Cube to sample it as a cubemap
+    pub fn to_texture_data(&self) -> TextureData {
+        let layers = self.faces
+            .iter()
+            .map(|face| face.mip_chain().iter().map(HdrImage::to_mip_level).collect())
+            .collect();
+
+        TextureData::new_layered(TextureFormat::Rgba16Float, 8, layers)
+    }
+}
+
+// +Y is up, u = 0.5 looks down +X and u grows towards +Z
+pub fn equirectangular_uv(direction: &Vector3<f32>) -> (f32, f32) {
+    let u = 0.5 + direction.z.atan2(direction.x) / (2.0 * PI);
+    let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+
+    (u, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AXES: [[f32; 3]; 6] = [[1.0, 0.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, 1.0], [0.0, 0.0, -1.0]];
+
+    #[test]
+    fn face_centres_look_down_the_axes_in_layer_order() {
+        for (layer, face) in CubeFace::ALL.iter().enumerate() {
+            assert_eq!(face.layer(), layer);
+            assert_eq!(face.direction(0.0, 0.0), Vector3::from(AXES[layer]));
+        }
+
+        // the top row of every side face looks up
+        for face in [CubeFace::PositiveX, CubeFace::NegativeX, CubeFace::PositiveZ, CubeFace::NegativeZ].iter() {
+            assert!(face.direction(0.0, -1.0).y > 0.5);
+        }
+    }
+
+    #[test]
+    fn equirectangular_faces_sample_their_own_direction() {
+        // every pixel holds the direction it was taken in
+        let (width, height) = (128, 64);
+        let data = (0..width * height)
+            .map(|index| {
+                let phi = (((index % width) as f32 + 0.5) / width as f32 - 0.5) * 2.0 * PI;
+                let theta = ((index / width) as f32 + 0.5) / height as f32 * PI;
+                Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+            })
+            .collect();
+
+        let cubemap = Cubemap::from_equirectangular(&HdrImage::new(width, height, data).unwrap(), 3);
+        assert_eq!(cubemap.size(), 3);
+
+        for (layer, face) in CubeFace::ALL.iter().enumerate() {
+            let centre = cubemap.face(*face).pixel(1, 1);
+            assert!((centre - Vector3::from(AXES[layer])).norm() < 0.05, "{:?} centre reads {:?}", face, centre);
+        }
+
+        let data = cubemap.to_texture_data();
+        assert_eq!((data.layers().len(), data.mips().len()), (6, 2));
+    }
+}
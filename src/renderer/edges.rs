@@ -0,0 +1,107 @@
+use std::collections::{HashMap, HashSet};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{halfedge::HalfEdgeMesh, Primitive, RenderError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeSelection {
+    // every edge once, seams and welded duplicates included only once
+    All,
+    // edges with a single face
+    Boundary,
+    // edges whose faces meet at more than the angle in radians, non manifold edges always count
+    Crease(f32)
+}
+
+// a line list over the edges of a triangle list, every line vertex comes from a corner of the source
+pub fn extract_edges(primitive: &Primitive, selection: EdgeSelection) -> Result<Primitive, RenderError> {
+    let mesh = HalfEdgeMesh::from_primitive(primitive)?;
+    let mut seen: HashSet<(u32, u32)> = HashSet::new();
+    let mut line_vertex_of: HashMap<u32, u32> = HashMap::new();
+    let mut sources: Vec<u32> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+
+    for (index, half_edge) in mesh.half_edges().iter().enumerate() {
+        let index = index as u32;
+        let (from, to) = (half_edge.vertex, mesh.target(index));
+
+        if from == to || !seen.insert((from.min(to), from.max(to))) {
+            continue;
+        }
+
+        let selected = match selection {
+            EdgeSelection::All => true,
+            EdgeSelection::Boundary => mesh.is_boundary_edge(index) && !mesh.is_non_manifold_edge(index),
+            EdgeSelection::Crease(angle) => {
+                mesh.is_non_manifold_edge(index) || matches!(mesh.dihedral_angle(index), Some(x) if x > angle)
+            }
+        };
+
+        if !selected {
+            continue;
+        }
+
+        // one line vertex per welded position, taken from the first corner that reaches it
+        let wedges = [half_edge.wedge, mesh.half_edge(half_edge.next).wedge];
+
+        for (vertex, wedge) in [from, to].iter().zip(wedges.iter()) {
+            let line_vertex = *line_vertex_of.entry(*vertex).or_insert_with(|| {
+                sources.push(*wedge);
+                (sources.len() - 1) as u32
+            });
+
+            indices.push(line_vertex);
+        }
+    }
+
+    let mut lines = primitive.select_vertices(&sources, indices);
+    lines.mode = PrimitiveTopology::LineList;
+
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use super::*;
+    use crate::renderer::shapes;
+
+    fn line_count(primitive: &Primitive, selection: EdgeSelection) -> usize {
+        let lines = extract_edges(primitive, selection).unwrap();
+        assert_eq!(lines.mode, PrimitiveTopology::LineList);
+
+        lines.indices().len() / 2
+    }
+
+    #[test]
+    fn cube_edges() {
+        let cube = shapes::cube(1.0, 1);
+        let primitive = &cube.primitives[0];
+
+        // twelve sides and one diagonal per face, the 24 face corners welded back to 8
+        assert_eq!(line_count(primitive, EdgeSelection::All), 12 + 6);
+        assert_eq!(line_count(primitive, EdgeSelection::Boundary), 0);
+        assert_eq!(line_count(primitive, EdgeSelection::Crease(PI / 6.0)), 12);
+        assert_eq!(line_count(primitive, EdgeSelection::Crease(PI / 2.0)), 0);
+
+        let creases = extract_edges(primitive, EdgeSelection::Crease(PI / 6.0)).unwrap();
+        assert_eq!(creases.vertex().len(), 8);
+
+        // every subdivided side splits into two lines
+        assert_eq!(line_count(&shapes::cube(1.0, 2).primitives[0], EdgeSelection::Crease(PI / 6.0)), 24);
+    }
+
+    #[test]
+    fn plane_edges() {
+        let plane = shapes::plane(1.0, 1.0, 2, 2);
+        let primitive = &plane.primitives[0];
+
+        assert_eq!(line_count(primitive, EdgeSelection::All), 12 + 4);
+        assert_eq!(line_count(primitive, EdgeSelection::Boundary), 8);
+        assert_eq!(line_count(primitive, EdgeSelection::Crease(0.01)), 0);
+
+        // boundary lines only touch the outer ring, never the centre vertex
+        let boundary = extract_edges(primitive, EdgeSelection::Boundary).unwrap();
+        assert_eq!(boundary.vertex().len(), 8);
+        assert!(boundary.vertex().iter().all(|x| x.position().x.abs() == 0.5 || x.position().z.abs() == 0.5));
+    }
+}
@@ -1,16 +1,19 @@
-use gltf::{Image, material::PbrMetallicRoughness};
-use std::{path::Path, ffi::OsStr};
+use std::path::Path;
 use crate::renderer::{material::Material, vertex::Vertex, Primitive, RenderError, Mesh, IntoWgpuEquivalent};
-use nalgebra::{Vector4, Vector3, Vector2};
-use gltf::material::NormalTexture;
+use crate::renderer::{instance::Instance, layout::Semantic, node::Node};
+use nalgebra::{Vector4, Vector3, Vector2, Matrix4, Quaternion, UnitQuaternion};
+use gltf::accessor::{DataType, Iter};
 use wgpu::{SamplerDescriptor, FilterMode, AddressMode};
 use gltf::json::texture::{MagFilter, MinFilter};
-use winit::event::VirtualKeyCode::Add;
 
-static DEFAULT_MATERIAL: &str = "default.png";
+static GPU_INSTANCING_EXTENSION: &str = "EXT_mesh_gpu_instancing";
+static GLB_MAGIC: &[u8] = b"glTF";
 
 pub struct GLTFImporter;
 
+// the mesh, its materials and samplers, and the nodes placing it
+pub type ImportedScene = (Mesh, Vec<Material>, Vec<SamplerDescriptor>, Vec<Node>);
+
 impl IntoWgpuEquivalent for MagFilter {
     type Output = wgpu::FilterMode;
 
@@ -84,12 +87,50 @@ impl IntoWgpuEquivalent for MinFilter {
     }
 }
 
+// the document, its buffers and the raw json, read once and shared by every part of an import
+struct Source {
+    document: gltf::Document,
+    buffers: Vec<gltf::buffer::Data>,
+    json: serde_json::Value
+}
+
 impl GLTFImporter {
+    // everything from a single read of the file
+    pub fn import<T>(path: T) -> Result<ImportedScene, RenderError>
+        where T: Into<String> {
+        let source = Self::read_source(&path.into())?;
+        let (mesh, materials, samplers) = Self::read_single_mesh(&source)?;
+        let nodes = Self::read_nodes(&source)?;
+
+        Ok((mesh, materials, samplers, nodes))
+    }
+
     pub fn import_single_mesh<T>(path: T) -> Result<(Mesh, Vec<Material>, Vec<SamplerDescriptor>), RenderError>
         where T: Into<String> {
-        let cloned_path = path.into().clone();
+        Self::read_single_mesh(&Self::read_source(&path.into())?)
+    }
+
+    pub fn import_nodes<T>(path: T) -> Result<Vec<Node>, RenderError>
+        where T: Into<String> {
+        Self::read_nodes(&Self::read_source(&path.into())?)
+    }
+
+    fn read_source(path: &str) -> Result<Source, RenderError> {
+        let bytes = std::fs::read(path)?;
+        let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(&bytes)?;
+        let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        let buffers = Self::read_buffers(&document, directory, blob)?;
+        let json = Self::read_raw_json(&bytes)?;
+
+        Ok(Source {
+            document,
+            buffers,
+            json
+        })
+    }
 
-        let (glft, buffers, _) = gltf::import(cloned_path)?;
+    fn read_single_mesh(source: &Source) -> Result<(Mesh, Vec<Material>, Vec<SamplerDescriptor>), RenderError> {
+        let glft = &source.document;
 
         let meshes = glft.meshes().collect::<Vec<gltf::Mesh<'_>>>();
         if meshes.len() != 1 {
@@ -98,13 +139,12 @@ impl GLTFImporter {
 
         let mut materials: Vec<Material> = Vec::new();
         let mut primitives: Vec<Primitive> = Vec::new();
-        let images = glft.images().collect::<Vec<Image>>();
 
         let mesh = meshes.first().unwrap();
         for gltf_primitive in mesh.primitives() {
             let mut primitive: Primitive = Default::default();
-            Self::fill_positions_for_primitive(&mut primitive, &gltf_primitive, &buffers);
-            Self::fill_material_for_primitive(&images, &mut materials, &mut primitive, &gltf_primitive);
+            Self::fill_positions_for_primitive(&mut primitive, &gltf_primitive, &source.buffers);
+            Self::fill_material_for_primitive(&mut materials, &mut primitive, &gltf_primitive);
             primitive.mode = gltf_primitive.mode().into_wgpu_equivalent();
             primitives.push(primitive);
         }
@@ -112,19 +152,196 @@ impl GLTFImporter {
         Ok((Mesh::new(primitives), materials, glft.samplers().map(|x| x.into_wgpu_equivalent()).collect()))
     }
 
-    fn fill_material_for_primitive(images: &Vec<gltf::Image<'_>>, materials: &mut Vec<Material>, intprimitive: &mut Primitive, primitive: &gltf::Primitive) {
+    fn read_nodes(source: &Source) -> Result<Vec<Node>, RenderError> {
+        let glft = &source.document;
+
+        let accessors = glft.accessors().collect::<Vec<gltf::Accessor<'_>>>();
+        let mut nodes: Vec<Node> = Vec::new();
+
+        let roots: Vec<gltf::Node<'_>> = match glft.default_scene().or_else(|| glft.scenes().next()) {
+            Some(scene) => scene.nodes().collect(),
+            None => glft.nodes().collect()
+        };
+
+        for root in roots {
+            Self::fill_nodes(&root, &Matrix4::identity(), &source.json, &accessors, &source.buffers, &mut nodes)?;
+        }
+
+        Ok(nodes)
+    }
+
+    // images are left to the texture loader, only the buffers are read here
+    fn read_buffers(document: &gltf::Document,
+                    directory: &Path,
+                    mut blob: Option<Vec<u8>>) -> Result<Vec<gltf::buffer::Data>, RenderError> {
+        document.buffers()
+            .map(|buffer| {
+                let mut data = match buffer.source() {
+                    gltf::buffer::Source::Bin => blob.take().ok_or_else(|| RenderError::Import("Missing binary chunk".to_string()))?,
+                    gltf::buffer::Source::Uri(uri) => match uri.strip_prefix("data:") {
+                        Some(data) => {
+                            let (_, encoded) = data.split_once(";base64,")
+                                .ok_or_else(|| RenderError::Import(format!("Buffer {} is not base64 encoded", buffer.index())))?;
+                            base64::decode(encoded).map_err(|x| RenderError::Import(x.to_string()))?
+                        }
+                        None => std::fs::read(directory.join(decode_uri(uri)))?
+                    }
+                };
+
+                if data.len() < buffer.length() {
+                    return Err(RenderError::Import(format!("Buffer {} has {} bytes, expected {}", buffer.index(), data.len(), buffer.length())));
+                }
+
+                // same padding gltf::import adds
+                data.resize(data.len().div_ceil(4) * 4, 0);
+
+                Ok(gltf::buffer::Data(data))
+            })
+            .collect()
+    }
+
+    fn read_raw_json(bytes: &[u8]) -> Result<serde_json::Value, RenderError> {
+        // gltf does not expose unknown extensions, so they are read from the raw document
+        if bytes.starts_with(GLB_MAGIC) {
+            let glb = gltf::Glb::from_slice(bytes)?;
+            return Ok(serde_json::from_slice(&glb.json)?);
+        }
+
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    fn fill_nodes(gltf_node: &gltf::Node<'_>,
+                  parent_transform: &Matrix4<f32>,
+                  json: &serde_json::Value,
+                  accessors: &[gltf::Accessor<'_>],
+                  buffer_data: &[gltf::buffer::Data],
+                  nodes: &mut Vec<Node>) -> Result<(), RenderError> {
+        let local = gltf_node.transform().matrix();
+        let transform = parent_transform * Matrix4::from(local);
+
+        let instances = match json["nodes"][gltf_node.index()]["extensions"].get(GPU_INSTANCING_EXTENSION) {
+            Some(extension) => Self::read_instances(&extension["attributes"], accessors, buffer_data)?,
+            None => Vec::new()
+        };
+
+        nodes.push(Node::new(gltf_node.mesh().map(|mesh| mesh.index()), transform, instances));
+
+        for child in gltf_node.children() {
+            Self::fill_nodes(&child, &transform, json, accessors, buffer_data, nodes)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_instances(attributes: &serde_json::Value,
+                      accessors: &[gltf::Accessor<'_>],
+                      buffer_data: &[gltf::buffer::Data]) -> Result<Vec<Instance>, RenderError> {
+        let translations = match Self::get_accessor(attributes, "TRANSLATION", accessors)? {
+            Some(accessor) => Some(Self::read_vec3(accessor, buffer_data)?),
+            None => None
+        };
+        let rotations = match Self::get_accessor(attributes, "ROTATION", accessors)? {
+            Some(accessor) => Some(Self::read_rotations(accessor, buffer_data)?),
+            None => None
+        };
+        let scales = match Self::get_accessor(attributes, "SCALE", accessors)? {
+            Some(accessor) => Some(Self::read_vec3(accessor, buffer_data)?),
+            None => None
+        };
+
+        // every attribute accessor of the extension must have the same count
+        let counts: Vec<usize> = [translations.as_ref().map(Vec::len), rotations.as_ref().map(Vec::len), scales.as_ref().map(Vec::len)]
+            .iter()
+            .filter_map(|x| *x)
+            .collect();
+
+        if counts.windows(2).any(|x| x[0] != x[1]) {
+            return Err(RenderError::Import(format!("Instance attribute accessors differ in count: {:?}", counts)));
+        }
+
+        let count = counts.first().copied().unwrap_or(0);
+
+        let mut instances: Vec<Instance> = Vec::with_capacity(count);
+        for i in 0..count {
+            let mut instance: Instance = Default::default();
+
+            if let Some(translation) = translations.as_ref().and_then(|x| x.get(i)) {
+                instance.set_translation(*translation);
+            }
+
+            if let Some(rotation) = rotations.as_ref().and_then(|x| x.get(i)) {
+                instance.set_rotation(*rotation);
+            }
+
+            if let Some(scale) = scales.as_ref().and_then(|x| x.get(i)) {
+                instance.set_scale(*scale);
+            }
+
+            instances.push(instance);
+        }
+
+        Ok(instances)
+    }
+
+    fn get_accessor<'a>(attributes: &serde_json::Value,
+                        name: &str,
+                        accessors: &'a [gltf::Accessor<'a>]) -> Result<Option<&'a gltf::Accessor<'a>>, RenderError> {
+        match attributes.get(name).and_then(|x| x.as_u64()) {
+            Some(index) => accessors.get(index as usize)
+                .map(Some)
+                .ok_or_else(|| RenderError::Import(format!("Instance attribute {} points to a missing accessor", name))),
+            None => Ok(None)
+        }
+    }
+
+    fn read_vec3(accessor: &gltf::Accessor<'_>, buffer_data: &[gltf::buffer::Data]) -> Result<Vec<Vector3<f32>>, RenderError> {
+        let iter = Iter::<[f32; 3]>::new(accessor.clone(), |buffer| Some(&buffer_data[buffer.index()]))
+            .ok_or_else(|| RenderError::Import("Unable to read instance accessor".to_string()))?;
+
+        Ok(iter.map(|x| Vector3::new(x[0], x[1], x[2])).collect())
+    }
+
+    fn read_rotations(accessor: &gltf::Accessor<'_>, buffer_data: &[gltf::buffer::Data]) -> Result<Vec<UnitQuaternion<f32>>, RenderError> {
+        let get_buffer = |buffer: gltf::Buffer<'_>| Some(&buffer_data[buffer.index()][..]);
+
+        // the extension allows normalized byte and short quaternions besides floats
+        let rotations: Option<Vec<[f32; 4]>> = match accessor.data_type() {
+            DataType::F32 => Iter::<[f32; 4]>::new(accessor.clone(), get_buffer)
+                .map(|iter| iter.collect()),
+            DataType::I8 => Iter::<[i8; 4]>::new(accessor.clone(), get_buffer)
+                .map(|iter| iter.map(|x| Self::denormalize(x, 127.0)).collect()),
+            DataType::I16 => Iter::<[i16; 4]>::new(accessor.clone(), get_buffer)
+                .map(|iter| iter.map(|x| Self::denormalize(x, 32767.0)).collect()),
+            _ => None
+        };
+
+        let rotations = rotations.ok_or_else(|| RenderError::Import("Unable to read instance rotation accessor".to_string()))?;
+
+        Ok(rotations
+            .iter()
+            .map(|x| UnitQuaternion::from_quaternion(Quaternion::new(x[3], x[0], x[1], x[2])))
+            .collect())
+    }
+
+    fn denormalize<T: Into<f32>>(value: [T; 4], max: f32) -> [f32; 4] {
+        let [x, y, z, w] = value;
+        [(x.into() / max).max(-1.0),
+         (y.into() / max).max(-1.0),
+         (z.into() / max).max(-1.0),
+         (w.into() / max).max(-1.0)]
+    }
+
+    fn fill_material_for_primitive(materials: &mut Vec<Material>, intprimitive: &mut Primitive, primitive: &gltf::Primitive) {
         let gltf_material: gltf::Material<'_> = primitive.material();
         let pbr = gltf_material.pbr_metallic_roughness();
 
         let base_color = pbr.base_color_factor();
         let color = Vector4::new(base_color[0], base_color[1], base_color[2], base_color[3]);
-        let normal_texture = Self::get_normal_map(&gltf_material.normal_texture());
-        let main_texture = Self::get_texture_url(&pbr.base_color_texture(), &images);
-        let roughness_texture = Self::get_texture_url(&pbr.metallic_roughness_texture(), &images);
+        let normal_texture = gltf_material.normal_texture().and_then(|x| Self::get_image_uri(&x.texture()));
+        let main_texture = pbr.base_color_texture().and_then(|x| Self::get_image_uri(&x.texture()));
+        let roughness_texture = pbr.metallic_roughness_texture().and_then(|x| Self::get_image_uri(&x.texture()));
 
-        let material = Material::new(main_texture.unwrap_or(DEFAULT_MATERIAL.to_string()),
-                                     normal_texture.unwrap_or(DEFAULT_MATERIAL.to_string()),
-                                     roughness_texture.unwrap_or(DEFAULT_MATERIAL.to_string()), color);
+        let material = Material::new(main_texture, normal_texture, roughness_texture, color);
 
         materials.push(material);
 
@@ -138,68 +355,196 @@ impl GLTFImporter {
             for pos in positions.map(|pos| Vector3::new(pos[0], pos[1], pos[2])).into_iter() {
                 let mut vert: Vertex = Default::default();
                 vert.set_position(pos);
-                intprimitive.vertex.push(vert);
+                intprimitive.push_vertex(vert);
             }
         }
 
         if let Some(normals) = reader.read_normals() {
             for (i, norm) in normals.enumerate() {
-                intprimitive.vertex[i].set_normal(Vector3::new(norm[0], norm[1], norm[2]));
+                intprimitive.vertex_mut()[i].set_normal(Vector3::new(norm[0], norm[1], norm[2]));
             }
         }
 
         if let Some(uvs) = reader.read_tex_coords(0) {
             for (i, uv) in uvs.into_f32().enumerate() {
-                intprimitive.vertex[i].set_uv(Vector2::new(uv[0], uv[1]));
+                intprimitive.vertex_mut()[i].set_uv(Vector2::new(uv[0], uv[1]));
             }
         }
 
         if let Some(tangents) = reader.read_tangents() {
             for (i, tan) in tangents.enumerate() {
-                intprimitive.vertex[i].set_tangent(Vector4::new(tan[0], tan[1], tan[2], tan[3]));
+                intprimitive.vertex_mut()[i].set_tangent(Vector4::new(tan[0], tan[1], tan[2], tan[3]));
             }
         }
 
+        if let Some(uvs) = reader.read_tex_coords(1) {
+            intprimitive.set_attribute(Semantic::TexCoord1, uvs.into_f32().map(|uv| [uv[0], uv[1], 0.0, 0.0]).collect());
+        }
+
+        if let Some(colors) = reader.read_colors(0) {
+            intprimitive.set_attribute(Semantic::Color, colors.into_rgba_f32().collect());
+        }
+
+        if let Some(joints) = reader.read_joints(0) {
+            intprimitive.set_attribute(Semantic::Joints, joints.into_u16().map(|x| [x[0] as f32, x[1] as f32, x[2] as f32, x[3] as f32]).collect());
+        }
+
+        if let Some(weights) = reader.read_weights(0) {
+            intprimitive.set_attribute(Semantic::Weights, weights.into_f32().collect());
+        }
+
         if let Some(index_enum) = reader.read_indices() {
-            for i in index_enum.into_u32() {
-                intprimitive.indices.push(i);
-            }
+            intprimitive.set_indices(index_enum.into_u32().collect());
         }
     }
 
-    fn get_texture_url(info: &Option<gltf::texture::Info<'_>>,
-                       images: &Vec<gltf::Image<'_>>) -> Option<String> {
-        if let Some(res) = info {
-            let tex = res.texture();
-            let image: Option<&gltf::Image<'_>> = images.get(tex.index());
-            if let Some(img) = image {
-                return match img.source() {
-                    gltf::image::Source::Uri { uri, .. } => {
-                        let texture_file_name = Path::new(&uri).file_name().and_then(OsStr::to_str).unwrap().to_string();
+    // the uri relative to the gltf file, textures embedded in a buffer have none
+    fn get_image_uri(texture: &gltf::Texture<'_>) -> Option<String> {
+        match texture.source().source() {
+            gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => Some(decode_uri(uri)),
+            _ => None
+        }
+    }
+}
 
-                        Some(texture_file_name)
-                    }
-                    _ => None
-                };
+// %xx escapes back to bytes, anything malformed is kept as written
+pub(crate) fn decode_uri(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
             }
         }
+    }
+
+    String::from_utf8(decoded).unwrap_or_else(|_| uri.to_string())
+}
 
-        None
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use serde_json::json;
+    use crate::renderer::texture::{TextureData, TextureLoader, TextureSlot};
+
+    // a gltf file and its one external buffer in a directory of their own
+    fn write_gltf(name: &str, mut document: serde_json::Value, buffer: &[u8]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("gltf-importer-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        document["asset"] = json!({ "version": "2.0" });
+        document["buffers"] = json!([{ "uri": "data.bin", "byteLength": buffer.len() }]);
+        std::fs::write(directory.join("data.bin"), buffer).unwrap();
+
+        let path = directory.join("scene.gltf");
+        std::fs::write(&path, serde_json::to_vec(&document).unwrap()).unwrap();
+
+        path
     }
 
-    fn get_normal_map(normal_texture: &Option<NormalTexture>) -> Option<String> {
-        if let Some(normal) = normal_texture {
-            let texture = normal.texture().source().source();
-            return match texture {
-                gltf::image::Source::Uri { uri, .. } => {
-                    let texture_file_name = Path::new(&uri).file_name().and_then(OsStr::to_str).unwrap().to_string();
+    fn floats(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
 
-                    Some(texture_file_name)
-                }
-                _ => None
-            };
+    // two translations and, when asked, scales for the given number of instances
+    fn instanced_node(scale_count: usize) -> (serde_json::Value, Vec<u8>) {
+        let mut buffer = floats(&[1.0, 0.0, 0.0, 0.0, 2.0, 0.0]);
+        buffer.extend(floats(&vec![3.0; scale_count * 3]));
+
+        let document = json!({
+            "extensionsUsed": [GPU_INSTANCING_EXTENSION],
+            "bufferViews": [{ "buffer": 0, "byteOffset": 0, "byteLength": 24 },
+                            { "buffer": 0, "byteOffset": 24, "byteLength": scale_count * 12 }],
+            "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 2, "type": "VEC3" },
+                          { "bufferView": 1, "componentType": 5126, "count": scale_count, "type": "VEC3" }],
+            "nodes": [{
+                "translation": [0.0, 0.0, 5.0],
+                "extensions": { GPU_INSTANCING_EXTENSION: { "attributes": { "TRANSLATION": 0, "SCALE": 1 } } }
+            }],
+            "scenes": [{ "nodes": [0] }],
+            "scene": 0
+        });
+
+        (document, buffer)
+    }
+
+    #[test]
+    fn instances_are_read() {
+        let (document, buffer) = instanced_node(2);
+        let path = write_gltf("instances", document, &buffer);
+        let nodes = GLTFImporter::import_nodes(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].instances.len(), 2);
+        assert_eq!(nodes[0].instances[1].translation(), &Vector3::new(0.0, 2.0, 0.0));
+        assert_eq!(nodes[0].instances[1].scale(), &Vector3::repeat(3.0));
+        assert_eq!(nodes[0].transform, Matrix4::new_translation(&Vector3::new(0.0, 0.0, 5.0)));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn mismatched_instance_counts_are_rejected() {
+        let (document, buffer) = instanced_node(3);
+        let path = write_gltf("mismatched-instances", document, &buffer);
+
+        match GLTFImporter::import_nodes(path.to_str().unwrap()) {
+            Err(RenderError::Import(message)) => assert!(message.contains("count"), "{}", message),
+            _ => panic!("accessors of different counts were accepted")
         }
 
-        None
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn textures_keep_their_directory() {
+        let buffer = floats(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        // the texture order differs from the image order on purpose
+        let document = json!({
+            "bufferViews": [{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }],
+            "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                            "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] }],
+            "images": [{ "uri": "normal.png" }, { "uri": "textures/foo%20bar.png" }],
+            "textures": [{ "source": 1 }, { "source": 0 }],
+            "materials": [{ "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } }, "normalTexture": { "index": 1 } }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "material": 0 }] }]
+        });
+        let path = write_gltf("textures", document, &buffer);
+
+        let directory = path.parent().unwrap();
+        std::fs::create_dir_all(directory.join("textures")).unwrap();
+        image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 0, 255])).save(directory.join("textures").join("foo bar.png")).unwrap();
+
+        let (_, materials, _) = GLTFImporter::import_single_mesh(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(materials[0].texture(), Some("textures/foo bar.png"));
+        assert_eq!(materials[0].normal(), Some("normal.png"));
+
+        let loader = TextureLoader::for_gltf(&path);
+        let data = TextureData::from_file(loader.resolve(materials[0].texture().unwrap()), TextureSlot::BaseColor).unwrap();
+        assert_eq!((data.width(), data.height()), (2, 2));
+
+        std::fs::remove_dir_all(directory).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn uris_are_percent_decoded() {
+        assert_eq!(decode_uri("textures/foo%20bar%2Fbaz.png"), "textures/foo bar/baz.png");
+        assert_eq!(decode_uri("100%.png"), "100%.png");
+        assert_eq!(decode_uri("%e2%9c%93.png"), "\u{2713}.png");
+    }
+}
@@ -0,0 +1,535 @@
+use std::collections::{HashMap, HashSet};
+use nalgebra::{Point3, Vector3};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{Primitive, RenderError};
+
+// connectivity runs over welded positions, the wedge keeps the original vertex of every corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HalfEdge {
+    // welded vertex the half edge leaves from
+    pub vertex: u32,
+    pub wedge: u32,
+    pub face: u32,
+    pub next: u32,
+    pub prev: u32,
+    // None on boundaries and non manifold edges
+    pub twin: Option<u32>
+}
+
+pub struct HalfEdgeMesh {
+    // vertex data of the source primitive, without indices
+    source: Primitive,
+    positions: Vec<Point3<f32>>,
+    // welded vertex of every source vertex
+    vertex_of: Vec<u32>,
+    half_edges: Vec<HalfEdge>,
+    // first half edge and corner count of every face
+    faces: Vec<(u32, u32)>,
+    outgoing: Vec<Vec<u32>>,
+    non_manifold_edges: Vec<(u32, u32)>
+}
+
+impl HalfEdgeMesh {
+    pub fn from_primitive(primitive: &Primitive) -> Result<Self, RenderError> {
+        if primitive.mode != PrimitiveTopology::TriangleList {
+            return Err(RenderError::Geometry(format!("half edges need a triangle list, got {:?}", primitive.mode)));
+        }
+
+        let indices: Vec<u32> = if primitive.indices().is_empty() {
+            (0..primitive.vertex().len() as u32).collect()
+        } else {
+            primitive.indices().to_vec()
+        };
+
+        let triangles = indices.chunks_exact(3);
+        if !triangles.remainder().is_empty() {
+            return Err(RenderError::Geometry(format!("{} indices do not make whole triangles", indices.len())));
+        }
+
+        let faces: Vec<Vec<u32>> = triangles.map(|x| x.to_vec()).collect();
+        Self::from_faces(primitive, &faces)
+    }
+
+    // faces are polygons of at least three wedges, given counter clockwise
+    pub fn from_faces(primitive: &Primitive, faces: &[Vec<u32>]) -> Result<Self, RenderError> {
+        let vertex_count = primitive.vertex().len();
+
+        if let Some(face) = faces.iter().find(|x| x.len() < 3 || x.iter().any(|x| *x as usize >= vertex_count)) {
+            return Err(RenderError::Geometry(format!("face {:?} is not a polygon over {} vertices", face, vertex_count)));
+        }
+
+        let mut welded: HashMap<[u32; 3], u32> = HashMap::new();
+        let mut positions: Vec<Point3<f32>> = Vec::new();
+
+        let vertex_of: Vec<u32> = primitive.vertex()
+            .iter()
+            .map(|vertex| {
+                // adding zero turns -0.0 into 0.0 so both weld
+                let position = vertex.position().add_scalar(0.0);
+                let key = [position.x.to_bits(), position.y.to_bits(), position.z.to_bits()];
+
+                *welded.entry(key).or_insert_with(|| {
+                    positions.push(Point3::from(position));
+                    (positions.len() - 1) as u32
+                })
+            })
+            .collect();
+
+        let mut half_edges: Vec<HalfEdge> = Vec::with_capacity(faces.iter().map(Vec::len).sum());
+        let mut face_ranges: Vec<(u32, u32)> = Vec::with_capacity(faces.len());
+        let mut outgoing: Vec<Vec<u32>> = vec![Vec::new(); positions.len()];
+
+        for (face, wedges) in faces.iter().enumerate() {
+            let first = half_edges.len() as u32;
+            let count = wedges.len() as u32;
+
+            for (corner, wedge) in wedges.iter().enumerate() {
+                let corner = corner as u32;
+                let vertex = vertex_of[*wedge as usize];
+                outgoing[vertex as usize].push(first + corner);
+
+                half_edges.push(HalfEdge {
+                    vertex,
+                    wedge: *wedge,
+                    face: face as u32,
+                    next: first + (corner + 1) % count,
+                    prev: first + (corner + count - 1) % count,
+                    twin: None
+                });
+            }
+
+            face_ranges.push((first, count));
+        }
+
+        let targets: Vec<u32> = half_edges.iter().map(|x| half_edges[x.next as usize].vertex).collect();
+
+        let mut directed: HashMap<(u32, u32), usize> = HashMap::new();
+        for (half_edge, to) in half_edges.iter().zip(targets.iter()) {
+            *directed.entry((half_edge.vertex, *to)).or_default() += 1;
+        }
+
+        let mut by_edge: HashMap<(u32, u32), u32> = HashMap::new();
+        for (index, (half_edge, to)) in half_edges.iter().zip(targets.iter()).enumerate() {
+            by_edge.insert((half_edge.vertex, *to), index as u32);
+        }
+
+        // an edge pairs up only when each direction is used exactly once
+        let mut non_manifold: HashSet<(u32, u32)> = HashSet::new();
+        for (half_edge, to) in half_edges.iter_mut().zip(targets.iter()) {
+            let (from, to) = (half_edge.vertex, *to);
+            let forward = directed.get(&(from, to)).copied().unwrap_or(0);
+            let backward = directed.get(&(to, from)).copied().unwrap_or(0);
+
+            if from == to || forward > 1 || backward > 1 {
+                non_manifold.insert((from.min(to), from.max(to)));
+            } else if backward == 1 {
+                half_edge.twin = Some(by_edge[&(to, from)]);
+            }
+        }
+
+        let mut non_manifold_edges: Vec<(u32, u32)> = non_manifold.into_iter().collect();
+        non_manifold_edges.sort_unstable();
+
+        Ok(Self {
+            source: primitive.select_vertices(&(0..vertex_count as u32).collect::<Vec<u32>>(), Vec::new()),
+            positions,
+            vertex_of,
+            half_edges,
+            faces: face_ranges,
+            outgoing,
+            non_manifold_edges
+        })
+    }
+
+    // the same vertices and, for triangle input, the same indices in the same order, polygons become fans
+    pub fn to_primitive(&self) -> Primitive {
+        let mut indices: Vec<u32> = Vec::with_capacity(self.half_edges.len());
+
+        for face in 0..self.faces.len() {
+            let wedges = self.face_wedges(face as u32);
+
+            for corner in 1..wedges.len() - 1 {
+                indices.extend_from_slice(&[wedges[0], wedges[corner], wedges[corner + 1]]);
+            }
+        }
+
+        let all: Vec<u32> = (0..self.source.vertex().len() as u32).collect();
+        self.source.select_vertices(&all, indices)
+    }
+
+    pub fn source(&self) -> &Primitive {
+        &self.source
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+
+    pub fn position(&self, vertex: u32) -> &Point3<f32> {
+        &self.positions[vertex as usize]
+    }
+
+    pub fn welded_vertex(&self, wedge: u32) -> u32 {
+        self.vertex_of[wedge as usize]
+    }
+
+    pub fn half_edges(&self) -> &Vec<HalfEdge> {
+        &self.half_edges
+    }
+
+    pub fn half_edge(&self, index: u32) -> &HalfEdge {
+        &self.half_edges[index as usize]
+    }
+
+    // the welded vertex a half edge points to
+    pub fn target(&self, index: u32) -> u32 {
+        self.half_edges[self.half_edges[index as usize].next as usize].vertex
+    }
+
+    pub fn face_half_edges(&self, face: u32) -> Vec<u32> {
+        let (first, count) = self.faces[face as usize];
+        (first..first + count).collect()
+    }
+
+    pub fn face_vertices(&self, face: u32) -> Vec<u32> {
+        self.face_half_edges(face).iter().map(|x| self.half_edges[*x as usize].vertex).collect()
+    }
+
+    pub fn face_wedges(&self, face: u32) -> Vec<u32> {
+        self.face_half_edges(face).iter().map(|x| self.half_edges[*x as usize].wedge).collect()
+    }
+
+    // across every edge of the face in order, None on boundaries and non manifold edges
+    pub fn face_neighbours(&self, face: u32) -> Vec<Option<u32>> {
+        self.face_half_edges(face)
+            .iter()
+            .map(|x| self.half_edges[*x as usize].twin.map(|twin| self.half_edges[twin as usize].face))
+            .collect()
+    }
+
+    // Newell's normal, zero for degenerate faces
+    pub fn face_normal(&self, face: u32) -> Vector3<f32> {
+        let points: Vec<Point3<f32>> = self.face_vertices(face).iter().map(|x| self.positions[*x as usize]).collect();
+        polygon_normal(&points).try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros)
+    }
+
+    // angle between the normals on both sides of the edge in radians, None without a twin or next to degenerate faces
+    pub fn dihedral_angle(&self, index: u32) -> Option<f32> {
+        let half_edge = &self.half_edges[index as usize];
+        let twin = &self.half_edges[half_edge.twin? as usize];
+        let a = self.face_normal(half_edge.face);
+        let b = self.face_normal(twin.face);
+
+        if a == Vector3::zeros() || b == Vector3::zeros() {
+            return None;
+        }
+
+        Some(a.dot(&b).clamp(-1.0, 1.0).acos())
+    }
+
+    pub fn outgoing(&self, vertex: u32) -> &Vec<u32> {
+        &self.outgoing[vertex as usize]
+    }
+
+    pub fn is_boundary_edge(&self, index: u32) -> bool {
+        self.half_edges[index as usize].twin.is_none()
+    }
+
+    pub fn is_boundary_vertex(&self, vertex: u32) -> bool {
+        self.outgoing[vertex as usize].iter().any(|x| self.is_boundary_edge(*x))
+    }
+
+    // outgoing half edges of one fan, turning clockwise from a boundary when there is one
+    fn fan(&self, vertex: u32) -> Vec<u32> {
+        let outgoing = &self.outgoing[vertex as usize];
+        let start = match outgoing.iter().find(|x| self.is_boundary_edge(**x)).or_else(|| outgoing.first()) {
+            Some(start) => *start,
+            None => return Vec::new()
+        };
+
+        let mut fan = vec![start];
+        let mut current = start;
+
+        while let Some(twin) = self.half_edges[self.half_edges[current as usize].prev as usize].twin {
+            if twin == start || fan.len() > outgoing.len() {
+                break;
+            }
+
+            fan.push(twin);
+            current = twin;
+        }
+
+        fan
+    }
+
+    // neighbours in fan order around manifold vertices, every neighbour once otherwise
+    pub fn one_ring(&self, vertex: u32) -> Vec<u32> {
+        if self.is_manifold_vertex(vertex) {
+            let fan = self.fan(vertex);
+            let mut ring: Vec<u32> = fan.iter().map(|x| self.target(*x)).collect();
+
+            // an open fan ends on the incoming boundary edge of its last face
+            if let Some(last) = fan.last() {
+                let prev = self.half_edges[*last as usize].prev;
+                if self.is_boundary_edge(prev) {
+                    ring.push(self.half_edges[prev as usize].vertex);
+                }
+            }
+
+            return ring;
+        }
+
+        let mut ring: Vec<u32> = Vec::new();
+
+        for half_edge in self.outgoing[vertex as usize].iter() {
+            let prev = self.half_edges[*half_edge as usize].prev;
+
+            for neighbour in [self.target(*half_edge), self.half_edges[prev as usize].vertex].iter() {
+                if *neighbour != vertex && !ring.contains(neighbour) {
+                    ring.push(*neighbour);
+                }
+            }
+        }
+
+        ring
+    }
+
+    pub fn vertex_faces(&self, vertex: u32) -> Vec<u32> {
+        self.outgoing[vertex as usize].iter().map(|x| self.half_edges[*x as usize].face).collect()
+    }
+
+    // undirected, smaller vertex first
+    pub fn non_manifold_edges(&self) -> &Vec<(u32, u32)> {
+        &self.non_manifold_edges
+    }
+
+    // on a non manifold edge, or where several fans meet in a single point
+    pub fn is_manifold_vertex(&self, vertex: u32) -> bool {
+        let outgoing = &self.outgoing[vertex as usize];
+
+        let on_bad_edge = outgoing.iter().any(|x| {
+            let edge = (self.half_edges[*x as usize].vertex, self.target(*x));
+            self.non_manifold_edges.binary_search(&(edge.0.min(edge.1), edge.0.max(edge.1))).is_ok()
+        });
+
+        !on_bad_edge && self.fan(vertex).len() == outgoing.len()
+    }
+
+    pub fn non_manifold_vertices(&self) -> Vec<u32> {
+        (0..self.positions.len() as u32).filter(|x| !self.is_manifold_vertex(*x)).collect()
+    }
+
+    pub fn is_manifold(&self) -> bool {
+        self.non_manifold_edges.is_empty() && (0..self.positions.len() as u32).all(|x| self.is_manifold_vertex(x))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.half_edges.iter().all(|x| x.twin.is_some())
+    }
+
+    // welded vertices of every hole, following the boundary half edges
+    pub fn boundary_loops(&self) -> Vec<Vec<u32>> {
+        let mut visited: Vec<bool> = vec![false; self.half_edges.len()];
+        let mut loops: Vec<Vec<u32>> = Vec::new();
+
+        for start in 0..self.half_edges.len() as u32 {
+            if visited[start as usize] || !self.is_boundary_edge(start) || self.is_non_manifold_edge(start) {
+                continue;
+            }
+
+            let mut boundary: Vec<u32> = Vec::new();
+            let mut current = start;
+
+            loop {
+                visited[current as usize] = true;
+                boundary.push(self.half_edges[current as usize].vertex);
+
+                // turn around the target until the next boundary half edge leaving it
+                let mut next = self.half_edges[current as usize].next;
+                let mut turns = 0;
+                while let Some(twin) = self.half_edges[next as usize].twin {
+                    next = self.half_edges[twin as usize].next;
+                    turns += 1;
+
+                    if turns > self.half_edges.len() {
+                        break;
+                    }
+                }
+
+                if next == start || visited[next as usize] || self.is_non_manifold_edge(next) {
+                    break;
+                }
+
+                current = next;
+            }
+
+            loops.push(boundary);
+        }
+
+        loops
+    }
+
+    pub fn is_non_manifold_edge(&self, index: u32) -> bool {
+        let from = self.half_edges[index as usize].vertex;
+        let to = self.target(index);
+
+        self.non_manifold_edges.binary_search(&(from.min(to), from.max(to))).is_ok()
+    }
+}
+
+// Newell's normal of a closed polygon, its length is twice the area when the polygon is planar
+pub fn polygon_normal(points: &[Point3<f32>]) -> Vector3<f32> {
+    let mut normal = Vector3::zeros();
+
+    for (corner, a) in points.iter().enumerate() {
+        let b = &points[(corner + 1) % points.len()];
+        normal += Vector3::new((a.y - b.y) * (a.z + b.z), (a.z - b.z) * (a.x + b.x), (a.x - b.x) * (a.y + b.y));
+    }
+
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Vector2, Vector4};
+    use crate::renderer::{layout::Semantic, shapes, vertex::Vertex};
+
+    fn mesh(points: &[[f32; 2]], triangles: &[[u32; 3]]) -> HalfEdgeMesh {
+        let vertices = points
+            .iter()
+            .map(|x| Vertex::new(Vector3::new(x[0], x[1], 0.0), Vector3::z(), Vector4::zeros(), Vector2::zeros()))
+            .collect();
+        let primitive = Primitive::new(vertices, triangles.concat(), 0, PrimitiveTopology::TriangleList);
+
+        HalfEdgeMesh::from_primitive(&primitive).unwrap()
+    }
+
+    fn twin_pairs(mesh: &HalfEdgeMesh) -> usize {
+        for (index, half_edge) in mesh.half_edges().iter().enumerate() {
+            if let Some(twin) = half_edge.twin {
+                assert_eq!(mesh.half_edge(twin).twin, Some(index as u32));
+                assert_eq!(mesh.half_edge(twin).vertex, mesh.target(index as u32));
+                assert_eq!(mesh.target(twin), half_edge.vertex);
+            }
+        }
+
+        mesh.half_edges().iter().filter(|x| x.twin.is_some()).count() / 2
+    }
+
+    // loops start wherever the search found them
+    fn rotated_to_smallest(mut boundary: Vec<u32>) -> Vec<u32> {
+        let first = (0..boundary.len()).min_by_key(|x| boundary[*x]).unwrap_or(0);
+        boundary.rotate_left(first);
+        boundary
+    }
+
+    #[test]
+    fn quad() {
+        let quad = mesh(&[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]], &[[0, 1, 2], [0, 2, 3]]);
+
+        // only the diagonal is shared
+        assert_eq!(twin_pairs(&quad), 1);
+        assert!(quad.is_manifold());
+        assert!(!quad.is_closed());
+
+        assert_eq!(quad.one_ring(0), vec![1, 2, 3]);
+        assert_eq!(quad.one_ring(1), vec![2, 0]);
+        assert_eq!(quad.one_ring(2), vec![3, 0, 1]);
+
+        let loops = quad.boundary_loops();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(rotated_to_smallest(loops[0].clone()), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn open_strip() {
+        // bottom row 0 to 3, top row 4 to 7
+        let points = [[0.0, 0.0], [1.0, 0.0], [2.0, 0.0], [3.0, 0.0], [0.0, 1.0], [1.0, 1.0], [2.0, 1.0], [3.0, 1.0]];
+        let triangles: Vec<[u32; 3]> = (0..3).flat_map(|x| vec![[x, x + 1, x + 5], [x, x + 5, x + 4]]).collect();
+        let strip = mesh(&points, &triangles);
+
+        // three diagonals and the two inner rungs
+        assert_eq!(twin_pairs(&strip), 5);
+        assert!(strip.is_manifold());
+
+        assert_eq!(strip.one_ring(1), vec![2, 6, 5, 0]);
+        assert_eq!(strip.one_ring(6), vec![5, 1, 2, 7]);
+        assert!(strip.is_boundary_vertex(1) && strip.is_boundary_vertex(6));
+
+        let loops = strip.boundary_loops();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(rotated_to_smallest(loops[0].clone()), vec![0, 1, 2, 3, 7, 6, 5, 4]);
+    }
+
+    #[test]
+    fn non_manifold_fan() {
+        // three triangles on the edge 0 1, a book with three pages
+        let points = [[0.0, 0.0], [1.0, 0.0], [0.5, 1.0], [0.5, -1.0], [0.5, 2.0]];
+        let fan = mesh(&points, &[[0, 1, 2], [1, 0, 3], [0, 1, 4]]);
+
+        assert_eq!(fan.non_manifold_edges(), &vec![(0, 1)]);
+        assert!(!fan.is_manifold());
+        assert_eq!(fan.non_manifold_vertices(), vec![0, 1]);
+
+        // nothing pairs across the shared edge
+        assert_eq!(twin_pairs(&fan), 0);
+
+        let mut ring = fan.one_ring(0);
+        ring.sort_unstable();
+        assert_eq!(ring, vec![1, 2, 3, 4]);
+
+        // the pages' outer edges still form loops, the shared edge is left out of them
+        for boundary in fan.boundary_loops() {
+            assert!(boundary.len() >= 2);
+            assert!(boundary.iter().all(|x| *x < 5));
+        }
+    }
+
+    #[test]
+    fn bowtie() {
+        // two triangles touching in vertex 0 only
+        let points = [[0.0, 0.0], [1.0, -0.5], [1.0, 0.5], [-1.0, 0.5], [-1.0, -0.5]];
+        let bowtie = mesh(&points, &[[0, 1, 2], [0, 3, 4]]);
+
+        assert!(bowtie.non_manifold_edges().is_empty());
+        assert_eq!(bowtie.non_manifold_vertices(), vec![0]);
+
+        let mut ring = bowtie.one_ring(0);
+        ring.sort_unstable();
+        assert_eq!(ring, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn round_trip_keeps_seams() {
+        let cube = shapes::cube(1.0, 1);
+        let mut source = cube.primitives.into_iter().next().unwrap();
+        let colors: Vec<[f32; 4]> = (0..source.vertex().len()).map(|x| [x as f32, 0.0, 0.0, 1.0]).collect();
+        source.set_attribute(Semantic::Color, colors.clone());
+
+        // the 24 face corners weld down to the 8 cube corners, the uv seams live on in the wedges
+        let mesh = HalfEdgeMesh::from_primitive(&source).unwrap();
+        assert_eq!(mesh.vertex_count(), 8);
+        assert!(mesh.is_closed());
+
+        let primitive = mesh.to_primitive();
+        let bytes = |x: &Primitive| bytemuck::cast_slice::<Vertex, u8>(x.vertex()).to_vec();
+
+        assert_eq!(bytes(&primitive), bytes(&source));
+        assert_eq!(primitive.indices().to_vec(), source.indices().to_vec());
+        assert_eq!((primitive.material_index, primitive.mode), (source.material_index, source.mode));
+        assert_eq!((0..colors.len()).map(|x| primitive.attribute(Semantic::Color, x)).collect::<Vec<_>>(), colors);
+    }
+
+    #[test]
+    fn polygons_come_back_as_fans() {
+        let square = mesh(&[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]], &[[0, 1, 2]]);
+        let polygon = HalfEdgeMesh::from_faces(square.source(), &[vec![0, 1, 2, 3]]).unwrap();
+
+        assert_eq!(polygon.to_primitive().indices().to_vec(), vec![0, 1, 2, 0, 2, 3]);
+    }
+}
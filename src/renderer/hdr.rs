@@ -0,0 +1,417 @@
+use std::{fs::File, io::{BufRead, BufReader}, path::Path};
+use half::f16;
+use nalgebra::Vector3;
+use wgpu::TextureFormat;
+use crate::renderer::{texture::{MipLevel, TextureData}, RenderError};
+
+// new style run length encoding is only allowed for these scanline widths
+const MIN_RLE_WIDTH: u32 = 8;
+const MAX_RLE_WIDTH: u32 = 0x7fff;
+// a 16k by 8k panorama, anything larger is taken for a broken header
+const MAX_PIXELS: usize = 1 << 27;
+
+// linear f32 rgb pixels, row major starting at the top left
+#[derive(Debug, Clone)]
+pub struct HdrImage {
+    width: u32,
+    height: u32,
+    data: Vec<Vector3<f32>>
+}
+
+impl HdrImage {
+    pub fn new(width: u32, height: u32, data: Vec<Vector3<f32>>) -> Result<Self, RenderError> {
+        if Some(data.len()) != pixel_count(width, height) {
+            return Err(RenderError::Texture(format!("{} pixels do not make a {}x{} image", data.len(), width, height)));
+        }
+
+        Ok(Self::from_parts(width, height, data))
+    }
+
+    // for data that has the right size by construction
+    pub(crate) fn from_parts(width: u32, height: u32, data: Vec<Vector3<f32>>) -> Self {
+        debug_assert_eq!(Some(data.len()), pixel_count(width, height));
+
+        Self {
+            width,
+            height,
+            data
+        }
+    }
+
+    pub fn from_file<P>(path: P) -> Result<Self, RenderError>
+        where P: AsRef<Path> {
+        let file = File::open(path)?;
+
+        Self::from_reader(&mut BufReader::new(file))
+    }
+
+    // reads a Radiance .hdr (RGBE) file, flat or run length encoded
+    pub fn from_reader<R>(reader: &mut R) -> Result<Self, RenderError>
+        where R: BufRead {
+        let (width, height, flip_y) = Self::read_header(reader)?;
+        let count = pixel_count(width, height)
+            .filter(|x| *x <= MAX_PIXELS)
+            .ok_or_else(|| RenderError::Texture(format!("HDR resolution {}x{} is too large", width, height)))?;
+
+        // the header alone is not trusted with a large allocation, every scanline takes at least four bytes
+        // so no more are reserved than the buffered input could start, the rest grows as scanlines arrive
+        let buffered = reader.fill_buf()?.len() / 4;
+        let mut data: Vec<Vector3<f32>> = Vec::with_capacity(count.min(buffered.saturating_mul(width as usize)));
+        let mut scanline: Vec<[u8; 4]> = vec![[0; 4]; width as usize];
+
+        for _ in 0..height {
+            Self::read_scanline(reader, &mut scanline)?;
+            data.extend(scanline.iter().map(|x| rgbe_to_rgb(*x)));
+        }
+
+        let mut image = Self::from_parts(width, height, data);
+
+        if flip_y {
+            image.flip_vertically();
+        }
+
+        Ok(image)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn data(&self) -> &Vec<Vector3<f32>> {
+        &self.data
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Vector3<f32> {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    // u wraps around, v is clamped, which is what an equirectangular panorama needs
+    pub fn sample_bilinear(&self, u: f32, v: f32) -> Vector3<f32> {
+        let x = u * self.width as f32 - 0.5;
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+
+        let wrap = |x: i64| x.rem_euclid(self.width as i64) as u32;
+        let x0i = wrap(x0 as i64);
+        let x1i = wrap(x0 as i64 + 1);
+        let y0i = y0 as u32;
+        let y1i = (y0i + 1).min(self.height - 1);
+
+        let top = self.pixel(x0i, y0i) * (1.0 - tx) + self.pixel(x1i, y0i) * tx;
+        let bottom = self.pixel(x0i, y1i) * (1.0 - tx) + self.pixel(x1i, y1i) * tx;
+
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    pub fn flip_vertically(&mut self) {
+        let width = self.width as usize;
+        let height = self.height as usize;
+
+        for y in 0..height / 2 {
+            for x in 0..width {
+                self.data.swap(y * width + x, (height - 1 - y) * width + x);
+            }
+        }
+    }
+
+    // 2x2 box filter, odd sizes clamp to the last row and column
+    pub fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut data: Vec<Vector3<f32>> = Vec::with_capacity(width as usize * height as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let x0 = (x * 2).min(self.width - 1);
+                let x1 = (x * 2 + 1).min(self.width - 1);
+                let y0 = (y * 2).min(self.height - 1);
+                let y1 = (y * 2 + 1).min(self.height - 1);
+
+                data.push((self.pixel(x0, y0) + self.pixel(x1, y0) + self.pixel(x0, y1) + self.pixel(x1, y1)) * 0.25);
+            }
+        }
+
+        Self::from_parts(width, height, data)
+    }
+
+    pub fn mip_chain(&self) -> Vec<HdrImage> {
+        let mut chain = vec![self.clone()];
+
+        while chain.last().map(|x| x.width > 1 || x.height > 1).unwrap_or(false) {
+            let next = chain.last().unwrap().downsample();
+            chain.push(next);
+        }
+
+        chain
+    }
+
+    // half floats keep the range and stay filterable everywhere, unlike Rgba32Float
+    pub fn to_mip_level(&self) -> MipLevel {
+        let mut data: Vec<u8> = Vec::with_capacity(self.data.len() * 8);
+
+        for pixel in self.data.iter() {
+            for value in [pixel.x, pixel.y, pixel.z, 1.0].iter() {
+                data.extend_from_slice(&f16::from_f32(*value).to_bits().to_le_bytes());
+            }
+        }
+
+        MipLevel {
+            width: self.width,
+            height: self.height,
+            data
+        }
+    }
+
+    pub fn to_texture_data(&self) -> TextureData {
+        let mips = self.mip_chain().iter().map(HdrImage::to_mip_level).collect();
+
+        TextureData::new(TextureFormat::Rgba16Float, 8, mips)
+    }
+
+    fn read_header<R>(reader: &mut R) -> Result<(u32, u32, bool), RenderError>
+        where R: BufRead {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+
+        if !line.starts_with("#?") {
+            return Err(RenderError::Texture("Not a Radiance HDR file".to_string()));
+        }
+
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(RenderError::Texture("Unexpected end of HDR header".to_string()));
+            }
+
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                break;
+            }
+
+            if let Some(format) = trimmed.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(RenderError::Texture(format!("Unsupported HDR format {}", format)));
+                }
+            }
+        }
+
+        line.clear();
+        reader.read_line(&mut line)?;
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let parse = |x: &str| x.parse::<u32>()
+            .ok()
+            .filter(|x| *x > 0)
+            .ok_or_else(|| RenderError::Texture(format!("Invalid HDR resolution {}", line.trim())));
+
+        match tokens.as_slice() {
+            ["-Y", height, "+X", width] => Ok((parse(width)?, parse(height)?, false)),
+            ["+Y", height, "+X", width] => Ok((parse(width)?, parse(height)?, true)),
+            _ => Err(RenderError::Texture(format!("Unsupported HDR orientation {}", line.trim())))
+        }
+    }
+
+    fn read_scanline<R>(reader: &mut R, scanline: &mut [[u8; 4]]) -> Result<(), RenderError>
+        where R: BufRead {
+        let width = scanline.len() as u32;
+        let mut first = [0u8; 4];
+        reader.read_exact(&mut first)?;
+
+        let is_rle = (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width) &&
+            first[0] == 2 && first[1] == 2 && first[2] & 0x80 == 0;
+
+        if !is_rle {
+            scanline[0] = first;
+            return Self::read_flat_scanline(reader, scanline);
+        }
+
+        if ((first[2] as u32) << 8 | first[3] as u32) != width {
+            return Err(RenderError::Texture("HDR scanline width mismatch".to_string()));
+        }
+
+        // every channel is run length encoded separately
+        for channel in 0..4 {
+            let mut x = 0usize;
+
+            while x < scanline.len() {
+                let mut count = [0u8; 1];
+                reader.read_exact(&mut count)?;
+
+                if count[0] > 128 {
+                    let run = (count[0] - 128) as usize;
+                    let mut value = [0u8; 1];
+                    reader.read_exact(&mut value)?;
+
+                    if x + run > scanline.len() {
+                        return Err(RenderError::Texture("HDR run overflows scanline".to_string()));
+                    }
+
+                    for pixel in scanline[x..x + run].iter_mut() {
+                        pixel[channel] = value[0];
+                    }
+                    x += run;
+                } else {
+                    let run = count[0] as usize;
+
+                    if run == 0 || x + run > scanline.len() {
+                        return Err(RenderError::Texture("Invalid HDR literal run".to_string()));
+                    }
+
+                    let mut values = vec![0u8; run];
+                    reader.read_exact(&mut values)?;
+
+                    for (pixel, value) in scanline[x..x + run].iter_mut().zip(values) {
+                        pixel[channel] = value;
+                    }
+                    x += run;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // the first pixel is already in place, (1, 1, 1, n) is the old style repeat marker
+    fn read_flat_scanline<R>(reader: &mut R, scanline: &mut [[u8; 4]]) -> Result<(), RenderError>
+        where R: BufRead {
+        let mut x = 1usize;
+        let mut shift = 0u32;
+
+        while x < scanline.len() {
+            let mut pixel = [0u8; 4];
+            reader.read_exact(&mut pixel)?;
+
+            if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 {
+                let run = (pixel[3] as usize) << shift;
+                let previous = scanline[x - 1];
+
+                if x + run > scanline.len() {
+                    return Err(RenderError::Texture("HDR run overflows scanline".to_string()));
+                }
+
+                for value in scanline[x..x + run].iter_mut() {
+                    *value = previous;
+                }
+                x += run;
+                shift += 8;
+            } else {
+                scanline[x] = pixel;
+                x += 1;
+                shift = 0;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn pixel_count(width: u32, height: u32) -> Option<usize> {
+    (width as usize).checked_mul(height as usize)
+}
+
+pub fn rgbe_to_rgb(rgbe: [u8; 4]) -> Vector3<f32> {
+    if rgbe[3] == 0 {
+        return Vector3::zeros();
+    }
+
+    let scale = 2f32.powi(rgbe[3] as i32 - (128 + 8));
+
+    Vector3::new(rgbe[0] as f32 * scale, rgbe[1] as f32 * scale, rgbe[2] as f32 * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an exponent of 136 scales the mantissas by one, so every channel decodes to itself
+    const E: u8 = 136;
+
+    fn file(resolution: &str, scanlines: &[u8]) -> Vec<u8> {
+        let mut bytes = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution).into_bytes();
+        bytes.extend_from_slice(scanlines);
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> HdrImage {
+        HdrImage::from_reader(&mut &bytes[..]).unwrap()
+    }
+
+    #[test]
+    fn flat_file() {
+        let pixels: Vec<u8> = (0..6u8).flat_map(|x| [x, x + 10, x + 20, E]).collect();
+        let image = decode(&file("-Y 2 +X 3", &pixels));
+
+        assert_eq!((image.width(), image.height()), (3, 2));
+        assert_eq!(image.pixel(0, 0), Vector3::new(0.0, 10.0, 20.0));
+        assert_eq!(image.pixel(2, 0), Vector3::new(2.0, 12.0, 22.0));
+        assert_eq!(image.pixel(1, 1), Vector3::new(4.0, 14.0, 24.0));
+
+        // bottom to top files end up the same way up
+        let flipped = decode(&file("+Y 2 +X 3", &pixels));
+        assert_eq!(flipped.pixel(1, 0), image.pixel(1, 1));
+        assert_eq!(flipped.pixel(1, 1), image.pixel(1, 0));
+    }
+
+    #[test]
+    fn old_style_rle_file() {
+        // two pixels, the second repeated three more times
+        let scanline = [1, 2, 3, E, 4, 5, 6, E, 1, 1, 1, 3];
+        let image = decode(&file("-Y 1 +X 5", &scanline));
+
+        assert_eq!(image.pixel(0, 0), Vector3::new(1.0, 2.0, 3.0));
+
+        for x in 1..5 {
+            assert_eq!(image.pixel(x, 0), Vector3::new(4.0, 5.0, 6.0));
+        }
+    }
+
+    #[test]
+    fn new_style_rle_file() {
+        let mut scanline = vec![2, 2, 0, 8];
+        // red is a run of four followed by four literals, the rest are single runs
+        scanline.extend_from_slice(&[128 + 4, 7, 4, 1, 2, 3, 4]);
+        scanline.extend_from_slice(&[128 + 8, 5]);
+        scanline.extend_from_slice(&[128 + 8, 6]);
+        scanline.extend_from_slice(&[128 + 8, E]);
+
+        let image = decode(&file("-Y 1 +X 8", &scanline));
+        let red: Vec<f32> = (0..8).map(|x| image.pixel(x, 0).x).collect();
+
+        assert_eq!(red, [7.0, 7.0, 7.0, 7.0, 1.0, 2.0, 3.0, 4.0]);
+        assert!((0..8).all(|x| image.pixel(x, 0).yz() == nalgebra::Vector2::new(5.0, 6.0)));
+    }
+
+    #[test]
+    fn overflowing_runs_are_rejected() {
+        let scanline = [2, 2, 0, 8, 128 + 9, 1];
+
+        assert!(HdrImage::from_reader(&mut &file("-Y 1 +X 8", &scanline)[..]).is_err());
+    }
+
+    #[test]
+    fn oversized_header_is_rejected() {
+        for resolution in ["-Y 70000 +X 70000", "-Y 4294967295 +X 4294967295"] {
+            match HdrImage::from_reader(&mut &file(resolution, &[])[..]) {
+                Err(RenderError::Texture(message)) => assert!(message.contains("too large"), "{}", message),
+                _ => panic!("{} was accepted", resolution)
+            }
+        }
+
+        // a plausible size still fails cleanly once the scanlines run out
+        assert!(HdrImage::from_reader(&mut &file("-Y 8000 +X 8000", &[1, 2, 3, E])[..]).is_err());
+    }
+
+    #[test]
+    fn mismatched_pixel_count_is_rejected() {
+        assert!(HdrImage::new(2, 2, vec![Vector3::zeros(); 3]).is_err());
+        assert!(HdrImage::new(2, 2, vec![Vector3::zeros(); 4]).is_ok());
+    }
+}
@@ -0,0 +1,112 @@
+use wgpu::{Buffer, BufferUsage, Device, IndexFormat};
+
+// 0xFFFF is the strip restart value for 16 bit indices, so it is never handed out as a vertex
+const MAX_U16_VERTEX_COUNT: usize = 0xFFFF;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>)
+}
+
+impl Default for Indices {
+    fn default() -> Self {
+        Indices::U16(Vec::new())
+    }
+}
+
+impl Indices {
+    // picks the narrowest format able to address every vertex
+    pub fn new(indices: Vec<u32>, vertex_count: usize) -> Self {
+        if vertex_count <= MAX_U16_VERTEX_COUNT && indices.iter().all(|x| (*x as usize) < MAX_U16_VERTEX_COUNT) {
+            return Indices::U16(indices.into_iter().map(|x| x as u16).collect());
+        }
+
+        Indices::U32(indices)
+    }
+
+    pub fn format(&self) -> IndexFormat {
+        match self {
+            Indices::U16(_) => IndexFormat::Uint16,
+            Indices::U32(_) => IndexFormat::Uint32
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> u32 {
+        match self {
+            Indices::U16(indices) => indices[index] as u32,
+            Indices::U32(indices) => indices[index]
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = u32> + '_> {
+        match self {
+            Indices::U16(indices) => Box::new(indices.iter().map(|x| *x as u32)),
+            Indices::U32(indices) => Box::new(indices.iter().copied())
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<u32> {
+        self.iter().collect()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Indices::U16(indices) => bytemuck::cast_slice(indices),
+            Indices::U32(indices) => bytemuck::cast_slice(indices)
+        }
+    }
+
+    pub fn get_buffer(&self, device: &Device) -> Buffer {
+        // buffer sizes must stay a multiple of four bytes, an odd u16 count gets one padding index
+        let mut data = self.as_bytes().to_vec();
+        data.resize((data.len() + 3) & !3, 0);
+
+        device.create_buffer_with_data(&data, BufferUsage::INDEX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u16_stops_short_of_the_restart_value() {
+        assert_eq!(Indices::new(vec![0, 1, 0xFFFE], 0xFFFF).format(), IndexFormat::Uint16);
+        assert_eq!(Indices::new(vec![0, 1, 2], 0x10000).format(), IndexFormat::Uint32);
+        assert_eq!(Indices::new(vec![0, 1, 0xFFFF], 3).format(), IndexFormat::Uint32);
+        assert_eq!(Indices::new(Vec::new(), 0), Indices::default());
+    }
+
+    #[test]
+    fn both_formats_round_trip() {
+        let source = vec![3, 0, 2, 0xFFFE, 1];
+
+        for indices in [Indices::new(source.clone(), 0xFFFF), Indices::new(source.clone(), 0x10000)].iter() {
+            assert_eq!(indices.len(), 5);
+            assert!(!indices.is_empty());
+            assert_eq!(indices.get(3), 0xFFFE);
+            assert_eq!(indices.to_vec(), source);
+            assert_eq!(indices.iter().collect::<Vec<u32>>(), source);
+        }
+
+        let narrow = Indices::new(source.clone(), 0xFFFF);
+        assert_eq!(narrow.as_bytes().len(), 10);
+        assert_eq!(bytemuck::cast_slice::<u8, u16>(narrow.as_bytes()), &[3, 0, 2, 0xFFFE, 1]);
+
+        let wide = Indices::new(source.clone(), 0x10000);
+        assert_eq!(wide.as_bytes().len(), 20);
+        assert_eq!(bytemuck::cast_slice::<u8, u32>(wide.as_bytes()), &source[..]);
+    }
+}
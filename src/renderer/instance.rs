@@ -0,0 +1,152 @@
+use nalgebra::{Vector3, UnitQuaternion, Matrix4, Translation3};
+use wgpu::{Buffer,
+           BufferUsage,
+           Device,
+           InputStepMode,
+           VertexAttributeDescriptor,
+           VertexBufferDescriptor,
+           VertexFormat};
+use bytemuck::{Zeroable,
+               Pod};
+
+unsafe impl Zeroable for InstanceRaw {}
+unsafe impl Pod for InstanceRaw {}
+
+// first shader location used by the per instance model matrix, right after the Vertex attributes
+pub const INSTANCE_SHADER_LOCATION: u32 = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Instance {
+    translation: Vector3<f32>,
+    rotation: UnitQuaternion<f32>,
+    scale: Vector3<f32>
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct InstanceRaw {
+    model: Matrix4<f32>
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::<f32>::zeros(),
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3::new(1.0, 1.0, 1.0)
+        }
+    }
+}
+
+impl Instance {
+    pub fn new(translation: Vector3<f32>,
+               rotation: UnitQuaternion<f32>,
+               scale: Vector3<f32>) -> Self {
+        Self {
+            translation,
+            rotation,
+            scale
+        }
+    }
+
+    pub fn set_translation(&mut self, translation: Vector3<f32>) {
+        self.translation = translation;
+    }
+
+    pub fn set_rotation(&mut self, rotation: UnitQuaternion<f32>) {
+        self.rotation = rotation;
+    }
+
+    pub fn set_scale(&mut self, scale: Vector3<f32>) {
+        self.scale = scale;
+    }
+
+    pub fn translation(&self) -> &Vector3<f32> {
+        &self.translation
+    }
+
+    pub fn rotation(&self) -> &UnitQuaternion<f32> {
+        &self.rotation
+    }
+
+    pub fn scale(&self) -> &Vector3<f32> {
+        &self.scale
+    }
+
+    // same T * R * S order glTF uses for nodes
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Translation3::from(self.translation).to_homogeneous() *
+            self.rotation.to_homogeneous() *
+            Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+}
+
+impl InstanceRaw {
+    pub fn new(model: Matrix4<f32>) -> Self {
+        Self {
+            model
+        }
+    }
+
+    pub fn get_buffer(instances: &[InstanceRaw], device: &Device) -> Buffer {
+        device.create_buffer_with_data(bytemuck::cast_slice(instances), BufferUsage::VERTEX)
+    }
+
+    pub fn get_buffer_descriptor<'a>() -> VertexBufferDescriptor<'a> {
+        VertexBufferDescriptor {
+            stride: std::mem::size_of::<InstanceRaw>() as u64,
+            step_mode: InputStepMode::Instance,
+            attributes: &[
+                VertexAttributeDescriptor {
+                    format: VertexFormat::Float4,
+                    shader_location: INSTANCE_SHADER_LOCATION,
+                    offset: 0,
+                },
+                VertexAttributeDescriptor {
+                    format: VertexFormat::Float4,
+                    shader_location: INSTANCE_SHADER_LOCATION + 1,
+                    offset: 4 * 4,
+                },
+                VertexAttributeDescriptor {
+                    format: VertexFormat::Float4,
+                    shader_location: INSTANCE_SHADER_LOCATION + 2,
+                    offset: 8 * 4,
+                },
+                VertexAttributeDescriptor {
+                    format: VertexFormat::Float4,
+                    shader_location: INSTANCE_SHADER_LOCATION + 3,
+                    offset: 12 * 4,
+                },
+            ],
+        }
+    }
+}
+
+impl From<Matrix4<f32>> for InstanceRaw {
+    fn from(model: Matrix4<f32>) -> Self {
+        Self::new(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+
+    #[test]
+    fn scale_then_rotate_then_translate() {
+        let instance = Instance::new(Vector3::new(1.0, 2.0, 3.0),
+                                     UnitQuaternion::from_axis_angle(&Vector3::z_axis(), std::f32::consts::FRAC_PI_2),
+                                     Vector3::new(2.0, 1.0, 1.0));
+
+        // (1, 0, 0) grows to (2, 0, 0), turns to (0, 2, 0) and moves to (1, 4, 3)
+        let point = instance.to_matrix().transform_point(&Point3::new(1.0, 0.0, 0.0));
+
+        assert!((point - Point3::new(1.0, 4.0, 3.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn default_is_identity() {
+        assert_eq!(Instance::default().to_matrix(), Matrix4::identity());
+    }
+}
@@ -0,0 +1,432 @@
+use std::{collections::HashMap, sync::OnceLock};
+use nalgebra::{Point3, Vector2, Vector3, Vector4};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{bounds::Aabb, sdf::SdfGrid, shapes, vertex::Vertex, Primitive};
+
+// loops larger than this get a center vertex instead of a fan, keeping the triangles out of concave corners
+const MAX_FAN_LOOP: usize = 6;
+
+// cube corners are numbered x + 2y + 4z, edges are numbered axis * 4 + the index of their lower corner on that axis
+struct Cube {
+    // corners of every face, counter clockwise seen from outside
+    faces: [[u8; 4]; 6],
+    // edge between two corners, indexed by both corners
+    edge_between: [[u8; 8]; 8]
+}
+
+// crossed edges in order, a loop that runs over a face twice would put fan diagonals on that face
+// where the neighbour has its own triangles, so it gets a center vertex instead
+struct Loop {
+    edges: Vec<u8>,
+    center: bool
+}
+
+// loops per case and per face decision
+struct Table {
+    loops: Vec<Vec<Loop>>
+}
+
+// vertices on a crossed lattice edge, or on a lattice point when the crossing lands exactly on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum VertexKey {
+    Edge(usize, usize),
+    Point(usize)
+}
+
+static TABLES: OnceLock<(Cube, Table)> = OnceLock::new();
+
+impl Cube {
+    fn new() -> Self {
+        let mut faces = [[0u8; 4]; 6];
+
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+            for side in 0..2 {
+                let corner = |a: usize, b: usize| ((side << axis) | (a << u) | (b << v)) as u8;
+                // u x v is the axis, so this order faces the positive side
+                let mut face = [corner(0, 0), corner(1, 0), corner(1, 1), corner(0, 1)];
+
+                if side == 0 {
+                    face.reverse();
+                }
+
+                faces[axis * 2 + side] = face;
+            }
+        }
+
+        let mut edge_between = [[u8::MAX; 8]; 8];
+
+        for axis in 0..3 {
+            let mut index = 0;
+
+            for corner in 0..8u8 {
+                if corner & (1 << axis) == 0 {
+                    let other = corner | (1 << axis);
+                    edge_between[corner as usize][other as usize] = (axis * 4 + index) as u8;
+                    edge_between[other as usize][corner as usize] = (axis * 4 + index) as u8;
+                    index += 1;
+                }
+            }
+        }
+
+        Self {
+            faces,
+            edge_between
+        }
+    }
+
+    // lower corner and axis of an edge
+    fn edge(&self, edge: u8) -> (u8, usize) {
+        let axis = (edge / 4) as usize;
+        let lower = (0..8u8).filter(|x| x & (1 << axis) == 0).nth((edge % 4) as usize).unwrap();
+
+        (lower, axis)
+    }
+}
+
+impl Table {
+    // bit f of a decision says whether the high corners of face f connect, it only matters on faces with
+    // four crossings, the same bit comes out for both cubes sharing the face, so the surface never cracks
+    fn new(cube: &Cube) -> Self {
+        let mut loops: Vec<Vec<Loop>> = Vec::with_capacity(256 * 64);
+
+        for case in 0..256usize {
+            let high = |corner: u8| case & (1 << corner) != 0;
+
+            for decision in 0..64usize {
+                // a segment leaves the cube through the edge where the face turns from high to low
+                let mut next: HashMap<u8, (u8, usize)> = HashMap::new();
+
+                for (index, face) in cube.faces.iter().enumerate() {
+                    let edge = |k: usize| cube.edge_between[face[k % 4] as usize][face[(k + 1) % 4] as usize];
+                    let exits: Vec<usize> = (0..4).filter(|k| high(face[*k]) && !high(face[(k + 1) % 4])).collect();
+                    let ambiguous = exits.len() == 2;
+
+                    for k in exits {
+                        // with four crossings the segment either closes around the low corner ahead or the high corner behind
+                        let enter = if !ambiguous {
+                            (1..4).map(|x| k + x).find(|x| !high(face[x % 4]) && high(face[(x + 1) % 4])).unwrap()
+                        } else if decision & (1 << index) != 0 {
+                            k + 1
+                        } else {
+                            k + 3
+                        };
+
+                        next.insert(edge(k), (edge(enter), index));
+                    }
+                }
+
+                let mut case_loops: Vec<Loop> = Vec::new();
+                let mut starts: Vec<u8> = next.keys().copied().collect();
+                starts.sort_unstable();
+
+                for start in starts {
+                    if !next.contains_key(&start) {
+                        continue;
+                    }
+
+                    let mut edges = vec![start];
+                    let mut faces = 0u8;
+                    let mut center = false;
+                    let mut current = start;
+
+                    while let Some((following, face)) = next.remove(&current) {
+                        center |= faces & (1 << face) != 0;
+                        faces |= 1 << face;
+
+                        if following == start {
+                            break;
+                        }
+
+                        edges.push(following);
+                        current = following;
+                    }
+
+                    let center = center || edges.len() > MAX_FAN_LOOP;
+
+                    case_loops.push(Loop {
+                        edges,
+                        center
+                    });
+                }
+
+                loops.push(case_loops);
+            }
+        }
+
+        Self {
+            loops
+        }
+    }
+}
+
+// samples the field on a lattice over the bounds, cells along each axis, and extracts the surface at iso
+pub fn marching_cubes<F>(field: F, bounds: &Aabb, cells: [u32; 3], iso: f32) -> Primitive
+    where F: Fn(&Point3<f32>) -> f32 {
+    let dimensions = [cells[0].max(1) + 1, cells[1].max(1) + 1, cells[2].max(1) + 1];
+    let size = bounds.size();
+    let spacing = Vector3::new(size.x / (dimensions[0] - 1) as f32,
+                               size.y / (dimensions[1] - 1) as f32,
+                               size.z / (dimensions[2] - 1) as f32);
+
+    let mut values: Vec<f32> = Vec::with_capacity(dimensions.iter().map(|x| *x as usize).product());
+
+    for z in 0..dimensions[2] {
+        for y in 0..dimensions[1] {
+            for x in 0..dimensions[0] {
+                values.push(field(&(bounds.min + spacing.component_mul(&Vector3::new(x as f32, y as f32, z as f32)))));
+            }
+        }
+    }
+
+    extract(&values, dimensions, &bounds.min, &spacing, iso)
+}
+
+// the grid's samples are the lattice, negative distances count as inside when iso is zero
+pub fn marching_cubes_grid(grid: &SdfGrid, iso: f32) -> Primitive {
+    extract(grid.distances(), grid.dimensions(), grid.origin(), &Vector3::repeat(grid.voxel_size()), iso)
+}
+
+// front faces and normals point towards larger values
+fn extract(values: &[f32], dimensions: [u32; 3], origin: &Point3<f32>, spacing: &Vector3<f32>, iso: f32) -> Primitive {
+    let (cube, table) = TABLES.get_or_init(|| {
+        let cube = Cube::new();
+        let table = Table::new(&cube);
+
+        (cube, table)
+    });
+    let [nx, ny, nz] = dimensions;
+
+    let index = |x: u32, y: u32, z: u32| (x + nx * (y + ny * z)) as usize;
+    let lattice = |x: u32, y: u32, z: u32| origin + spacing.component_mul(&Vector3::new(x as f32, y as f32, z as f32));
+
+    // central differences inside, one sided on the border
+    let gradient = |x: u32, y: u32, z: u32| {
+        let point = [x, y, z];
+        let mut gradient = Vector3::zeros();
+
+        for axis in 0..3 {
+            let mut low = point;
+            let mut high = point;
+            low[axis] = point[axis].saturating_sub(1);
+            high[axis] = (point[axis] + 1).min(dimensions[axis] - 1);
+
+            if low[axis] != high[axis] {
+                let difference = values[index(high[0], high[1], high[2])] - values[index(low[0], low[1], low[2])];
+                gradient[axis] = difference / ((high[axis] - low[axis]) as f32 * spacing[axis]);
+            }
+        }
+
+        gradient
+    };
+
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut welded: HashMap<VertexKey, u32> = HashMap::new();
+    let mut indices: Vec<u32> = Vec::new();
+
+    for z in 0..nz.saturating_sub(1) {
+        for y in 0..ny.saturating_sub(1) {
+            for x in 0..nx.saturating_sub(1) {
+                let corner_point = |corner: u8| [x + (corner & 1) as u32, y + ((corner >> 1) & 1) as u32, z + ((corner >> 2) & 1) as u32];
+                let corner_value = |corner: u8| {
+                    let [cx, cy, cz] = corner_point(corner);
+                    values[index(cx, cy, cz)]
+                };
+
+                let mut case = 0usize;
+
+                for corner in 0..8u8 {
+                    if corner_value(corner) >= iso {
+                        case |= 1 << corner;
+                    }
+                }
+
+                if case == 0 || case == 255 {
+                    continue;
+                }
+
+                // asymptotic decider, the high corners connect when the bilinear saddle reaches iso, corners
+                // sorted so both cubes sharing the face round the same way, the first and last are a diagonal
+                let mut decision = 0usize;
+
+                for (face_index, face) in cube.faces.iter().enumerate() {
+                    let mut sorted = *face;
+                    sorted.sort_unstable();
+
+                    let [a, b, d, c] = [corner_value(sorted[0]), corner_value(sorted[1]), corner_value(sorted[2]), corner_value(sorted[3])];
+                    let denominator = a + c - b - d;
+
+                    if denominator != 0.0 && (a * c - b * d) / denominator >= iso {
+                        decision |= 1 << face_index;
+                    }
+                }
+
+                for surface_loop in &table.loops[case * 64 + decision] {
+                    let mut polygon: Vec<u32> = Vec::with_capacity(surface_loop.edges.len());
+
+                    for edge in &surface_loop.edges {
+                        let (lower, axis) = cube.edge(*edge);
+                        let [lx, ly, lz] = corner_point(lower);
+                        let mut upper = [lx, ly, lz];
+                        upper[axis] += 1;
+
+                        let (low, high) = (index(lx, ly, lz), index(upper[0], upper[1], upper[2]));
+                        let t = ((iso - values[low]) / (values[high] - values[low])).clamp(0.0, 1.0);
+
+                        let key = match t {
+                            t if t <= 0.0 => VertexKey::Point(low),
+                            t if t >= 1.0 => VertexKey::Point(high),
+                            _ => VertexKey::Edge(low, axis)
+                        };
+
+                        let vertex = *welded.entry(key).or_insert_with(|| {
+                            let position = lattice(lx, ly, lz) + (lattice(upper[0], upper[1], upper[2]) - lattice(lx, ly, lz)) * t;
+                            let normal = gradient(lx, ly, lz).lerp(&gradient(upper[0], upper[1], upper[2]), t);
+
+                            vertices.push(Vertex::new(position.coords,
+                                                      normal.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros),
+                                                      Vector4::zeros(),
+                                                      Vector2::zeros()));
+                            (vertices.len() - 1) as u32
+                        });
+
+                        polygon.push(vertex);
+                    }
+
+                    triangulate(&polygon, surface_loop.center, &mut vertices, &mut indices);
+                }
+            }
+        }
+    }
+
+    let mut primitive = Primitive::new(vertices, indices, 0, PrimitiveTopology::TriangleList);
+    shapes::generate_tangents(&mut primitive);
+
+    primitive
+}
+
+fn triangulate(polygon: &[u32], center: bool, vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) {
+    let position = |vertices: &Vec<Vertex>, x: u32| *vertices[x as usize].position();
+    let mut push = |vertices: &Vec<Vertex>, triangle: [u32; 3]| {
+        let [a, b, c] = [position(vertices, triangle[0]), position(vertices, triangle[1]), position(vertices, triangle[2])];
+
+        // crossings that landed on a lattice point collapse, their triangles add nothing
+        if a != b && b != c && a != c {
+            indices.extend_from_slice(&triangle);
+        }
+    };
+
+    if !center {
+        for corner in 1..polygon.len().saturating_sub(1) {
+            push(vertices, [polygon[0], polygon[corner], polygon[corner + 1]]);
+        }
+
+        return;
+    }
+
+    let count = polygon.len() as f32;
+    let middle: Vector3<f32> = polygon.iter().map(|x| position(vertices, *x)).sum::<Vector3<f32>>() / count;
+    let normal: Vector3<f32> = polygon.iter().map(|x| *vertices[*x as usize].normal()).sum();
+
+    vertices.push(Vertex::new(middle,
+                              normal.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros),
+                              Vector4::zeros(),
+                              Vector2::zeros()));
+    let middle = (vertices.len() - 1) as u32;
+
+    for corner in 0..polygon.len() {
+        push(vertices, [middle, polygon[corner], polygon[(corner + 1) % polygon.len()]]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    // how many triangles use every directed edge
+    fn directed_edges(primitive: &Primitive) -> HashMap<(u32, u32), usize> {
+        let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+
+        for triangle in primitive.indices().to_vec().chunks_exact(3) {
+            for corner in 0..3 {
+                *edges.entry((triangle[corner], triangle[(corner + 1) % 3])).or_default() += 1;
+            }
+        }
+
+        edges
+    }
+
+    #[test]
+    fn sphere_is_closed_welded_and_facing_out() {
+        let bounds = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let sphere = marching_cubes(|x| x.coords.norm() - 0.77, &bounds, [16, 16, 16], 0.0);
+        let edges = directed_edges(&sphere);
+
+        assert!(!sphere.indices().is_empty());
+
+        // every edge runs once each way, so it is shared by exactly two triangles wound the same way
+        for ((a, b), count) in edges.iter() {
+            assert_eq!(*count, 1);
+            assert_eq!(edges.get(&(*b, *a)), Some(&1));
+        }
+
+        let mut positions: Vec<[u32; 3]> = sphere.vertex().iter().map(|x| [x.position().x.to_bits(), x.position().y.to_bits(), x.position().z.to_bits()]).collect();
+        positions.sort_unstable();
+        positions.dedup();
+        assert_eq!(positions.len(), sphere.vertex().len());
+
+        for triangle in sphere.indices().to_vec().chunks_exact(3) {
+            let corner = |x: usize| *sphere.vertex()[triangle[x] as usize].position();
+            let normal = (corner(1) - corner(0)).cross(&(corner(2) - corner(0)));
+
+            assert!(normal.dot(&(corner(0) + corner(1) + corner(2))) > 0.0);
+        }
+
+        for vertex in sphere.vertex().iter() {
+            assert!((vertex.position().norm() - 0.77).abs() < 0.02);
+            assert!(vertex.normal().dot(&vertex.position().normalize()) > 0.9);
+        }
+    }
+
+    #[test]
+    fn ambiguous_shared_faces_do_not_crack() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let bounds = Aabb::new(Point3::origin(), Point3::new(2.0, 1.0, 1.0));
+        let on_border = |point: &Vector3<f32>| {
+            [point.x, point.y, point.z].iter().zip([2.0, 1.0, 1.0].iter()).map(|(x, max)| x.abs() < 1e-6 || (x - max).abs() < 1e-6).collect::<Vec<bool>>()
+        };
+
+        for _ in 0..500 {
+            // two cells along x, the face between them has its high corners on one diagonal
+            let mut values = [0f32; 12];
+
+            for value in values.iter_mut() {
+                *value = rng.gen_range(-1.0, 1.0);
+            }
+
+            let lattice = |x: usize, y: usize, z: usize| x + 3 * (y + 2 * z);
+            values[lattice(1, 0, 0)] = rng.gen_range(0.1, 1.0);
+            values[lattice(1, 1, 1)] = rng.gen_range(0.1, 1.0);
+            values[lattice(1, 1, 0)] = -rng.gen_range(0.1, 1.0);
+            values[lattice(1, 0, 1)] = -rng.gen_range(0.1, 1.0);
+
+            let field = |point: &Point3<f32>| values[lattice(point.x.round() as usize, point.y.round() as usize, point.z.round() as usize)];
+            let surface = marching_cubes(field, &bounds, [2, 1, 1], 0.0);
+            let edges = directed_edges(&surface);
+
+            // an edge with a single triangle can only lie on the outside of the lattice
+            for ((a, b), count) in edges.iter() {
+                assert_eq!(*count, 1);
+
+                if !edges.contains_key(&(*b, *a)) {
+                    let a = on_border(surface.vertex()[*a as usize].position());
+                    let b = on_border(surface.vertex()[*b as usize].position());
+
+                    assert!(a.iter().zip(b.iter()).any(|(a, b)| *a && *b), "crack inside the lattice in {:?}", values);
+                }
+            }
+        }
+    }
+}
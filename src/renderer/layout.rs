@@ -0,0 +1,435 @@
+use half::f16;
+use wgpu::{InputStepMode, VertexAttributeDescriptor, VertexBufferDescriptor, VertexFormat};
+use crate::renderer::Primitive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Semantic {
+    Position,
+    Normal,
+    Tangent,
+    TexCoord0,
+    TexCoord1,
+    Color,
+    Joints,
+    Weights
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VertexAttribute {
+    pub semantic: Semantic,
+    pub format: VertexFormat
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packing {
+    // every attribute in one buffer
+    Interleaved,
+    // one buffer per attribute
+    Separate
+}
+
+#[derive(Debug, Clone)]
+pub struct VertexLayout {
+    packing: Packing,
+    attributes: Vec<VertexAttribute>,
+    // per buffer, the index into attributes and the byte offset inside a vertex
+    streams: Vec<Vec<(usize, u64)>>,
+    strides: Vec<u64>,
+    descriptors: Vec<Vec<VertexAttributeDescriptor>>
+}
+
+impl Semantic {
+    pub const ALL: [Semantic; 8] = [Semantic::Position,
+                                    Semantic::Normal,
+                                    Semantic::Tangent,
+                                    Semantic::TexCoord0,
+                                    Semantic::TexCoord1,
+                                    Semantic::Color,
+                                    Semantic::Joints,
+                                    Semantic::Weights];
+
+    // locations 4 to 7 are taken by the instance transform
+    pub fn location(self) -> u32 {
+        match self {
+            Semantic::Position => 0,
+            Semantic::Normal => 1,
+            Semantic::Tangent => 2,
+            Semantic::TexCoord0 => 3,
+            Semantic::TexCoord1 => 8,
+            Semantic::Color => 9,
+            Semantic::Joints => 10,
+            Semantic::Weights => 11
+        }
+    }
+
+    // stored in Vertex, every primitive has them
+    pub fn is_core(self) -> bool {
+        matches!(self, Semantic::Position | Semantic::Normal | Semantic::Tangent | Semantic::TexCoord0)
+    }
+
+    // what gets uploaded for a primitive missing the attribute
+    pub fn default_value(self) -> [f32; 4] {
+        match self {
+            Semantic::Color => [1.0, 1.0, 1.0, 1.0],
+            Semantic::Weights => [1.0, 0.0, 0.0, 0.0],
+            _ => [0.0, 0.0, 0.0, 0.0]
+        }
+    }
+}
+
+impl VertexAttribute {
+    pub fn new(semantic: Semantic, format: VertexFormat) -> Self {
+        Self {
+            semantic,
+            format
+        }
+    }
+}
+
+impl VertexLayout {
+    pub fn new(attributes: Vec<VertexAttribute>, packing: Packing) -> Self {
+        for (i, attribute) in attributes.iter().enumerate() {
+            assert!(attributes[..i].iter().all(|x| x.semantic != attribute.semantic),
+                    "{:?} appears twice in the vertex layout", attribute.semantic);
+        }
+
+        let groups: Vec<Vec<usize>> = match packing {
+            Packing::Interleaved => vec![(0..attributes.len()).collect()],
+            Packing::Separate => (0..attributes.len()).map(|x| vec![x]).collect()
+        };
+
+        let mut streams = Vec::with_capacity(groups.len());
+        let mut strides = Vec::with_capacity(groups.len());
+        let mut descriptors = Vec::with_capacity(groups.len());
+
+        for group in groups {
+            let mut offset = 0u64;
+            let mut stream = Vec::with_capacity(group.len());
+            let mut descriptor = Vec::with_capacity(group.len());
+
+            for index in group {
+                let attribute = attributes[index];
+                stream.push((index, offset));
+                descriptor.push(VertexAttributeDescriptor {
+                    format: attribute.format,
+                    shader_location: attribute.semantic.location(),
+                    offset
+                });
+
+                offset = align(offset + format_size(attribute.format));
+            }
+
+            streams.push(stream);
+            strides.push(offset);
+            descriptors.push(descriptor);
+        }
+
+        Self {
+            packing,
+            attributes,
+            streams,
+            strides,
+            descriptors
+        }
+    }
+
+    // Vertex byte for byte, Vertex::get_state_descriptor is built from it
+    pub fn standard() -> Self {
+        Self::new(vec![VertexAttribute::new(Semantic::Position, VertexFormat::Float3),
+                       VertexAttribute::new(Semantic::Normal, VertexFormat::Float3),
+                       VertexAttribute::new(Semantic::Tangent, VertexFormat::Float4),
+                       VertexAttribute::new(Semantic::TexCoord0, VertexFormat::Float2)],
+                  Packing::Interleaved)
+    }
+
+    pub fn packing(&self) -> Packing {
+        self.packing
+    }
+
+    pub fn attributes(&self) -> &Vec<VertexAttribute> {
+        &self.attributes
+    }
+
+    pub fn stream_count(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn stride(&self, stream: usize) -> u64 {
+        self.strides[stream]
+    }
+
+    // bound in order from slot 0, instance buffers go after stream_count()
+    pub fn buffer_descriptors(&self) -> Vec<VertexBufferDescriptor<'_>> {
+        self.descriptors
+            .iter()
+            .zip(self.strides.iter())
+            .map(|(attributes, stride)| VertexBufferDescriptor {
+                stride: *stride,
+                step_mode: InputStepMode::Vertex,
+                attributes
+            })
+            .collect()
+    }
+
+    // one byte buffer per stream, attributes the primitive lacks get their semantic's default
+    pub fn pack(&self, primitive: &Primitive) -> Vec<Vec<u8>> {
+        self.pack_values(primitive.vertex().len(), |semantic, vertex| primitive.attribute(semantic, vertex))
+    }
+
+    // like pack, with every value coming from the closure, for encodings that transform the attributes first
+    pub fn pack_values<F>(&self, vertex_count: usize, value: F) -> Vec<Vec<u8>>
+        where F: Fn(Semantic, usize) -> [f32; 4] {
+        self.streams
+            .iter()
+            .zip(self.strides.iter())
+            .map(|(stream, stride)| {
+                let mut data: Vec<u8> = Vec::with_capacity(vertex_count * *stride as usize);
+
+                for vertex in 0..vertex_count {
+                    let start = data.len();
+
+                    for (index, offset) in stream {
+                        let attribute = self.attributes[*index];
+                        data.resize(start + *offset as usize, 0);
+                        encode(attribute.format, &value(attribute.semantic, vertex), &mut data);
+                    }
+
+                    data.resize(start + *stride as usize, 0);
+                }
+
+                data
+            })
+            .collect()
+    }
+
+    // reads one attribute of one vertex back out of packed streams, None when the layout lacks the semantic
+    pub fn read(&self, streams: &[Vec<u8>], semantic: Semantic, vertex: usize) -> Option<[f32; 4]> {
+        self.streams.iter().enumerate().find_map(|(stream, attributes)| {
+            attributes
+                .iter()
+                .find(|(index, _)| self.attributes[*index].semantic == semantic)
+                .map(|(index, offset)| {
+                    let start = vertex * self.strides[stream] as usize + *offset as usize;
+                    decode(self.attributes[*index].format, &streams[stream][start..])
+                })
+        })
+    }
+}
+
+fn align(offset: u64) -> u64 {
+    (offset + 3) & !3
+}
+
+pub fn format_components(format: VertexFormat) -> usize {
+    match format {
+        VertexFormat::Float | VertexFormat::Uint | VertexFormat::Int => 1,
+        VertexFormat::Uchar2 | VertexFormat::Char2 | VertexFormat::Uchar2Norm | VertexFormat::Char2Norm |
+        VertexFormat::Ushort2 | VertexFormat::Short2 | VertexFormat::Ushort2Norm | VertexFormat::Short2Norm |
+        VertexFormat::Half2 | VertexFormat::Float2 | VertexFormat::Uint2 | VertexFormat::Int2 => 2,
+        VertexFormat::Float3 | VertexFormat::Uint3 | VertexFormat::Int3 => 3,
+        _ => 4
+    }
+}
+
+pub fn format_size(format: VertexFormat) -> u64 {
+    let component_size = match format {
+        VertexFormat::Uchar2 | VertexFormat::Uchar4 | VertexFormat::Char2 | VertexFormat::Char4 |
+        VertexFormat::Uchar2Norm | VertexFormat::Uchar4Norm | VertexFormat::Char2Norm | VertexFormat::Char4Norm => 1,
+        VertexFormat::Ushort2 | VertexFormat::Ushort4 | VertexFormat::Short2 | VertexFormat::Short4 |
+        VertexFormat::Ushort2Norm | VertexFormat::Ushort4Norm | VertexFormat::Short2Norm | VertexFormat::Short4Norm |
+        VertexFormat::Half2 | VertexFormat::Half4 => 2,
+        _ => 4
+    };
+
+    component_size * format_components(format) as u64
+}
+
+// normalized formats clamp to their range, integer formats round and saturate
+pub fn encode(format: VertexFormat, value: &[f32; 4], out: &mut Vec<u8>) {
+    let value = &value[..format_components(format)];
+
+    for x in value {
+        match format {
+            VertexFormat::Uchar2 | VertexFormat::Uchar4 => out.push(x.round() as u8),
+            VertexFormat::Char2 | VertexFormat::Char4 => out.push(x.round() as i8 as u8),
+            VertexFormat::Uchar2Norm | VertexFormat::Uchar4Norm => out.push((x.clamp(0.0, 1.0) * 255.0).round() as u8),
+            VertexFormat::Char2Norm | VertexFormat::Char4Norm => out.push((x.clamp(-1.0, 1.0) * 127.0).round() as i8 as u8),
+            VertexFormat::Ushort2 | VertexFormat::Ushort4 => out.extend_from_slice(&(x.round() as u16).to_le_bytes()),
+            VertexFormat::Short2 | VertexFormat::Short4 => out.extend_from_slice(&(x.round() as i16).to_le_bytes()),
+            VertexFormat::Ushort2Norm | VertexFormat::Ushort4Norm =>
+                out.extend_from_slice(&((x.clamp(0.0, 1.0) * 65535.0).round() as u16).to_le_bytes()),
+            VertexFormat::Short2Norm | VertexFormat::Short4Norm =>
+                out.extend_from_slice(&((x.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes()),
+            VertexFormat::Half2 | VertexFormat::Half4 => out.extend_from_slice(&f16::from_f32(*x).to_bits().to_le_bytes()),
+            VertexFormat::Uint | VertexFormat::Uint2 | VertexFormat::Uint3 | VertexFormat::Uint4 =>
+                out.extend_from_slice(&(x.round() as u32).to_le_bytes()),
+            VertexFormat::Int | VertexFormat::Int2 | VertexFormat::Int3 | VertexFormat::Int4 =>
+                out.extend_from_slice(&(x.round() as i32).to_le_bytes()),
+            VertexFormat::Float | VertexFormat::Float2 | VertexFormat::Float3 | VertexFormat::Float4 =>
+                out.extend_from_slice(&x.to_le_bytes())
+        }
+    }
+}
+
+// what the vertex fetch makes of the bits encode wrote, missing components are zero
+pub fn decode(format: VertexFormat, bytes: &[u8]) -> [f32; 4] {
+    let mut value = [0.0; 4];
+    let size = (format_size(format) / format_components(format) as u64) as usize;
+
+    for (component, x) in value.iter_mut().take(format_components(format)).enumerate() {
+        let bytes = &bytes[component * size..(component + 1) * size];
+        let two = || [bytes[0], bytes[1]];
+        let four = || [bytes[0], bytes[1], bytes[2], bytes[3]];
+
+        *x = match format {
+            VertexFormat::Uchar2 | VertexFormat::Uchar4 => bytes[0] as f32,
+            VertexFormat::Char2 | VertexFormat::Char4 => bytes[0] as i8 as f32,
+            VertexFormat::Uchar2Norm | VertexFormat::Uchar4Norm => bytes[0] as f32 / 255.0,
+            VertexFormat::Char2Norm | VertexFormat::Char4Norm => (bytes[0] as i8 as f32 / 127.0).max(-1.0),
+            VertexFormat::Ushort2 | VertexFormat::Ushort4 => u16::from_le_bytes(two()) as f32,
+            VertexFormat::Short2 | VertexFormat::Short4 => i16::from_le_bytes(two()) as f32,
+            VertexFormat::Ushort2Norm | VertexFormat::Ushort4Norm => u16::from_le_bytes(two()) as f32 / 65535.0,
+            VertexFormat::Short2Norm | VertexFormat::Short4Norm => (i16::from_le_bytes(two()) as f32 / 32767.0).max(-1.0),
+            VertexFormat::Half2 | VertexFormat::Half4 => f16::from_bits(u16::from_le_bytes(two())).to_f32(),
+            VertexFormat::Uint | VertexFormat::Uint2 | VertexFormat::Uint3 | VertexFormat::Uint4 => u32::from_le_bytes(four()) as f32,
+            VertexFormat::Int | VertexFormat::Int2 | VertexFormat::Int3 | VertexFormat::Int4 => i32::from_le_bytes(four()) as f32,
+            VertexFormat::Float | VertexFormat::Float2 | VertexFormat::Float3 | VertexFormat::Float4 => f32::from_le_bytes(four())
+        };
+    }
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{shapes, vertex::Vertex};
+
+    // a format that fits each semantic's range, so the round trip only loses precision
+    fn format_for(semantic: Semantic) -> VertexFormat {
+        match semantic {
+            Semantic::Position => VertexFormat::Float3,
+            Semantic::Normal => VertexFormat::Char4Norm,
+            Semantic::Tangent => VertexFormat::Short4Norm,
+            Semantic::TexCoord0 => VertexFormat::Half2,
+            Semantic::TexCoord1 => VertexFormat::Ushort2Norm,
+            Semantic::Color => VertexFormat::Uchar4Norm,
+            Semantic::Joints => VertexFormat::Ushort4,
+            Semantic::Weights => VertexFormat::Uchar4Norm
+        }
+    }
+
+    fn tolerance(format: VertexFormat) -> f32 {
+        match format {
+            VertexFormat::Char4Norm => 1.0 / 127.0,
+            VertexFormat::Uchar4Norm => 1.0 / 255.0,
+            VertexFormat::Half2 => 1e-3,
+            VertexFormat::Short4Norm => 1.0 / 32767.0,
+            VertexFormat::Ushort2Norm => 1.0 / 65535.0,
+            _ => 0.0
+        }
+    }
+
+    // every semantic set to something other than its default
+    fn test_primitive() -> Primitive {
+        let mut primitive = shapes::uv_sphere(1.0, 8, 6).primitives.remove(0);
+        let count = primitive.vertex().len();
+
+        primitive.set_attribute(Semantic::TexCoord1, (0..count).map(|x| [x as f32 / count as f32, 0.25, 0.0, 0.0]).collect());
+        primitive.set_attribute(Semantic::Color, (0..count).map(|x| [0.5, x as f32 / count as f32, 0.0, 1.0]).collect());
+        primitive.set_attribute(Semantic::Joints, (0..count).map(|x| [x as f32, 1.0, 2.0, 300.0]).collect());
+        primitive.set_attribute(Semantic::Weights, vec![[0.5, 0.25, 0.25, 0.0]; count]);
+
+        primitive
+    }
+
+    #[test]
+    fn every_semantic_round_trips() {
+        let primitive = test_primitive();
+        let attributes: Vec<VertexAttribute> = Semantic::ALL.iter().map(|x| VertexAttribute::new(*x, format_for(*x))).collect();
+
+        for packing in [Packing::Interleaved, Packing::Separate] {
+            let layout = VertexLayout::new(attributes.clone(), packing);
+            let streams = layout.pack(&primitive);
+
+            for semantic in Semantic::ALL.iter() {
+                let format = format_for(*semantic);
+
+                for vertex in 0..primitive.vertex().len() {
+                    let expected = primitive.attribute(*semantic, vertex);
+                    let read = layout.read(&streams, *semantic, vertex).unwrap();
+
+                    for component in 0..format_components(format) {
+                        assert!((read[component] - expected[component]).abs() <= tolerance(format),
+                                "{:?} {:?} {:?} != {:?}", packing, semantic, read, expected);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn missing_semantics_read_none_and_pack_their_default() {
+        let primitive = shapes::cube(1.0, 1).primitives.remove(0);
+        let layout = VertexLayout::new(vec![VertexAttribute::new(Semantic::Color, VertexFormat::Uchar4Norm)], Packing::Interleaved);
+        let streams = layout.pack(&primitive);
+
+        assert_eq!(layout.read(&streams, Semantic::Position, 0), None);
+        assert_eq!(layout.read(&streams, Semantic::Color, 3), Some(Semantic::Color.default_value()));
+    }
+
+    #[test]
+    fn offsets_and_strides_are_four_byte_aligned() {
+        let attributes = vec![VertexAttribute::new(Semantic::Position, VertexFormat::Float3),
+                              VertexAttribute::new(Semantic::TexCoord0, VertexFormat::Char2Norm),
+                              VertexAttribute::new(Semantic::Color, VertexFormat::Uchar4Norm),
+                              VertexAttribute::new(Semantic::Weights, VertexFormat::Half4)];
+
+        let interleaved = VertexLayout::new(attributes.clone(), Packing::Interleaved);
+        let offsets: Vec<u64> = interleaved.buffer_descriptors()[0].attributes.iter().map(|x| x.offset).collect();
+
+        assert_eq!(interleaved.stream_count(), 1);
+        assert_eq!(offsets, [0, 12, 16, 20]);
+        assert_eq!(interleaved.stride(0), 28);
+
+        let separate = VertexLayout::new(attributes, Packing::Separate);
+        let strides: Vec<u64> = (0..separate.stream_count()).map(|x| separate.stride(x)).collect();
+
+        assert_eq!(strides, [12, 4, 4, 8]);
+        assert!(separate.buffer_descriptors().iter().all(|x| x.attributes.len() == 1 && x.attributes[0].offset == 0));
+    }
+
+    #[test]
+    fn shader_locations_follow_the_semantics() {
+        let attributes: Vec<VertexAttribute> = Semantic::ALL.iter().map(|x| VertexAttribute::new(*x, format_for(*x))).collect();
+        let layout = VertexLayout::new(attributes, Packing::Interleaved);
+        let locations: Vec<u32> = layout.buffer_descriptors()[0].attributes.iter().map(|x| x.shader_location).collect();
+
+        assert_eq!(locations, Semantic::ALL.iter().map(|x| x.location()).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn standard_layout_matches_vertex() {
+        let primitive = shapes::cube(1.0, 1).primitives.remove(0);
+        let layout = VertexLayout::standard();
+
+        assert_eq!(layout.stride(0), std::mem::size_of::<Vertex>() as u64);
+        assert_eq!(layout.pack(&primitive)[0], bytemuck::cast_slice::<Vertex, u8>(primitive.vertex()));
+
+        let state = Vertex::get_state_descriptor(wgpu::IndexFormat::Uint16);
+        assert_eq!(state.vertex_buffers.len(), 1);
+        assert_eq!(state.vertex_buffers[0].stride, layout.stride(0));
+        assert_eq!(state.vertex_buffers[0].attributes, layout.buffer_descriptors()[0].attributes);
+    }
+
+    #[test]
+    fn integer_formats_round_and_saturate() {
+        let mut bytes: Vec<u8> = Vec::new();
+        encode(VertexFormat::Uchar4, &[1.4, 1.6, 300.0, -5.0], &mut bytes);
+        assert_eq!(bytes, [1, 2, 255, 0]);
+
+        let mut bytes: Vec<u8> = Vec::new();
+        encode(VertexFormat::Char2Norm, &[2.0, -2.0, 0.0, 0.0], &mut bytes);
+        assert_eq!(decode(VertexFormat::Char2Norm, &bytes), [1.0, -1.0, 0.0, 0.0]);
+    }
+}
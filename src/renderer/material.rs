@@ -1,16 +1,17 @@
 use nalgebra::Vector4;
 
+// textures left as None are replaced by the built in defaults of their slot when loaded
 pub struct Material {
-    texture: String,
-    normal: String,
-    roughness: String,
+    texture: Option<String>,
+    normal: Option<String>,
+    roughness: Option<String>,
     color: Vector4<f32>
 }
 
 impl Material {
-    pub fn new(texture: String,
-               normal: String,
-               roughness: String,
+    pub fn new(texture: Option<String>,
+               normal: Option<String>,
+               roughness: Option<String>,
                color: Vector4<f32>) -> Self {
         Self {
             texture,
@@ -19,4 +20,20 @@ impl Material {
             color
         }
     }
+
+    pub fn texture(&self) -> Option<&str> {
+        self.texture.as_deref()
+    }
+
+    pub fn normal(&self) -> Option<&str> {
+        self.normal.as_deref()
+    }
+
+    pub fn roughness(&self) -> Option<&str> {
+        self.roughness.as_deref()
+    }
+
+    pub fn color(&self) -> &Vector4<f32> {
+        &self.color
+    }
 }
\ No newline at end of file
@@ -1,18 +1,50 @@
-use std::fmt::Debug;
+use std::{cell::Cell, collections::HashMap, fmt::Debug};
 use derive_more::Display;
-use crate::renderer::vertex::Vertex;
+use nalgebra::{Matrix4, Point3, Vector2, Vector3, Vector4};
+use crate::renderer::{bounds::Bounds, indices::Indices, layout::{Semantic, VertexLayout}, vertex::Vertex};
 use wgpu::{Buffer, Device, BufferUsage};
 
 pub mod vertex;
+pub mod indices;
+pub mod layout;
+pub mod compress;
+pub mod shapes;
+pub mod batch;
+pub mod halfedge;
+pub mod subdivide;
+pub mod edges;
+pub mod smoothing;
+pub mod slice;
+pub mod bvh;
+pub mod sdf;
+pub mod isosurface;
+pub mod voxel;
+pub mod terrain;
+pub mod noise;
+pub mod scatter;
+pub mod cache;
 pub mod camera;
 pub mod gltfimporter;
 pub mod material;
+pub mod instance;
+pub mod node;
+pub mod texture;
+pub mod bounds;
+pub mod optimize;
+pub mod simplify;
+pub mod hdr;
+pub mod cubemap;
 
+// vertex data is only reachable through accessors so mutations can drop the cached bounds
 pub struct Primitive {
-    pub vertex: Vec<Vertex>,
-    pub indices: Vec<u32>,
+    vertex: Vec<Vertex>,
+    indices: Indices,
+    // semantics Vertex has no room for, one value per vertex, position, normal, tangent and the first uv
+    // set always live in Vertex itself and the layout only decides how they are uploaded
+    attributes: HashMap<Semantic, Vec<[f32; 4]>>,
     pub material_index: usize,
-    pub mode: wgpu::PrimitiveTopology
+    pub mode: wgpu::PrimitiveTopology,
+    bounds: Cell<Option<Bounds>>
 }
 
 pub trait IntoWgpuEquivalent {
@@ -22,34 +54,189 @@ pub trait IntoWgpuEquivalent {
 }
 
 pub struct Mesh {
-    pub primitives: Vec<Primitive>
+    pub primitives: Vec<Primitive>,
+    // lower detail versions, from the closest to the farthest
+    pub lods: Vec<Lod>
+}
+
+pub struct Lod {
+    pub primitives: Vec<Primitive>,
+    // square root of the largest quadric cost of any collapse, the summed squared distances to the planes
+    // merged into a vertex, in model units, a guide for picking levels rather than a bound on the distance
+    pub error: f32
 }
 
 impl Primitive {
+    pub fn new(vertex: Vec<Vertex>, indices: Vec<u32>, material_index: usize, mode: wgpu::PrimitiveTopology) -> Self {
+        let indices = Indices::new(indices, vertex.len());
+
+        Self {
+            vertex,
+            indices,
+            attributes: HashMap::new(),
+            material_index,
+            mode,
+            bounds: Cell::new(None)
+        }
+    }
+
+    pub fn vertex(&self) -> &Vec<Vertex> {
+        &self.vertex
+    }
+
+    // a slice so the vertex count can only change through push_vertex, which keeps the attributes in step
+    pub fn vertex_mut(&mut self) -> &mut [Vertex] {
+        self.bounds.set(None);
+        &mut self.vertex
+    }
+
+    // extra attributes of the new vertex start at their semantic's default
+    pub fn push_vertex(&mut self, vertex: Vertex) {
+        self.bounds.set(None);
+        self.vertex.push(vertex);
+
+        for (semantic, values) in self.attributes.iter_mut() {
+            values.push(semantic.default_value());
+        }
+    }
+
+    pub fn indices(&self) -> &Indices {
+        &self.indices
+    }
+
+    // stored as u16 when the current vertex count allows it
+    pub fn set_indices(&mut self, indices: Vec<u32>) {
+        self.indices = Indices::new(indices, self.vertex.len());
+    }
+
+    pub fn index_format(&self) -> wgpu::IndexFormat {
+        self.indices.format()
+    }
+
+    pub fn has_attribute(&self, semantic: Semantic) -> bool {
+        semantic.is_core() || self.attributes.contains_key(&semantic)
+    }
+
+    // the semantics stored beside Vertex
+    pub fn extra_semantics(&self) -> Vec<Semantic> {
+        Semantic::ALL.iter().copied().filter(|x| self.attributes.contains_key(x)).collect()
+    }
+
+    // missing attributes read as the semantic's default, positions have w = 1
+    pub fn attribute(&self, semantic: Semantic, index: usize) -> [f32; 4] {
+        let vertex = &self.vertex[index];
+
+        match semantic {
+            Semantic::Position => [vertex.position().x, vertex.position().y, vertex.position().z, 1.0],
+            Semantic::Normal => [vertex.normal().x, vertex.normal().y, vertex.normal().z, 0.0],
+            Semantic::Tangent => [vertex.tangent().x, vertex.tangent().y, vertex.tangent().z, vertex.tangent().w],
+            Semantic::TexCoord0 => [vertex.uv().x, vertex.uv().y, 0.0, 0.0],
+            _ => self.attributes.get(&semantic).map_or(semantic.default_value(), |x| x[index])
+        }
+    }
+
+    pub fn set_attribute(&mut self, semantic: Semantic, values: Vec<[f32; 4]>) {
+        assert_eq!(values.len(), self.vertex.len());
+
+        for (vertex, value) in self.vertex_mut().iter_mut().zip(values.iter()) {
+            match semantic {
+                Semantic::Position => vertex.set_position(Vector3::new(value[0], value[1], value[2])),
+                Semantic::Normal => vertex.set_normal(Vector3::new(value[0], value[1], value[2])),
+                Semantic::Tangent => vertex.set_tangent(Vector4::from(*value)),
+                Semantic::TexCoord0 => vertex.set_uv(Vector2::new(value[0], value[1])),
+                _ => break
+            }
+        }
+
+        if !semantic.is_core() {
+            self.attributes.insert(semantic, values);
+        }
+    }
+
+    pub fn remove_attribute(&mut self, semantic: Semantic) {
+        self.attributes.remove(&semantic);
+    }
+
+    // a primitive with the same material and mode made of the given vertices, extra attributes included
+    pub fn select_vertices(&self, sources: &[u32], indices: Vec<u32>) -> Primitive {
+        let vertex = sources.iter().map(|x| self.vertex[*x as usize]).collect();
+        let mut primitive = Primitive::new(vertex, indices, self.material_index, self.mode);
+
+        for (semantic, values) in self.attributes.iter() {
+            primitive.attributes.insert(*semantic, sources.iter().map(|x| values[*x as usize]).collect());
+        }
+
+        primitive
+    }
+
+    // covers every vertex, referenced by the indices or not
+    pub fn bounds(&self) -> Bounds {
+        if let Some(bounds) = self.bounds.get() {
+            return bounds;
+        }
+
+        let points: Vec<Point3<f32>> = self.vertex.iter().map(|x| Point3::from(*x.position())).collect();
+        let bounds = Bounds::from_points(&points);
+        self.bounds.set(Some(bounds));
+
+        bounds
+    }
+
+    pub fn transformed_bounds(&self, model: &Matrix4<f32>) -> Bounds {
+        self.bounds().transform(model)
+    }
+
     pub fn get_vertex_buffer(&self, device: &Device) -> Buffer {
         device.create_buffer_with_data(bytemuck::cast_slice(&self.vertex), BufferUsage::VERTEX)
     }
 
+    // one buffer per stream of the layout, bound in the same order
+    pub fn get_vertex_buffers(&self, device: &Device, layout: &VertexLayout) -> Vec<Buffer> {
+        layout.pack(self)
+            .iter()
+            .map(|x| device.create_buffer_with_data(x, BufferUsage::VERTEX))
+            .collect()
+    }
+
     pub fn get_index_buffer(&self, device: &Device) -> Buffer {
-        device.create_buffer_with_data(bytemuck::cast_slice(&self.indices), BufferUsage::INDEX)
+        self.indices.get_buffer(device)
     }
 }
 
 impl Default for Primitive {
     fn default() -> Self {
-        Self {
-            vertex: Vec::new(),
-            indices: Vec::new(),
-            material_index: 0,
-            mode: wgpu::PrimitiveTopology::TriangleList
-        }
+        Self::new(Vec::new(), Vec::new(), 0, wgpu::PrimitiveTopology::TriangleList)
     }
 }
 
 impl Mesh {
     pub fn new(primitives: Vec<Primitive>) -> Self {
         Self {
-            primitives
+            primitives,
+            lods: Vec::new()
+        }
+    }
+
+    // merges the cached bounds of every primitive on each call, O(primitives), not cached here because
+    // primitives is public and can change under the mesh
+    pub fn bounds(&self) -> Bounds {
+        self.primitives
+            .iter()
+            .fold(Bounds::empty(), |bounds, primitive| bounds.merge(&primitive.bounds()))
+    }
+
+    pub fn transformed_bounds(&self, model: &Matrix4<f32>) -> Bounds {
+        self.primitives
+            .iter()
+            .fold(Bounds::empty(), |bounds, primitive| bounds.merge(&primitive.transformed_bounds(model)))
+    }
+}
+
+impl Lod {
+    pub fn new(primitives: Vec<Primitive>, error: f32) -> Self {
+        Self {
+            primitives,
+            error
         }
     }
 }
@@ -57,11 +244,37 @@ impl Mesh {
 #[derive(Debug, Display)]
 pub enum RenderError {
     #[display(fmt = "Import problem: {}", _0)]
-    Import(String)
+    Import(String),
+    #[display(fmt = "IO problem: {}", _0)]
+    Io(String),
+    #[display(fmt = "Texture problem: {}", _0)]
+    Texture(String),
+    #[display(fmt = "Geometry problem: {}", _0)]
+    Geometry(String),
+    #[display(fmt = "Cache problem: {}", _0)]
+    Cache(String)
 }
 
 impl From<gltf::Error> for RenderError {
     fn from(err: gltf::Error) -> Self {
         RenderError::Import(err.to_string())
     }
+}
+
+impl From<serde_json::Error> for RenderError {
+    fn from(err: serde_json::Error) -> Self {
+        RenderError::Import(err.to_string())
+    }
+}
+
+impl From<image::ImageError> for RenderError {
+    fn from(err: image::ImageError) -> Self {
+        RenderError::Texture(err.to_string())
+    }
+}
+
+impl From<std::io::Error> for RenderError {
+    fn from(err: std::io::Error) -> Self {
+        RenderError::Io(err.to_string())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,84 @@
+use nalgebra::Matrix4;
+use crate::renderer::instance::{Instance, InstanceRaw};
+
+pub struct Node {
+    pub mesh_index: Option<usize>,
+    // world transform, parents already applied
+    pub transform: Matrix4<f32>,
+    // filled from EXT_mesh_gpu_instancing, relative to the node transform
+    pub instances: Vec<Instance>
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self {
+            mesh_index: None,
+            transform: Matrix4::identity(),
+            instances: Vec::new()
+        }
+    }
+}
+
+impl Node {
+    pub fn new(mesh_index: Option<usize>, transform: Matrix4<f32>, instances: Vec<Instance>) -> Self {
+        Self {
+            mesh_index,
+            transform,
+            instances
+        }
+    }
+
+    pub fn is_instanced(&self) -> bool {
+        !self.instances.is_empty()
+    }
+
+    // a node without instances is still drawn once, with its own transform
+    pub fn instance_data(&self) -> Vec<InstanceRaw> {
+        if !self.is_instanced() {
+            return vec![InstanceRaw::new(self.transform)];
+        }
+
+        self.instances
+            .iter()
+            .map(|instance| InstanceRaw::new(self.transform * instance.to_matrix()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{UnitQuaternion, Vector3};
+    use crate::renderer::instance::Instance;
+
+    fn matrices(data: &[InstanceRaw]) -> Vec<Matrix4<f32>> {
+        bytemuck::cast_slice::<InstanceRaw, f32>(data)
+            .chunks_exact(16)
+            .map(Matrix4::from_column_slice)
+            .collect()
+    }
+
+    #[test]
+    fn plain_node_draws_once() {
+        let transform = Matrix4::new_translation(&Vector3::new(1.0, 2.0, 3.0));
+        let node = Node::new(Some(0), transform, Vec::new());
+
+        assert!(!node.is_instanced());
+        assert_eq!(matrices(&node.instance_data()), vec![transform]);
+    }
+
+    #[test]
+    fn instances_are_relative_to_the_node() {
+        let transform = Matrix4::new_scaling(2.0);
+        let instances = vec![Instance::new(Vector3::x(), UnitQuaternion::identity(), Vector3::repeat(1.0)),
+                             Instance::new(Vector3::y(), UnitQuaternion::identity(), Vector3::repeat(3.0))];
+        let node = Node::new(Some(0), transform, instances.clone());
+
+        let expected: Vec<Matrix4<f32>> = instances.iter().map(|x| transform * x.to_matrix()).collect();
+
+        assert!(node.is_instanced());
+        assert_eq!(matrices(&node.instance_data()), expected);
+        // the node transform applies after the instance one
+        assert_eq!(expected[0].transform_point(&nalgebra::Point3::origin()), nalgebra::Point3::new(2.0, 0.0, 0.0));
+    }
+}
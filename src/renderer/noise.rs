@@ -0,0 +1,444 @@
+use std::{collections::HashMap, ops::{Add, Mul}};
+use image::{Rgba, RgbaImage};
+use nalgebra::{Vector2, Vector3, Vector4};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{shapes, Primitive};
+
+// edges of a cube, the first two components double as the 2d set
+const GRADIENTS_3: [[f32; 3]; 12] = [
+    [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0], [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0]
+];
+
+// edges of a tesseract
+const GRADIENTS_4: [[f32; 4]; 32] = [
+    [0.0, 1.0, 1.0, 1.0], [0.0, 1.0, 1.0, -1.0], [0.0, 1.0, -1.0, 1.0], [0.0, 1.0, -1.0, -1.0],
+    [0.0, -1.0, 1.0, 1.0], [0.0, -1.0, 1.0, -1.0], [0.0, -1.0, -1.0, 1.0], [0.0, -1.0, -1.0, -1.0],
+    [1.0, 0.0, 1.0, 1.0], [1.0, 0.0, 1.0, -1.0], [1.0, 0.0, -1.0, 1.0], [1.0, 0.0, -1.0, -1.0],
+    [-1.0, 0.0, 1.0, 1.0], [-1.0, 0.0, 1.0, -1.0], [-1.0, 0.0, -1.0, 1.0], [-1.0, 0.0, -1.0, -1.0],
+    [1.0, 1.0, 0.0, 1.0], [1.0, 1.0, 0.0, -1.0], [1.0, -1.0, 0.0, 1.0], [1.0, -1.0, 0.0, -1.0],
+    [-1.0, 1.0, 0.0, 1.0], [-1.0, 1.0, 0.0, -1.0], [-1.0, -1.0, 0.0, 1.0], [-1.0, -1.0, 0.0, -1.0],
+    [1.0, 1.0, 1.0, 0.0], [1.0, 1.0, -1.0, 0.0], [1.0, -1.0, 1.0, 0.0], [1.0, -1.0, -1.0, 0.0],
+    [-1.0, 1.0, 1.0, 0.0], [-1.0, 1.0, -1.0, 0.0], [-1.0, -1.0, 1.0, 0.0], [-1.0, -1.0, -1.0, 0.0]
+];
+
+// brings the largest values of every kind close to one
+const GRADIENT_SCALES: [f32; 3] = [1.0, 1.0, 0.85];
+const SIMPLEX_SCALES: [f32; 3] = [70.0, 32.0, 27.0];
+
+// seeded permutation shared by every kind of noise, all of them return roughly -1 to 1 and zero on lattice points
+#[derive(Debug, Clone, PartialEq)]
+pub struct Noise {
+    permutation: Vec<u8>
+}
+
+// octaves are summed with the frequency multiplied by lacunarity and the amplitude by gain every time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fractal {
+    pub octaves: u32,
+    pub frequency: f32,
+    pub lacunarity: f32,
+    pub gain: f32
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Default for Fractal {
+    fn default() -> Self {
+        Self {
+            octaves: 5,
+            frequency: 1.0,
+            lacunarity: 2.0,
+            gain: 0.5
+        }
+    }
+}
+
+impl Noise {
+    pub fn new(seed: u32) -> Self {
+        let mut permutation: Vec<u8> = (0..=255).collect();
+
+        // xorshift, zero would stay zero forever
+        let mut state = seed ^ 0x9e37_79b9;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+
+        for index in (1..permutation.len()).rev() {
+            let other = next() as usize % (index + 1);
+            permutation.swap(index, other);
+        }
+
+        // doubled so chained lookups never wrap
+        let doubled = permutation.clone();
+        permutation.extend(doubled);
+
+        Self {
+            permutation
+        }
+    }
+
+    pub fn gradient2(&self, point: &Vector2<f32>) -> f32 {
+        self.gradient([point.x, point.y])
+    }
+
+    pub fn gradient3(&self, point: &Vector3<f32>) -> f32 {
+        self.gradient([point.x, point.y, point.z])
+    }
+
+    pub fn gradient4(&self, point: &Vector4<f32>) -> f32 {
+        self.gradient([point.x, point.y, point.z, point.w])
+    }
+
+    pub fn simplex2(&self, point: &Vector2<f32>) -> f32 {
+        self.simplex([point.x, point.y])
+    }
+
+    pub fn simplex3(&self, point: &Vector3<f32>) -> f32 {
+        self.simplex([point.x, point.y, point.z])
+    }
+
+    pub fn simplex4(&self, point: &Vector4<f32>) -> f32 {
+        self.simplex([point.x, point.y, point.z, point.w])
+    }
+
+    // three decorrelated simplex samples, meant as the displacement of warp
+    pub fn vector3(&self, point: &Vector3<f32>) -> Vector3<f32> {
+        Vector3::new(self.simplex3(point),
+                     self.simplex3(&(point + Vector3::new(31.4, 17.7, 5.3))),
+                     self.simplex3(&(point + Vector3::new(-12.9, 47.1, 23.8))))
+    }
+
+    fn hash(&self, lattice: &[i32]) -> usize {
+        lattice.iter().rev().fold(0, |hash, x| self.permutation[(*x & 255) as usize + hash] as usize)
+    }
+
+    fn dot(&self, lattice: &[i32], offset: &[f32]) -> f32 {
+        let hash = self.hash(lattice);
+
+        match offset.len() {
+            4 => GRADIENTS_4[hash % 32].iter().zip(offset).map(|(a, b)| a * b).sum(),
+            _ => GRADIENTS_3[hash % 12].iter().zip(offset).map(|(a, b)| a * b).sum()
+        }
+    }
+
+    // improved perlin noise, quintic fade between the gradients on the corners of the lattice cell
+    fn gradient<const N: usize>(&self, point: [f32; N]) -> f32 {
+        let base: [i32; N] = point.map(|x| x.floor() as i32);
+        let mut fraction = [0.0; N];
+
+        for axis in 0..N {
+            fraction[axis] = point[axis] - base[axis] as f32;
+        }
+
+        // corner bits are axes, so neighbours along the first axis sit next to each other
+        let mut values = [0.0f32; 16];
+        let mut count = 1usize << N;
+
+        for (corner, value) in values.iter_mut().take(count).enumerate() {
+            let mut lattice = base;
+            let mut offset = fraction;
+
+            for axis in 0..N {
+                let bit = (corner >> axis) & 1;
+                lattice[axis] += bit as i32;
+                offset[axis] -= bit as f32;
+            }
+
+            *value = self.dot(&lattice, &offset);
+        }
+
+        for t in fraction.iter() {
+            let fade = t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+            count /= 2;
+
+            for index in 0..count {
+                values[index] = values[index * 2] + (values[index * 2 + 1] - values[index * 2]) * fade;
+            }
+        }
+
+        values[0] * GRADIENT_SCALES[N - 2]
+    }
+
+    // sums the falloff of the corners of the skewed simplex holding the point
+    fn simplex<const N: usize>(&self, point: [f32; N]) -> f32 {
+        let n = N as f32;
+        let skew = ((n + 1.0).sqrt() - 1.0) / n;
+        let unskew = (1.0 - 1.0 / (n + 1.0).sqrt()) / n;
+        let radius = if N == 2 { 0.5 } else { 0.6 };
+
+        let skewed = point.iter().sum::<f32>() * skew;
+        let base: [i32; N] = point.map(|x| (x + skewed).floor() as i32);
+        let unskewed = base.iter().sum::<i32>() as f32 * unskew;
+        let mut origin = [0.0; N];
+
+        for axis in 0..N {
+            origin[axis] = point[axis] - (base[axis] as f32 - unskewed);
+        }
+
+        // the simplex steps along the axes from the largest offset to the smallest, ties go to the first axis
+        let mut rank = [0usize; N];
+
+        for (axis, rank) in rank.iter_mut().enumerate() {
+            *rank = (0..N).filter(|x| origin[*x] > origin[axis] || (origin[*x] == origin[axis] && *x < axis)).count();
+        }
+
+        let mut total = 0.0;
+
+        for corner in 0..=N {
+            let mut lattice = base;
+            let mut offset = [0.0; N];
+
+            for axis in 0..N {
+                let step = if rank[axis] < corner { 1 } else { 0 };
+                lattice[axis] += step;
+                offset[axis] = origin[axis] - step as f32 + corner as f32 * unskew;
+            }
+
+            let falloff = radius - offset.iter().map(|x| x * x).sum::<f32>();
+
+            if falloff > 0.0 {
+                total += falloff.powi(4) * self.dot(&lattice, &offset);
+            }
+        }
+
+        total * SIMPLEX_SCALES[N - 2]
+    }
+}
+
+// fractal brownian motion, normalised back to the range of a single octave
+pub fn fbm<P, F>(noise: F, point: P, fractal: &Fractal) -> f32
+    where P: Copy + Mul<f32, Output = P>, F: Fn(P) -> f32 {
+    octaves(fractal, |frequency| noise(point * frequency))
+}
+
+// sharp crests where the noise crosses zero, each octave weighted by the one before, between zero and one
+pub fn ridged<P, F>(noise: F, point: P, fractal: &Fractal) -> f32
+    where P: Copy + Mul<f32, Output = P>, F: Fn(P) -> f32 {
+    let mut weight = 1.0;
+
+    octaves(fractal, |frequency| {
+        let ridge = (1.0 - noise(point * frequency).abs()).powi(2);
+        let value = ridge * weight;
+        weight = value.clamp(0.0, 1.0);
+
+        value
+    })
+}
+
+// folded octaves, billowy and between zero and one
+pub fn turbulence<P, F>(noise: F, point: P, fractal: &Fractal) -> f32
+    where P: Copy + Mul<f32, Output = P>, F: Fn(P) -> f32 {
+    octaves(fractal, |frequency| noise(point * frequency).abs())
+}
+
+// samples the noise at a point pushed around by another field, noise.vector3 makes a decent displacement
+pub fn warp<P, F, W>(noise: F, displacement: W, point: P, strength: f32) -> f32
+    where P: Copy + Add<Output = P> + Mul<f32, Output = P>, F: Fn(P) -> f32, W: Fn(P) -> P {
+    noise(point + displacement(point) * strength)
+}
+
+fn octaves<F>(fractal: &Fractal, mut octave: F) -> f32
+    where F: FnMut(f32) -> f32 {
+    let mut frequency = fractal.frequency;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut range = 0.0;
+
+    for _ in 0..fractal.octaves.max(1) {
+        total += octave(frequency) * amplitude;
+        range += amplitude;
+        frequency *= fractal.lacunarity;
+        amplitude *= fractal.gain;
+    }
+
+    total / range
+}
+
+// grayscale image from a sampler over uvs in 0 to 1 at pixel centers, values are clamped to 0 to 1 so signed noise
+// wants a x * 0.5 + 0.5 first, TextureData::from_rgba takes the result
+pub fn bake_noise_image<F>(width: u32, height: u32, sampler: F) -> RgbaImage
+    where F: Fn(&Vector2<f32>) -> f32 {
+    RgbaImage::from_fn(width, height, |x, y| {
+        let uv = Vector2::new((x as f32 + 0.5) / width as f32, (y as f32 + 0.5) / height as f32);
+        let value = (sampler(&uv).clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        Rgba([value, value, value, 255])
+    })
+}
+
+// moves every vertex along its normal by the field at its position times amount, vertices sharing a position move
+// along their averaged normal so seams stay closed, triangle lists get new normals and tangents afterwards, smoothed
+// only between vertices that shared their normal before, so hard edges stay hard
+pub fn displace_along_normals<F>(primitive: &mut Primitive, amount: f32, field: F)
+    where F: Fn(&Vector3<f32>) -> f32 {
+    let key = |vector: &Vector3<f32>| {
+        let vector = vector.add_scalar(0.0);
+        [vector.x.to_bits(), vector.y.to_bits(), vector.z.to_bits()]
+    };
+
+    let mut directions: HashMap<[u32; 3], Vector3<f32>> = HashMap::new();
+
+    for vertex in primitive.vertex().iter() {
+        *directions.entry(key(vertex.position())).or_insert_with(Vector3::zeros) += vertex.normal();
+    }
+
+    let positions: Vec<[u32; 3]> = primitive.vertex().iter().map(|x| key(x.position())).collect();
+    let smoothing: Vec<([u32; 3], [u32; 3])> = primitive.vertex().iter().map(|x| (key(x.position()), key(x.normal()))).collect();
+
+    for (vertex, position) in primitive.vertex_mut().iter_mut().zip(positions.iter()) {
+        let direction = directions[position].try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros);
+        let position = vertex.position() + direction * field(vertex.position()) * amount;
+        vertex.set_position(position);
+    }
+
+    if primitive.mode != PrimitiveTopology::TriangleList {
+        return;
+    }
+
+    // area weighted, summed over the original position and normal so duplicates on a smooth seam keep agreeing
+    let indices: Vec<u32> = if primitive.indices().is_empty() {
+        (0..primitive.vertex().len() as u32).collect()
+    } else {
+        primitive.indices().to_vec()
+    };
+    let mut normals: HashMap<([u32; 3], [u32; 3]), Vector3<f32>> = HashMap::new();
+
+    for triangle in indices.chunks_exact(3) {
+        let corner = |x: usize| *primitive.vertex()[triangle[x] as usize].position();
+        let face = (corner(1) - corner(0)).cross(&(corner(2) - corner(0)));
+
+        for index in triangle {
+            *normals.entry(smoothing[*index as usize]).or_insert_with(Vector3::zeros) += face;
+        }
+    }
+
+    for (vertex, group) in primitive.vertex_mut().iter_mut().zip(smoothing.iter()) {
+        if let Some(normal) = normals.get(group).and_then(|x| x.try_normalize(f32::EPSILON)) {
+            vertex.set_normal(normal);
+        }
+    }
+
+    shapes::generate_tangents(primitive);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn random_points(count: usize) -> Vec<Vector4<f32>> {
+        let mut rng = StdRng::seed_from_u64(7);
+
+        (0..count)
+            .map(|_| Vector4::new(rng.gen_range(-50.0, 50.0), rng.gen_range(-50.0, 50.0), rng.gen_range(-50.0, 50.0), rng.gen_range(-50.0, 50.0)))
+            .collect()
+    }
+
+    // every kind of noise over the first two, three or four components
+    fn samples(noise: &Noise, point: &Vector4<f32>) -> [f32; 6] {
+        [noise.gradient2(&point.xy()), noise.gradient3(&point.xyz()), noise.gradient4(point),
+         noise.simplex2(&point.xy()), noise.simplex3(&point.xyz()), noise.simplex4(point)]
+    }
+
+    #[test]
+    fn zero_on_lattice_points() {
+        let noise = Noise::new(3);
+
+        for point in random_points(200) {
+            let lattice = point.map(f32::round);
+            assert_eq!(noise.gradient2(&lattice.xy()), 0.0);
+            assert_eq!(noise.gradient3(&lattice.xyz()), 0.0);
+            assert_eq!(noise.gradient4(&lattice), 0.0);
+
+            // simplex lattice points are integers in skewed space
+            let unskew = |n: f32, lattice: f32| lattice * (1.0 - 1.0 / (n + 1.0).sqrt()) / n;
+            let x2 = lattice.xy().add_scalar(-unskew(2.0, lattice.x + lattice.y));
+            let x3 = lattice.xyz().add_scalar(-unskew(3.0, lattice.x + lattice.y + lattice.z));
+            let x4 = lattice.add_scalar(-unskew(4.0, lattice.sum()));
+
+            assert!(noise.simplex2(&x2).abs() < 1e-4);
+            assert!(noise.simplex3(&x3).abs() < 1e-4);
+            assert!(noise.simplex4(&x4).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn stays_within_one() {
+        let noise = Noise::new(11);
+        let mut largest = [0.0f32; 6];
+
+        for point in random_points(20000) {
+            for (largest, value) in largest.iter_mut().zip(samples(&noise, &point).iter()) {
+                *largest = largest.max(value.abs());
+            }
+        }
+
+        // roughly -1 to 1, and actually using most of it
+        for value in largest.iter() {
+            assert!(*value <= 1.05 && *value > 0.5, "{:?}", largest);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_noise() {
+        let points = random_points(100);
+        let a = Noise::new(42);
+        let b = Noise::new(42);
+        let c = Noise::new(43);
+
+        assert_eq!(a, b);
+        assert!(points.iter().all(|x| samples(&a, x) == samples(&b, x)));
+        assert!(points.iter().any(|x| samples(&a, x) != samples(&c, x)));
+    }
+
+    #[test]
+    fn baked_image_has_the_requested_size() {
+        let noise = Noise::new(0);
+        let image = bake_noise_image(33, 17, |uv| noise.simplex2(&(uv * 8.0)) * 0.5 + 0.5);
+
+        assert_eq!(image.dimensions(), (33, 17));
+        assert!(image.pixels().all(|x| x[0] == x[1] && x[1] == x[2] && x[3] == 255));
+    }
+
+    #[test]
+    fn displaced_cube_keeps_hard_edges_and_closed_seams() {
+        // only corners, so a constant field scales the cube and every face stays flat
+        let mut cube = shapes::cube(1.0, 1).primitives.remove(0);
+        let original = cube.vertex().to_vec();
+        displace_along_normals(&mut cube, 0.1, |_| 1.0);
+
+        for (before, after) in original.iter().zip(cube.vertex().iter()) {
+            assert!((after.normal() - before.normal()).norm() < 1e-5, "{:?} became {:?}", before.normal(), after.normal());
+        }
+
+        // vertices that shared a position still do
+        for a in 0..original.len() {
+            for b in 0..original.len() {
+                if original[a].position() == original[b].position() {
+                    assert_eq!(cube.vertex()[a].position(), cube.vertex()[b].position());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn displaced_sphere_stays_smooth_across_its_seam() {
+        let mut sphere = shapes::uv_sphere(1.0, 16, 8).primitives.remove(0);
+        displace_along_normals(&mut sphere, 0.2, |x| x.y);
+
+        for a in sphere.vertex().iter() {
+            for b in sphere.vertex().iter().filter(|x| x.position() == a.position()) {
+                assert!((a.normal() - b.normal()).norm() < 1e-5);
+            }
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use std::collections::{HashMap, VecDeque};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{layout::Semantic, vertex::Vertex, Primitive};
+
+// Tom Forsyth's "Linear-Speed Vertex Cache Optimisation" constants
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+// FIFO size used for the before and after statistics
+pub const STATS_CACHE_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptimizationStats {
+    pub vertices_before: usize,
+    pub vertices_after: usize,
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+    pub atvr_before: f32,
+    pub atvr_after: f32
+}
+
+// welds, then reorders indices for the post transform cache and vertices for fetch locality
+pub fn optimize(primitive: &mut Primitive, weld_epsilon: f32) -> OptimizationStats {
+    let vertices_before = primitive.vertex().len();
+    let acmr_before = acmr(&primitive.indices().to_vec(), STATS_CACHE_SIZE);
+    let atvr_before = atvr(&primitive.indices().to_vec(), vertices_before, STATS_CACHE_SIZE);
+
+    weld_vertices(primitive, weld_epsilon);
+    optimize_vertex_cache(primitive);
+    optimize_vertex_fetch(primitive);
+
+    let vertices_after = primitive.vertex().len();
+
+    OptimizationStats {
+        vertices_before,
+        vertices_after,
+        acmr_before,
+        acmr_after: acmr(&primitive.indices().to_vec(), STATS_CACHE_SIZE),
+        atvr_before,
+        atvr_after: atvr(&primitive.indices().to_vec(), vertices_after, STATS_CACHE_SIZE)
+    }
+}
+
+// merges vertices whose attributes all differ by at most epsilon, returns how many were removed
+pub fn weld_vertices(primitive: &mut Primitive, epsilon: f32) -> usize {
+    let vertex_count = primitive.vertex().len();
+    let indices: Vec<u32> = if primitive.indices().is_empty() {
+        (0..vertex_count as u32).collect()
+    } else {
+        primitive.indices().to_vec()
+    };
+
+    let cell = epsilon.max(f32::EPSILON);
+    let cell_of = |vertex: &Vertex| {
+        let position = vertex.position() / cell;
+        (position.x.floor() as i64, position.y.floor() as i64, position.z.floor() as i64)
+    };
+
+    let mut grid: HashMap<(i64, i64, i64), Vec<u32>> = HashMap::new();
+    // source vertex kept for every welded one
+    let mut sources: Vec<u32> = Vec::with_capacity(vertex_count);
+    let mut remap: Vec<u32> = Vec::with_capacity(vertex_count);
+    let extra_semantics = primitive.extra_semantics();
+
+    for (source, vertex) in primitive.vertex().iter().enumerate() {
+        let (x, y, z) = cell_of(vertex);
+        let mut found: Option<u32> = None;
+
+        'search: for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    if let Some(candidates) = grid.get(&(x + dx, y + dy, z + dz)) {
+                        let equal = |other: &u32| {
+                            let other = sources[*other as usize] as usize;
+                            vertices_equal(&primitive.vertex()[other], vertex, epsilon) &&
+                                extra_semantics.iter().all(|semantic| attributes_equal(primitive, *semantic, other, source, epsilon))
+                        };
+
+                        if let Some(candidate) = candidates.iter().find(|x| equal(x)) {
+                            found = Some(*candidate);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+
+        let index = found.unwrap_or_else(|| {
+            sources.push(source as u32);
+            let index = (sources.len() - 1) as u32;
+            grid.entry((x, y, z)).or_default().push(index);
+            index
+        });
+
+        remap.push(index);
+    }
+
+    let mut new_indices: Vec<u32> = indices.iter().map(|x| remap[*x as usize]).collect();
+
+    // welding can collapse triangles to a line or a point
+    if primitive.mode == PrimitiveTopology::TriangleList {
+        new_indices = new_indices
+            .chunks(3)
+            .filter(|x| x.len() == 3 && x[0] != x[1] && x[1] != x[2] && x[0] != x[2])
+            .flatten()
+            .copied()
+            .collect();
+    }
+
+    let removed = vertex_count - sources.len();
+    *primitive = primitive.select_vertices(&sources, new_indices);
+
+    removed
+}
+
+fn vertices_equal(a: &Vertex, b: &Vertex, epsilon: f32) -> bool {
+    (a.position() - b.position()).amax() <= epsilon &&
+        (a.normal() - b.normal()).amax() <= epsilon &&
+        (a.tangent() - b.tangent()).amax() <= epsilon &&
+        (a.uv() - b.uv()).amax() <= epsilon
+}
+
+fn attributes_equal(primitive: &Primitive, semantic: Semantic, a: usize, b: usize, epsilon: f32) -> bool {
+    let a = primitive.attribute(semantic, a);
+    let b = primitive.attribute(semantic, b);
+
+    a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() <= epsilon)
+}
+
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: u32) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        // the last triangle's vertices get a fixed score so its neighbours are not preferred over it
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+        None => 0.0
+    };
+
+    // vertices with few triangles left are finished first, so they do not linger
+    cache_score + VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+// Forsyth's greedy reordering of a triangle list, other topologies are left untouched
+pub fn optimize_vertex_cache(primitive: &mut Primitive) {
+    if primitive.mode != PrimitiveTopology::TriangleList || primitive.indices().len() < 3 {
+        return;
+    }
+
+    let indices = primitive.indices().to_vec();
+    let vertex_count = primitive.vertex().len();
+    let triangle_count = indices.len() / 3;
+
+    // triangles touching every vertex, packed as offsets into one list
+    let mut remaining: Vec<u32> = vec![0; vertex_count];
+    for index in indices.iter().take(triangle_count * 3) {
+        remaining[*index as usize] += 1;
+    }
+
+    let mut offsets: Vec<usize> = vec![0; vertex_count + 1];
+    for vertex in 0..vertex_count {
+        offsets[vertex + 1] = offsets[vertex] + remaining[vertex] as usize;
+    }
+
+    let mut adjacency: Vec<u32> = vec![0; offsets[vertex_count]];
+    let mut fill = offsets.clone();
+    for triangle in 0..triangle_count {
+        for corner in 0..3 {
+            let vertex = indices[triangle * 3 + corner] as usize;
+            adjacency[fill[vertex]] = triangle as u32;
+            fill[vertex] += 1;
+        }
+    }
+
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f32> = (0..vertex_count).map(|x| vertex_score(None, remaining[x])).collect();
+    let mut emitted: Vec<bool> = vec![false; triangle_count];
+    let mut triangle_scores: Vec<f32> = (0..triangle_count)
+        .map(|x| (0..3).map(|corner| vertex_scores[indices[x * 3 + corner] as usize]).sum())
+        .collect();
+
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut output: Vec<u32> = Vec::with_capacity(triangle_count * 3);
+    let mut best: Option<usize> = None;
+    let mut scan_cursor = 0usize;
+
+    for _ in 0..triangle_count {
+        // nothing adjacent to the cache, take the best remaining triangle
+        let triangle = match best {
+            Some(triangle) => triangle,
+            None => {
+                while emitted[scan_cursor] {
+                    scan_cursor += 1;
+                }
+
+                (scan_cursor..triangle_count)
+                    .filter(|x| !emitted[*x])
+                    .max_by(|a, b| triangle_scores[*a].total_cmp(&triangle_scores[*b]))
+                    .unwrap()
+            }
+        };
+
+        emitted[triangle] = true;
+        let corners = [indices[triangle * 3], indices[triangle * 3 + 1], indices[triangle * 3 + 2]];
+        output.extend_from_slice(&corners);
+
+        for vertex in corners.iter().map(|x| *x as usize) {
+            let start = offsets[vertex];
+            let end = start + remaining[vertex] as usize;
+            if let Some(position) = adjacency[start..end].iter().position(|x| *x as usize == triangle) {
+                adjacency.swap(start + position, end - 1);
+                remaining[vertex] -= 1;
+            }
+        }
+
+        let mut new_cache: Vec<u32> = corners.to_vec();
+        new_cache.extend(cache.iter().filter(|x| !corners.contains(x)));
+
+        for (position, vertex) in new_cache.iter().enumerate() {
+            cache_position[*vertex as usize] = if position < CACHE_SIZE { Some(position) } else { None };
+        }
+
+        let touched = new_cache.clone();
+        new_cache.truncate(CACHE_SIZE);
+        cache = new_cache;
+
+        for vertex in touched.iter().map(|x| *x as usize) {
+            vertex_scores[vertex] = vertex_score(cache_position[vertex], remaining[vertex]);
+        }
+
+        best = None;
+        let mut best_score = f32::MIN;
+
+        for vertex in touched.iter().map(|x| *x as usize) {
+            let start = offsets[vertex];
+            let end = start + remaining[vertex] as usize;
+
+            for other in adjacency[start..end].iter().map(|x| *x as usize) {
+                let score = (0..3).map(|corner| vertex_scores[indices[other * 3 + corner] as usize]).sum();
+                triangle_scores[other] = score;
+
+                if score > best_score {
+                    best_score = score;
+                    best = Some(other);
+                }
+            }
+        }
+    }
+
+    primitive.set_indices(output);
+}
+
+// renumbers vertices in order of first use and drops the ones no index points to
+pub fn optimize_vertex_fetch(primitive: &mut Primitive) {
+    if primitive.indices().is_empty() {
+        return;
+    }
+
+    let mut remap: Vec<Option<u32>> = vec![None; primitive.vertex().len()];
+    let mut sources: Vec<u32> = Vec::with_capacity(primitive.vertex().len());
+
+    let indices: Vec<u32> = primitive.indices()
+        .iter()
+        .map(|index| {
+            *remap[index as usize].get_or_insert_with(|| {
+                sources.push(index);
+                (sources.len() - 1) as u32
+            })
+        })
+        .collect();
+
+    *primitive = primitive.select_vertices(&sources, indices);
+}
+
+// vertices transformed per triangle with a FIFO cache, 3.0 is the worst case and 0.5 the ideal on big grids
+pub fn acmr(indices: &[u32], cache_size: usize) -> f32 {
+    let triangles = indices.len() / 3;
+
+    if triangles == 0 {
+        return 0.0;
+    }
+
+    cache_misses(indices, cache_size) as f32 / triangles as f32
+}
+
+// vertices transformed per unique vertex, 1.0 is the ideal
+pub fn atvr(indices: &[u32], vertex_count: usize, cache_size: usize) -> f32 {
+    if vertex_count == 0 {
+        return 0.0;
+    }
+
+    cache_misses(indices, cache_size) as f32 / vertex_count as f32
+}
+
+fn cache_misses(indices: &[u32], cache_size: usize) -> usize {
+    let mut cache: VecDeque<u32> = VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+
+    for index in indices {
+        if !cache.contains(index) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(*index);
+        }
+    }
+
+    misses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::shapes;
+
+    // every triangle as the bits of its corners, rotated to start at the smallest so the winding is kept
+    fn triangle_set(primitive: &Primitive) -> Vec<[[u32; 5]; 3]> {
+        let corner = |index: u32| {
+            let vertex = &primitive.vertex()[index as usize];
+            [vertex.position().x.to_bits(), vertex.position().y.to_bits(), vertex.position().z.to_bits(),
+             vertex.uv().x.to_bits(), vertex.uv().y.to_bits()]
+        };
+
+        let mut triangles: Vec<[[u32; 5]; 3]> = primitive
+            .indices()
+            .to_vec()
+            .chunks_exact(3)
+            .map(|x| {
+                let mut triangle = [corner(x[0]), corner(x[1]), corner(x[2])];
+                let first = (0..3).min_by_key(|x| triangle[*x]).unwrap();
+                triangle.rotate_left(first);
+                triangle
+            })
+            .collect();
+
+        triangles.sort_unstable();
+        triangles
+    }
+
+    fn unindexed(primitive: &Primitive) -> Primitive {
+        primitive.select_vertices(&primitive.indices().to_vec(), Vec::new())
+    }
+
+    // the same triangles in a fixed but scattered order
+    fn shuffled(primitive: &Primitive) -> Primitive {
+        let indices = primitive.indices().to_vec();
+        let count = indices.len() / 3;
+        let scattered = (0..count)
+            .flat_map(|x| {
+                let triangle = (x * 7919) % count;
+                indices[triangle * 3..triangle * 3 + 3].to_vec()
+            })
+            .collect();
+
+        primitive.select_vertices(&(0..primitive.vertex().len() as u32).collect::<Vec<u32>>(), scattered)
+    }
+
+    #[test]
+    fn weld_merges_duplicates() {
+        let plane = &shapes::plane(1.0, 1.0, 4, 4).primitives[0];
+        let mut welded = unindexed(plane);
+
+        assert_eq!(weld_vertices(&mut welded, 1e-6), 96 - 25);
+        assert_eq!(welded.vertex().len(), 25);
+        assert_eq!(triangle_set(&welded), triangle_set(plane));
+    }
+
+    #[test]
+    fn weld_keeps_seams() {
+        // cube corners share positions but not normals or uvs
+        let cube = &shapes::cube(2.0, 1).primitives[0];
+        let mut welded = unindexed(cube);
+
+        weld_vertices(&mut welded, 1e-4);
+
+        assert_eq!(welded.vertex().len(), 24);
+        assert_eq!(triangle_set(&welded), triangle_set(cube));
+    }
+
+    #[test]
+    fn vertex_cache_never_raises_acmr_on_a_grid() {
+        let grid = &shapes::plane(1.0, 1.0, 32, 32).primitives[0];
+
+        let ordered = grid.select_vertices(&(0..grid.vertex().len() as u32).collect::<Vec<u32>>(), grid.indices().to_vec());
+
+        for mut primitive in [shuffled(grid), ordered] {
+            let before = acmr(&primitive.indices().to_vec(), STATS_CACHE_SIZE);
+            optimize_vertex_cache(&mut primitive);
+            let after = acmr(&primitive.indices().to_vec(), STATS_CACHE_SIZE);
+
+            assert!(after <= before, "acmr went from {} to {}", before, after);
+            assert!(after < 1.0, "acmr {}", after);
+            assert_eq!(triangle_set(&primitive), triangle_set(grid));
+        }
+    }
+
+    #[test]
+    fn vertex_fetch_keeps_the_triangles() {
+        let grid = &shapes::plane(1.0, 1.0, 8, 8).primitives[0];
+        let mut primitive = shuffled(grid);
+
+        optimize_vertex_fetch(&mut primitive);
+
+        assert_eq!(triangle_set(&primitive), triangle_set(grid));
+
+        // first use order, so every index is at most one past the largest seen before it
+        let mut next = 0;
+
+        for index in primitive.indices().iter() {
+            assert!(index <= next);
+            next = next.max(index + 1);
+        }
+
+        assert_eq!(next as usize, primitive.vertex().len());
+    }
+}
@@ -0,0 +1,304 @@
+use std::{collections::HashMap, f32::consts::PI};
+use image::RgbaImage;
+use nalgebra::{UnitQuaternion, Vector2, Vector3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{instance::Instance, layout::Semantic, Primitive, RenderError};
+
+// every candidate is kept with a probability between zero and one read at its position
+#[derive(Debug, Clone, Copy)]
+pub enum Density<'a> {
+    Uniform,
+    // red channel at the sample's uv, repeating outside 0 to 1
+    Texture(&'a RgbaImage),
+    // one component of a vertex attribute, interpolated over the triangle
+    Attribute(Semantic, usize)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScatterOptions<'a> {
+    // points wanted, fewer come back when spacing or density reject too many candidates
+    pub count: usize,
+    // poisson disk spacing between any two points, zero turns it off
+    pub min_distance: f32,
+    // candidates tried per wanted point before giving up
+    pub attempts: usize,
+    pub density: Density<'a>,
+    pub seed: u64,
+    // instances point their +Y along the normal instead of staying upright
+    pub align_to_normal: bool,
+    // uniform scale picked between the two
+    pub scale: (f32, f32)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceSample {
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub uv: Vector2<f32>,
+    pub triangle: usize
+}
+
+impl Default for ScatterOptions<'_> {
+    fn default() -> Self {
+        Self {
+            count: 1000,
+            min_distance: 0.0,
+            attempts: 30,
+            density: Density::Uniform,
+            seed: 0,
+            align_to_normal: true,
+            scale: (1.0, 1.0)
+        }
+    }
+}
+
+// points spread evenly by area over a triangle list, the same seed always gives the same points
+pub fn scatter_points(primitive: &Primitive, options: &ScatterOptions) -> Result<Vec<SurfaceSample>, RenderError> {
+    scatter(primitive, options, &mut StdRng::seed_from_u64(options.seed))
+}
+
+// one instance per point with a random turn around its up axis, ready for InstanceRaw
+pub fn scatter_instances(primitive: &Primitive, options: &ScatterOptions) -> Result<Vec<Instance>, RenderError> {
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let samples = scatter(primitive, options, &mut rng)?;
+
+    Ok(samples
+        .iter()
+        .map(|sample| {
+            let up = if options.align_to_normal { sample.normal } else { Vector3::y() };
+
+            // rotation_between has no answer for the exact opposite direction
+            let tilt = UnitQuaternion::rotation_between(&Vector3::y(), &up)
+                .unwrap_or_else(|| UnitQuaternion::from_axis_angle(&Vector3::x_axis(), PI));
+            let yaw = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), rng.gen::<f32>() * 2.0 * PI);
+
+            let (low, high) = options.scale;
+            let scale = low + (high - low) * rng.gen::<f32>();
+
+            Instance::new(sample.position, tilt * yaw, Vector3::repeat(scale))
+        })
+        .collect())
+}
+
+fn scatter(primitive: &Primitive, options: &ScatterOptions, rng: &mut StdRng) -> Result<Vec<SurfaceSample>, RenderError> {
+    if primitive.mode != PrimitiveTopology::TriangleList {
+        return Err(RenderError::Geometry("points can only be scattered over triangle lists".to_string()));
+    }
+
+    let indices: Vec<u32> = if primitive.indices().is_empty() {
+        (0..primitive.vertex().len() as u32).collect()
+    } else {
+        primitive.indices().to_vec()
+    };
+
+    let triangles: Vec<&[u32]> = indices.chunks_exact(3).collect();
+    let position = |index: u32| *primitive.vertex()[index as usize].position();
+
+    // running total of the areas, a uniform pick along it lands on triangles in proportion to their size
+    let mut total = 0.0;
+    let cumulative: Vec<f32> = triangles
+        .iter()
+        .map(|triangle| {
+            let (a, b, c) = (position(triangle[0]), position(triangle[1]), position(triangle[2]));
+            total += (b - a).cross(&(c - a)).norm() * 0.5;
+            total
+        })
+        .collect();
+
+    let mut samples: Vec<SurfaceSample> = Vec::with_capacity(options.count);
+
+    if total <= 0.0 {
+        return Ok(samples);
+    }
+
+    // accepted points by cell, a cell is min_distance wide so only the neighbouring ones need checking
+    let mut grid: HashMap<[i32; 3], Vec<usize>> = HashMap::new();
+    let cell_of = |point: &Vector3<f32>| {
+        let cell = point / options.min_distance;
+        [cell.x.floor() as i32, cell.y.floor() as i32, cell.z.floor() as i32]
+    };
+
+    for _ in 0..options.count.saturating_mul(options.attempts.max(1)) {
+        if samples.len() >= options.count {
+            break;
+        }
+
+        let pick = rng.gen::<f32>() * total;
+        let triangle = cumulative.partition_point(|x| *x <= pick).min(cumulative.len() - 1);
+
+        // square root warping keeps the barycentric coordinates uniform over the area
+        let root = rng.gen::<f32>().sqrt();
+        let second = rng.gen::<f32>();
+        let weights = [1.0 - root, root * (1.0 - second), root * second];
+        let corners = triangles[triangle];
+
+        let interpolate = |value: &dyn Fn(u32) -> Vector3<f32>| {
+            corners.iter().zip(weights.iter()).map(|(x, w)| value(*x) * *w).sum::<Vector3<f32>>()
+        };
+
+        let point = interpolate(&|x| position(x));
+        let uv = interpolate(&|x| primitive.vertex()[x as usize].uv().push(0.0)).xy();
+
+        let density = match options.density {
+            Density::Uniform => 1.0,
+            Density::Texture(image) => texture_density(image, &uv),
+            Density::Attribute(semantic, component) => corners
+                .iter()
+                .zip(weights.iter())
+                .map(|(x, w)| primitive.attribute(semantic, *x as usize)[component.min(3)] * w)
+                .sum()
+        };
+
+        if density < 1.0 && rng.gen::<f32>() >= density {
+            continue;
+        }
+
+        if options.min_distance > 0.0 {
+            let cell = cell_of(&point);
+            let mut neighbours = (-1..=1).flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| [cell[0] + x, cell[1] + y, cell[2] + z])));
+
+            let crowded = neighbours.any(|x| {
+                grid.get(&x).into_iter().flatten().any(|y| (samples[*y].position - point).norm() < options.min_distance)
+            });
+
+            if crowded {
+                continue;
+            }
+
+            grid.entry(cell).or_default().push(samples.len());
+        }
+
+        let (a, b, c) = (position(corners[0]), position(corners[1]), position(corners[2]));
+        let face = (b - a).cross(&(c - a)).try_normalize(f32::EPSILON).unwrap_or_else(Vector3::y);
+        let normal = interpolate(&|x| *primitive.vertex()[x as usize].normal()).try_normalize(f32::EPSILON).unwrap_or(face);
+
+        samples.push(SurfaceSample {
+            position: point,
+            normal,
+            uv,
+            triangle
+        });
+    }
+
+    Ok(samples)
+}
+
+// nearest texel, wrapping like a repeating sampler
+fn texture_density(image: &RgbaImage, uv: &Vector2<f32>) -> f32 {
+    if image.width() == 0 || image.height() == 0 {
+        return 0.0;
+    }
+
+    let x = (uv.x.rem_euclid(1.0) * image.width() as f32) as u32;
+    let y = (uv.y.rem_euclid(1.0) * image.height() as f32) as u32;
+
+    image.get_pixel(x.min(image.width() - 1), y.min(image.height() - 1))[0] as f32 / 255.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use nalgebra::Vector4;
+    use crate::renderer::{shapes, vertex::Vertex};
+
+    // two triangles on y = 0 without indices, the second three times the area of the first
+    fn uneven_pair() -> Primitive {
+        let corners = [[0.0, 0.0], [0.0, 1.0], [2.0, 0.0], [5.0, 0.0], [5.0, 2.0], [8.0, 0.0]];
+        let vertices = corners
+            .iter()
+            .map(|[x, z]| Vertex::new(Vector3::new(*x, 0.0, *z), Vector3::y(), Vector4::zeros(), Vector2::new(*x, *z)))
+            .collect();
+
+        Primitive::new(vertices, Vec::new(), 0, PrimitiveTopology::TriangleList)
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_points() {
+        let cube = shapes::cube(2.0, 2);
+        let options = ScatterOptions {
+            count: 200,
+            seed: 7,
+            ..ScatterOptions::default()
+        };
+
+        let first = scatter_points(&cube.primitives[0], &options).unwrap();
+        assert_eq!(first.len(), 200);
+        assert_eq!(first, scatter_points(&cube.primitives[0], &options).unwrap());
+        assert_ne!(first, scatter_points(&cube.primitives[0], &ScatterOptions { seed: 8, ..options }).unwrap());
+    }
+
+    #[test]
+    fn points_lie_on_their_triangles() {
+        let sphere = shapes::icosphere(1.5, 1);
+        let primitive = &sphere.primitives[0];
+        let indices = primitive.indices().to_vec();
+
+        for sample in scatter_points(primitive, &ScatterOptions { count: 500, ..ScatterOptions::default() }).unwrap() {
+            let corner = |x: usize| *primitive.vertex()[indices[sample.triangle * 3 + x] as usize].position();
+            let (a, b, c) = (corner(0), corner(1), corner(2));
+            let normal = (b - a).cross(&(c - a));
+
+            // on the plane and on the inner side of every edge
+            assert!((sample.position - a).dot(&normal.normalize()).abs() < 1e-5);
+            for (from, to) in [(a, b), (b, c), (c, a)].iter() {
+                assert!((to - from).cross(&(sample.position - from)).dot(&normal) >= -1e-5);
+            }
+
+            assert!((sample.normal.norm() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn triangles_get_points_by_area() {
+        let samples = scatter_points(&uneven_pair(), &ScatterOptions { count: 4000, ..ScatterOptions::default() }).unwrap();
+        let large = samples.iter().filter(|x| x.triangle == 1).count() as f32 / samples.len() as f32;
+
+        assert_eq!(samples.len(), 4000);
+        assert!((large - 0.75).abs() < 0.03, "{} of the points on the larger triangle", large);
+        assert!(samples.iter().all(|x| x.position.y == 0.0 && x.normal == Vector3::y()));
+    }
+
+    #[test]
+    fn points_keep_their_distance() {
+        let plane = shapes::plane(4.0, 4.0, 4, 4);
+        let options = ScatterOptions {
+            count: 400,
+            min_distance: 0.3,
+            ..ScatterOptions::default()
+        };
+
+        let samples = scatter_points(&plane.primitives[0], &options).unwrap();
+        assert!(samples.len() > 50);
+
+        for (index, first) in samples.iter().enumerate() {
+            for second in samples[index + 1..].iter() {
+                assert!((first.position - second.position).norm() >= 0.3);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_density_gives_no_points() {
+        let mut primitive = uneven_pair();
+        let black = RgbaImage::from_pixel(4, 4, Rgba([0, 255, 255, 255]));
+
+        let samples = scatter_points(&primitive, &ScatterOptions { density: Density::Texture(&black), ..ScatterOptions::default() }).unwrap();
+        assert!(samples.is_empty());
+
+        primitive.set_attribute(Semantic::Color, vec![[1.0, 1.0, 0.0, 1.0]; 6]);
+        let options = ScatterOptions {
+            density: Density::Attribute(Semantic::Color, 2),
+            ..ScatterOptions::default()
+        };
+        assert!(scatter_points(&primitive, &options).unwrap().is_empty());
+
+        let options = ScatterOptions {
+            density: Density::Attribute(Semantic::Color, 0),
+            count: 100,
+            ..options
+        };
+        assert_eq!(scatter_points(&primitive, &options).unwrap().len(), 100);
+    }
+}
@@ -0,0 +1,373 @@
+use std::{fs, path::Path};
+use nalgebra::{Point3, Vector3};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{bounds::Aabb, bvh::{closest_point_on_triangle, count_crossings, intersect_triangle, MeshBvh, Ray}, Mesh, RenderError};
+
+const MAGIC: [u8; 4] = *b"SDF1";
+const HEADER_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceQuery {
+    Bvh,
+    // every triangle for every sample, only worth it for tiny meshes
+    BruteForce
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SdfOptions {
+    // samples along the longest side of the bounds, padding included
+    pub resolution: u32,
+    // empty voxels kept around the bounds on every side
+    pub padding: u32,
+    pub query: DistanceQuery
+}
+
+enum Source {
+    Bvh(MeshBvh),
+    Triangles(Vec<[Point3<f32>; 3]>, Aabb)
+}
+
+// distances are negative inside, samples sit on voxel centers with x changing fastest
+#[derive(Debug, Clone, PartialEq)]
+pub struct SdfGrid {
+    dimensions: [u32; 3],
+    // center of the first voxel
+    origin: Point3<f32>,
+    voxel_size: f32,
+    distances: Vec<f32>
+}
+
+impl Default for SdfOptions {
+    fn default() -> Self {
+        Self {
+            resolution: 64,
+            padding: 2,
+            query: DistanceQuery::Bvh
+        }
+    }
+}
+
+impl SdfGrid {
+    pub fn new(dimensions: [u32; 3], origin: Point3<f32>, voxel_size: f32, distances: Vec<f32>) -> Result<Self, RenderError> {
+        if voxel_count(dimensions)? != distances.len() {
+            return Err(RenderError::Geometry(format!("{} distances do not fill {:?} voxels", distances.len(), dimensions)));
+        }
+
+        Ok(Self {
+            dimensions,
+            origin,
+            voxel_size,
+            distances
+        })
+    }
+
+    pub fn dimensions(&self) -> [u32; 3] {
+        self.dimensions
+    }
+
+    pub fn origin(&self) -> &Point3<f32> {
+        &self.origin
+    }
+
+    pub fn voxel_size(&self) -> f32 {
+        self.voxel_size
+    }
+
+    pub fn distances(&self) -> &Vec<f32> {
+        &self.distances
+    }
+
+    pub fn index(&self, x: u32, y: u32, z: u32) -> usize {
+        (x + self.dimensions[0] * (y + self.dimensions[1] * z)) as usize
+    }
+
+    pub fn get(&self, x: u32, y: u32, z: u32) -> f32 {
+        self.distances[self.index(x, y, z)]
+    }
+
+    pub fn position(&self, x: u32, y: u32, z: u32) -> Point3<f32> {
+        self.origin + Vector3::new(x as f32, y as f32, z as f32) * self.voxel_size
+    }
+
+    // trilinear, points outside the grid read the closest border sample
+    pub fn sample(&self, point: &Point3<f32>) -> f32 {
+        let local = (point - self.origin) / self.voxel_size;
+        let mut base = [0u32; 3];
+        let mut fraction = [0f32; 3];
+
+        for axis in 0..3 {
+            let last = self.dimensions[axis].saturating_sub(1) as f32;
+            let coordinate = local[axis].clamp(0.0, last);
+            let cell = coordinate.floor().min((last - 1.0).max(0.0));
+
+            base[axis] = cell as u32;
+            fraction[axis] = coordinate - cell;
+        }
+
+        let corner = |offset: [u32; 3]| {
+            let mut coordinates = [0u32; 3];
+
+            for axis in 0..3 {
+                coordinates[axis] = (base[axis] + offset[axis]).min(self.dimensions[axis] - 1);
+            }
+
+            self.get(coordinates[0], coordinates[1], coordinates[2])
+        };
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let x00 = lerp(corner([0, 0, 0]), corner([1, 0, 0]), fraction[0]);
+        let x10 = lerp(corner([0, 1, 0]), corner([1, 1, 0]), fraction[0]);
+        let x01 = lerp(corner([0, 0, 1]), corner([1, 0, 1]), fraction[0]);
+        let x11 = lerp(corner([0, 1, 1]), corner([1, 1, 1]), fraction[0]);
+
+        lerp(lerp(x00, x10, fraction[1]), lerp(x01, x11, fraction[1]), fraction[2])
+    }
+
+    // central differences, pointing away from the surface
+    pub fn gradient(&self, point: &Point3<f32>) -> Vector3<f32> {
+        let step = self.voxel_size * 0.5;
+        let difference = |axis: Vector3<f32>| self.sample(&(point + axis * step)) - self.sample(&(point - axis * step));
+
+        Vector3::new(difference(Vector3::x()), difference(Vector3::y()), difference(Vector3::z())) / (2.0 * step)
+    }
+
+    // little endian: magic, three u32 dimensions, origin, voxel size, then every distance as f32
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(HEADER_SIZE + self.distances.len() * 4);
+        bytes.extend_from_slice(&MAGIC);
+
+        for dimension in self.dimensions.iter() {
+            bytes.extend_from_slice(&dimension.to_le_bytes());
+        }
+
+        for value in self.origin.iter().chain(std::iter::once(&self.voxel_size)).chain(self.distances.iter()) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RenderError> {
+        if bytes.len() < HEADER_SIZE || bytes[..4] != MAGIC {
+            return Err(RenderError::Geometry("not a distance field".to_string()));
+        }
+
+        let word = |index: usize| {
+            let mut word = [0u8; 4];
+            word.copy_from_slice(&bytes[4 + index * 4..8 + index * 4]);
+            u32::from_le_bytes(word)
+        };
+
+        let dimensions = [word(0), word(1), word(2)];
+        let origin = Point3::new(f32::from_bits(word(3)), f32::from_bits(word(4)), f32::from_bits(word(5)));
+        let voxel_size = f32::from_bits(word(6));
+        let count = voxel_count(dimensions)?;
+
+        if count.checked_mul(4).and_then(|x| x.checked_add(HEADER_SIZE)) != Some(bytes.len()) {
+            return Err(RenderError::Geometry(format!("{} bytes do not hold {:?} distances", bytes.len(), dimensions)));
+        }
+
+        let distances = (0..count).map(|x| f32::from_bits(word(7 + x))).collect();
+
+        Self::new(dimensions, origin, voxel_size, distances)
+    }
+
+    pub fn write_raw<P: AsRef<Path>>(&self, path: P) -> Result<(), RenderError> {
+        fs::write(path, self.to_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn read_raw<P: AsRef<Path>>(path: P) -> Result<Self, RenderError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+impl Source {
+    fn is_empty(&self) -> bool {
+        match self {
+            Source::Bvh(bvh) => bvh.triangle_count() == 0,
+            Source::Triangles(triangles, _) => triangles.is_empty()
+        }
+    }
+
+    fn distance(&self, point: &Point3<f32>) -> f32 {
+        match self {
+            Source::Bvh(bvh) => bvh.closest_point(point, f32::INFINITY).map_or(f32::INFINITY, |x| x.distance),
+            Source::Triangles(triangles, _) => triangles
+                .iter()
+                .map(|[a, b, c]| nalgebra::distance(point, &closest_point_on_triangle(point, a, b, c).0))
+                .fold(f32::INFINITY, f32::min)
+        }
+    }
+
+    fn crossings(&self, ray: &Ray) -> usize {
+        match self {
+            Source::Bvh(bvh) => bvh.crossings(ray),
+            Source::Triangles(triangles, bounds) => {
+                let distances = triangles.iter().filter_map(|[a, b, c]| intersect_triangle(ray, a, b, c).map(|x| x.0)).collect();
+                count_crossings(ray, bounds, distances)
+            }
+        }
+    }
+}
+
+// every axis needs a sample for the grid to be sampled at all
+fn voxel_count(dimensions: [u32; 3]) -> Result<usize, RenderError> {
+    if dimensions.contains(&0) {
+        return Err(RenderError::Geometry(format!("empty distance field {:?}", dimensions)));
+    }
+
+    dimensions
+        .iter()
+        .try_fold(1usize, |count, x| count.checked_mul(*x as usize))
+        .ok_or_else(|| RenderError::Geometry(format!("distance field {:?} is too large", dimensions)))
+}
+
+// irregular so a ray rarely runs along an edge, the sign is the majority vote of their parities
+fn sign_rays(origin: Point3<f32>) -> [Ray; 3] {
+    [Ray::new(origin, Vector3::new(0.5773, 0.6188, 0.5329)),
+     Ray::new(origin, Vector3::new(-0.6981, 0.1522, -0.6995)),
+     Ray::new(origin, Vector3::new(0.2236, -0.9165, 0.3317))]
+}
+
+fn triangles(mesh: &Mesh) -> Vec<[Point3<f32>; 3]> {
+    let mut triangles: Vec<[Point3<f32>; 3]> = Vec::new();
+
+    for primitive in mesh.primitives.iter().filter(|x| x.mode == PrimitiveTopology::TriangleList) {
+        let indices: Vec<u32> = if primitive.indices().is_empty() {
+            (0..primitive.vertex().len() as u32).collect()
+        } else {
+            primitive.indices().to_vec()
+        };
+
+        for triangle in indices.chunks_exact(3) {
+            let corner = |x: usize| Point3::from(*primitive.vertex()[triangle[x] as usize].position());
+            triangles.push([corner(0), corner(1), corner(2)]);
+        }
+    }
+
+    triangles
+}
+
+// the sign is only meaningful for watertight meshes, open ones get a best guess near their holes
+pub fn bake_sdf(mesh: &Mesh, options: &SdfOptions) -> Result<SdfGrid, RenderError> {
+    let bounds = mesh.bounds().aabb;
+
+    if bounds.is_empty() || options.resolution == 0 {
+        return Err(RenderError::Geometry("nothing to bake".to_string()));
+    }
+
+    let resolution = options.resolution.max(options.padding * 2 + 2);
+    let longest = bounds.size().max().max(f32::EPSILON);
+    let voxel_size = longest / (resolution - options.padding * 2) as f32;
+    let padding = voxel_size * options.padding as f32;
+
+    let mut dimensions = [0u32; 3];
+    for (axis, dimension) in dimensions.iter_mut().enumerate() {
+        *dimension = ((bounds.size()[axis] + 2.0 * padding) / voxel_size).ceil().max(1.0) as u32;
+    }
+
+    // centered on the bounds, the rounding up spreads evenly on both sides
+    let extent = Vector3::new(dimensions[0] as f32, dimensions[1] as f32, dimensions[2] as f32) * voxel_size;
+    let origin = bounds.center() - extent * 0.5 + Vector3::repeat(voxel_size * 0.5);
+
+    let source = match options.query {
+        DistanceQuery::Bvh => Source::Bvh(MeshBvh::new(mesh)),
+        DistanceQuery::BruteForce => Source::Triangles(triangles(mesh), bounds)
+    };
+
+    if source.is_empty() {
+        return Err(RenderError::Geometry("mesh has no triangles".to_string()));
+    }
+
+    let mut grid = SdfGrid::new(dimensions, origin, voxel_size, vec![0.0; voxel_count(dimensions)?])?;
+
+    for z in 0..dimensions[2] {
+        for y in 0..dimensions[1] {
+            for x in 0..dimensions[0] {
+                let point = grid.position(x, y, z);
+                let inside = sign_rays(point).iter().filter(|ray| source.crossings(ray) % 2 == 1).count() >= 2;
+                let distance = source.distance(&point);
+                let index = grid.index(x, y, z);
+
+                grid.distances[index] = if inside { -distance } else { distance };
+            }
+        }
+    }
+
+    Ok(grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::shapes;
+
+    fn baked_cube() -> SdfGrid {
+        let options = SdfOptions {
+            resolution: 12,
+            ..Default::default()
+        };
+
+        bake_sdf(&shapes::cube(1.0, 1), &options).unwrap()
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let grid = baked_cube();
+
+        assert_eq!(SdfGrid::from_bytes(&grid.to_bytes()).unwrap(), grid);
+    }
+
+    #[test]
+    fn cube_is_negative_inside_and_positive_outside() {
+        let grid = baked_cube();
+        let bounds = shapes::cube(1.0, 1).bounds().aabb;
+
+        assert!(grid.sample(&bounds.center()) < 0.0);
+        assert!(grid.sample(&(bounds.center() + bounds.size())) > 0.0);
+
+        // the center is half a side away from every face
+        assert!((grid.sample(&bounds.center()) + bounds.size().x * 0.5).abs() < grid.voxel_size());
+
+        for (index, distance) in grid.distances().iter().enumerate() {
+            let width = grid.dimensions()[0] as usize;
+            let height = grid.dimensions()[1] as usize;
+            let point = grid.position((index % width) as u32, (index / width % height) as u32, (index / (width * height)) as u32);
+            let inside = (0..3).all(|axis| point[axis] > bounds.min[axis] && point[axis] < bounds.max[axis]);
+
+            assert_eq!(*distance < 0.0, inside, "{:?}", point);
+        }
+    }
+
+    #[test]
+    fn damaged_bytes_are_rejected() {
+        let bytes = baked_cube().to_bytes();
+
+        assert!(SdfGrid::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(SdfGrid::from_bytes(&bytes[..HEADER_SIZE - 1]).is_err());
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert!(SdfGrid::from_bytes(&magic).is_err());
+
+        // dimensions whose product overflows
+        let mut huge = bytes.clone();
+        for axis in 0..3 {
+            huge[4 + axis * 4..8 + axis * 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        }
+        assert!(SdfGrid::from_bytes(&huge).is_err());
+
+        let mut empty = bytes[..HEADER_SIZE].to_vec();
+        empty[4..8].copy_from_slice(&0u32.to_le_bytes());
+        assert!(SdfGrid::from_bytes(&empty).is_err());
+    }
+
+    #[test]
+    fn mismatched_distances_are_rejected() {
+        assert!(SdfGrid::new([2, 2, 2], Point3::origin(), 1.0, vec![0.0; 7]).is_err());
+        assert!(SdfGrid::new([0, 2, 2], Point3::origin(), 1.0, Vec::new()).is_err());
+        assert!(SdfGrid::new([2, 2, 2], Point3::origin(), 1.0, vec![0.0; 8]).is_ok());
+    }
+}
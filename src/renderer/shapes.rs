@@ -0,0 +1,479 @@
+use std::{collections::HashMap, f32::consts::PI};
+use nalgebra::{Vector2, Vector3, Vector4};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{cubemap::equirectangular_uv, optimize, vertex::Vertex, Mesh, Primitive};
+
+// every generator gives a single triangle list primitive using material 0, centered on the origin with +Y up
+
+#[derive(Default)]
+struct Builder {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>
+}
+
+// radius and height along the profile of a surface of revolution, with the matching normal
+struct ProfilePoint {
+    radius: f32,
+    height: f32,
+    normal: Vector2<f32>,
+    v: f32
+}
+
+// sin and cos of a fraction of a full turn, exact on quarter turns so seams and poles weld
+fn turn(fraction: f32) -> (f32, f32) {
+    let quarters = fraction * 4.0;
+
+    if quarters != quarters.round() {
+        return (fraction * 2.0 * PI).sin_cos();
+    }
+
+    match (quarters as i32).rem_euclid(4) {
+        0 => (0.0, 1.0),
+        1 => (1.0, 0.0),
+        2 => (0.0, -1.0),
+        _ => (-1.0, 0.0)
+    }
+}
+
+impl Builder {
+    fn vertex(&mut self, position: Vector3<f32>, normal: Vector3<f32>, uv: Vector2<f32>) -> u32 {
+        self.vertices.push(Vertex::new(position, normal, Vector4::zeros(), uv));
+        (self.vertices.len() - 1) as u32
+    }
+
+    // drops degenerate triangles and winds the rest counter clockwise around their vertex normals
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        let position = |x: u32| *self.vertices[x as usize].position();
+        let normal = |x: u32| *self.vertices[x as usize].normal();
+
+        let ab = position(b) - position(a);
+        let ac = position(c) - position(a);
+        let face = ab.cross(&ac);
+
+        if face.norm() <= ab.norm() * ac.norm() * 1e-6 {
+            return;
+        }
+
+        if face.dot(&(normal(a) + normal(b) + normal(c))) >= 0.0 {
+            self.indices.extend_from_slice(&[a, b, c]);
+        } else {
+            self.indices.extend_from_slice(&[a, c, b]);
+        }
+    }
+
+    // (columns + 1) * (rows + 1) vertices, two triangles per cell
+    fn grid<F>(&mut self, columns: u32, rows: u32, vertex: F)
+        where F: Fn(u32, u32) -> (Vector3<f32>, Vector3<f32>, Vector2<f32>) {
+        let start = self.vertices.len() as u32;
+
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (position, normal, uv) = vertex(column, row);
+                self.vertex(position, normal, uv);
+            }
+        }
+
+        let stride = columns + 1;
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let a = start + row * stride + column;
+                let d = a + stride;
+
+                self.triangle(a, a + 1, d + 1);
+                self.triangle(a, d + 1, d);
+            }
+        }
+    }
+
+    // sweeps the profile around +Y, u follows the angle and v comes from the profile
+    fn revolve(&mut self, profile: &[ProfilePoint], segments: u32) {
+        self.grid(segments, profile.len() as u32 - 1, |column, row| {
+            let u = column as f32 / segments as f32;
+            let (sin, cos) = turn(u);
+            let point = &profile[row as usize];
+
+            (Vector3::new(point.radius * cos, point.height, point.radius * sin),
+             Vector3::new(point.normal.x * cos, point.normal.y, point.normal.x * sin),
+             Vector2::new(u, point.v))
+        });
+    }
+
+    // flat disc facing up or down, uvs projected from above
+    fn disc(&mut self, radius: f32, height: f32, up: bool, segments: u32) {
+        let normal = if up { Vector3::y() } else { -Vector3::y() };
+
+        self.grid(segments, 1, |column, row| {
+            let (sin, cos) = turn(column as f32 / segments as f32);
+            let r = radius * row as f32;
+
+            (Vector3::new(r * cos, height, r * sin),
+             normal,
+             Vector2::new(0.5 + 0.5 * row as f32 * cos, 0.5 + 0.5 * row as f32 * sin))
+        });
+    }
+
+    fn build(self) -> Mesh {
+        let mut primitive = Primitive::new(self.vertices, self.indices, 0, PrimitiveTopology::TriangleList);
+        generate_tangents(&mut primitive);
+
+        Mesh::new(vec![primitive])
+    }
+}
+
+// size is the edge length, every face is split in subdivisions * subdivisions quads
+pub fn cube(size: f32, subdivisions: u32) -> Mesh {
+    let subdivisions = subdivisions.max(1);
+    // normal, then the directions u and v grow along so textures stay upright on the sides
+    let faces = [(Vector3::x(), -Vector3::z(), -Vector3::y()),
+                 (-Vector3::x(), Vector3::z(), -Vector3::y()),
+                 (Vector3::y(), Vector3::x(), Vector3::z()),
+                 (-Vector3::y(), Vector3::x(), -Vector3::z()),
+                 (Vector3::z(), Vector3::x(), -Vector3::y()),
+                 (-Vector3::z(), -Vector3::x(), -Vector3::y())];
+
+    let mut builder = Builder::default();
+
+    for (normal, u_axis, v_axis) in faces.iter() {
+        builder.grid(subdivisions, subdivisions, |column, row| {
+            let u = column as f32 / subdivisions as f32;
+            let v = row as f32 / subdivisions as f32;
+
+            (normal * size * 0.5 + u_axis * (u - 0.5) * size + v_axis * (v - 0.5) * size, *normal, Vector2::new(u, v))
+        });
+    }
+
+    builder.build()
+}
+
+// lies in the XZ plane facing +Y
+pub fn plane(width: f32, depth: f32, subdivisions_x: u32, subdivisions_z: u32) -> Mesh {
+    let (columns, rows) = (subdivisions_x.max(1), subdivisions_z.max(1));
+    let mut builder = Builder::default();
+
+    builder.grid(columns, rows, |column, row| {
+        let u = column as f32 / columns as f32;
+        let v = row as f32 / rows as f32;
+
+        (Vector3::new((u - 0.5) * width, 0.0, (v - 0.5) * depth), Vector3::y(), Vector2::new(u, v))
+    });
+
+    builder.build()
+}
+
+// segments around the equator, rings from pole to pole
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Mesh {
+    let rings = rings.max(2);
+    let profile: Vec<ProfilePoint> = (0..=rings)
+        .map(|ring| {
+            let v = ring as f32 / rings as f32;
+            let (sin, cos) = turn(v * 0.5);
+
+            ProfilePoint { radius: radius * sin, height: radius * cos, normal: Vector2::new(sin, cos), v }
+        })
+        .collect();
+
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments.max(3));
+    builder.build()
+}
+
+// subdivided icosahedron, so the triangles stay close to equal sized, with equirectangular uvs
+pub fn icosphere(radius: f32, subdivisions: u32) -> Mesh {
+    let t = (1.0 + 5f32.sqrt()) * 0.5;
+    let mut positions: Vec<Vector3<f32>> = [[-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+                                            [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+                                            [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0]]
+        .iter()
+        .map(|x| Vector3::new(x[0], x[1], x[2]).normalize())
+        .collect();
+
+    let mut triangles: Vec<[u32; 3]> = vec![[0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+                                            [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+                                            [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+                                            [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1]];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push((positions[a as usize] + positions[b as usize]).normalize());
+                (positions.len() - 1) as u32
+            })
+        };
+
+        triangles = triangles
+            .iter()
+            .flat_map(|[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(*a, *b), midpoint(*b, *c), midpoint(*c, *a));
+                vec![[*a, ab, ca], [*b, bc, ab], [*c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // uvs are per corner so triangles crossing the u seam or touching a pole can be fixed up, welding shares them again
+    let mut builder = Builder::default();
+
+    for triangle in triangles.iter() {
+        let corners: Vec<Vector3<f32>> = triangle.iter().map(|x| positions[*x as usize]).collect();
+        let mut uvs: Vec<Vector2<f32>> = corners
+            .iter()
+            .map(|x| {
+                let (u, v) = equirectangular_uv(x);
+                Vector2::new(u, v)
+            })
+            .collect();
+
+        let is_pole: Vec<bool> = corners.iter().map(|x| x.x.abs() < 1e-6 && x.z.abs() < 1e-6).collect();
+        let max_u = (0..3).filter(|x| !is_pole[*x]).map(|x| uvs[x].x).fold(f32::MIN, f32::max);
+
+        for corner in 0..3 {
+            if !is_pole[corner] && max_u - uvs[corner].x > 0.5 {
+                uvs[corner].x += 1.0;
+            }
+        }
+
+        for corner in (0..3).filter(|x| is_pole[*x]) {
+            let others: Vec<usize> = (0..3).filter(|x| !is_pole[*x]).collect();
+            uvs[corner].x = others.iter().map(|x| uvs[*x].x).sum::<f32>() / others.len() as f32;
+        }
+
+        let indices: Vec<u32> = (0..3).map(|x| builder.vertex(corners[x] * radius, corners[x], uvs[x])).collect();
+        builder.triangle(indices[0], indices[1], indices[2]);
+    }
+
+    let mut primitive = Primitive::new(builder.vertices, builder.indices, 0, PrimitiveTopology::TriangleList);
+    optimize::weld_vertices(&mut primitive, 1e-6);
+    generate_tangents(&mut primitive);
+
+    Mesh::new(vec![primitive])
+}
+
+// caps at +-height / 2
+pub fn cylinder(radius: f32, height: f32, segments: u32, height_segments: u32) -> Mesh {
+    let segments = segments.max(3);
+    let rows = height_segments.max(1);
+    let profile: Vec<ProfilePoint> = (0..=rows)
+        .map(|row| {
+            let v = row as f32 / rows as f32;
+            ProfilePoint { radius, height: height * (0.5 - v), normal: Vector2::new(1.0, 0.0), v }
+        })
+        .collect();
+
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments);
+    builder.disc(radius, height * 0.5, true, segments);
+    builder.disc(radius, -height * 0.5, false, segments);
+    builder.build()
+}
+
+// apex at +height / 2, base cap at -height / 2
+pub fn cone(radius: f32, height: f32, segments: u32, height_segments: u32) -> Mesh {
+    let segments = segments.max(3);
+    let rows = height_segments.max(1);
+    let slope = Vector2::new(height, radius).normalize();
+    let profile: Vec<ProfilePoint> = (0..=rows)
+        .map(|row| {
+            let v = row as f32 / rows as f32;
+            ProfilePoint { radius: radius * v, height: height * (0.5 - v), normal: slope, v }
+        })
+        .collect();
+
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments);
+    builder.disc(radius, -height * 0.5, false, segments);
+    builder.build()
+}
+
+// height is the straight part only, rings is per hemisphere, v follows the arc length
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Mesh {
+    let rings = rings.max(1);
+    let length = PI * radius + height;
+    let quarter = PI * 0.5 * radius;
+
+    let mut profile: Vec<ProfilePoint> = Vec::with_capacity(2 * rings as usize + 2);
+
+    for (center, first_turn, first_arc) in [(height * 0.5, 0.0, 0.0), (-height * 0.5, 0.25, quarter + height)].iter() {
+        for ring in 0..=rings {
+            let fraction = ring as f32 / rings as f32;
+            let (sin, cos) = turn(first_turn + fraction * 0.25);
+
+            profile.push(ProfilePoint {
+                radius: radius * sin,
+                height: center + radius * cos,
+                normal: Vector2::new(sin, cos),
+                v: (first_arc + fraction * quarter) / length
+            });
+        }
+    }
+
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments.max(3));
+    builder.build()
+}
+
+// lies around +Y, major segments go around the ring and minor ones around the tube
+pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> Mesh {
+    let rows = minor_segments.max(3);
+    let profile: Vec<ProfilePoint> = (0..=rows)
+        .map(|row| {
+            let v = row as f32 / rows as f32;
+            let (sin, cos) = turn(v);
+
+            ProfilePoint {
+                radius: major_radius + minor_radius * cos,
+                height: minor_radius * sin,
+                normal: Vector2::new(cos, sin),
+                v
+            }
+        })
+        .collect();
+
+    let mut builder = Builder::default();
+    builder.revolve(&profile, major_segments.max(3));
+    builder.build()
+}
+
+// per vertex tangents from the uv derivatives of the surrounding triangles, w holds the bitangent sign
+pub fn generate_tangents(primitive: &mut Primitive) {
+    if primitive.mode != PrimitiveTopology::TriangleList {
+        return;
+    }
+
+    let vertex_count = primitive.vertex().len();
+    let indices: Vec<u32> = if primitive.indices().is_empty() {
+        (0..vertex_count as u32).collect()
+    } else {
+        primitive.indices().to_vec()
+    };
+    let mut tangents: Vec<Vector3<f32>> = vec![Vector3::zeros(); vertex_count];
+    let mut bitangents: Vec<Vector3<f32>> = vec![Vector3::zeros(); vertex_count];
+
+    for triangle in indices.chunks_exact(3) {
+        let vertex = |corner: usize| &primitive.vertex()[triangle[corner] as usize];
+
+        let edge_1 = vertex(1).position() - vertex(0).position();
+        let edge_2 = vertex(2).position() - vertex(0).position();
+        let delta_1 = vertex(1).uv() - vertex(0).uv();
+        let delta_2 = vertex(2).uv() - vertex(0).uv();
+
+        let determinant = delta_1.x * delta_2.y - delta_2.x * delta_1.y;
+
+        if determinant == 0.0 {
+            continue;
+        }
+
+        let tangent = (edge_1 * delta_2.y - edge_2 * delta_1.y) / determinant;
+        let bitangent = (edge_2 * delta_1.x - edge_1 * delta_2.x) / determinant;
+
+        for index in triangle {
+            tangents[*index as usize] += tangent;
+            bitangents[*index as usize] += bitangent;
+        }
+    }
+
+    for (i, vertex) in primitive.vertex_mut().iter_mut().enumerate() {
+        let normal = *vertex.normal();
+        let mut tangent = tangents[i] - normal * normal.dot(&tangents[i]);
+
+        // no usable uvs around this vertex, any direction in the tangent plane will do
+        if tangent.norm_squared() <= f32::EPSILON {
+            let axis = if normal.x.abs() < 0.9 { Vector3::x() } else { Vector3::y() };
+            tangent = axis - normal * normal.dot(&axis);
+        }
+
+        let tangent = tangent.normalize();
+        let handedness = if normal.cross(&tangent).dot(&bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+
+        vertex.set_tangent(Vector4::new(tangent.x, tangent.y, tangent.z, handedness));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::halfedge::HalfEdgeMesh;
+
+    fn all_shapes() -> Vec<(&'static str, Mesh)> {
+        vec![("cube", cube(1.0, 2)),
+             ("plane", plane(2.0, 1.0, 3, 2)),
+             ("uv sphere", uv_sphere(1.0, 8, 4)),
+             ("icosphere", icosphere(1.0, 1)),
+             ("cylinder", cylinder(0.5, 2.0, 8, 2)),
+             ("cone", cone(0.5, 1.0, 8, 2)),
+             ("capsule", capsule(0.5, 1.0, 8, 3)),
+             ("torus", torus(2.0, 0.5, 8, 6))]
+    }
+
+    #[test]
+    fn vertex_and_triangle_counts() {
+        let counts: Vec<(usize, usize)> = [cube(1.0, 2), plane(2.0, 1.0, 3, 2), uv_sphere(1.0, 8, 4), icosphere(1.0, 1), torus(2.0, 0.5, 8, 6)]
+            .iter()
+            .map(|x| (x.primitives[0].vertex().len(), x.primitives[0].indices().len() / 3))
+            .collect();
+
+        // the sphere drops the triangles that collapse onto its poles, the icosphere keeps its seam and pole wedges
+        assert_eq!(counts[0], (6 * 9, 6 * 8));
+        assert_eq!(counts[1], (4 * 3, 3 * 2 * 2));
+        assert_eq!(counts[2], (9 * 5, 8 * 4 * 2 - 2 * 8));
+        assert_eq!(counts[3].1, 80);
+        assert_eq!(counts[4], (9 * 7, 8 * 6 * 2));
+    }
+
+    #[test]
+    fn solids_are_closed_and_wound_outwards() {
+        for (name, mesh) in all_shapes() {
+            let primitive = &mesh.primitives[0];
+            let half_edges = HalfEdgeMesh::from_primitive(primitive).unwrap();
+
+            assert!(half_edges.is_manifold(), "{} is not manifold", name);
+            assert_eq!(half_edges.is_closed(), name != "plane", "{}", name);
+
+            for triangle in primitive.indices().to_vec().chunks_exact(3) {
+                let vertex = |x: usize| &primitive.vertex()[triangle[x] as usize];
+                let face = (vertex(1).position() - vertex(0).position()).cross(&(vertex(2).position() - vertex(0).position()));
+                assert!(face.dot(&(vertex(0).normal() + vertex(1).normal() + vertex(2).normal())) > 0.0, "{}", name);
+            }
+        }
+
+        assert_eq!(HalfEdgeMesh::from_primitive(&plane(1.0, 1.0, 2, 2).primitives[0]).unwrap().boundary_loops().len(), 1);
+    }
+
+    #[test]
+    fn normals_and_tangents_are_unit_and_orthogonal() {
+        for (name, mesh) in all_shapes() {
+            for vertex in mesh.primitives[0].vertex().iter() {
+                let tangent = vertex.tangent().xyz();
+
+                assert!((vertex.normal().norm() - 1.0).abs() < 1e-5, "{}", name);
+                assert!((tangent.norm() - 1.0).abs() < 1e-5, "{}", name);
+                assert!(tangent.dot(vertex.normal()).abs() < 1e-5, "{}", name);
+                assert_eq!(vertex.tangent().w.abs(), 1.0, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn tangents_without_indices_match_the_indexed_ones() {
+        let indexed = plane(2.0, 2.0, 2, 2);
+        let source = &indexed.primitives[0];
+
+        let vertices: Vec<Vertex> = source.indices()
+            .iter()
+            .map(|x| {
+                let mut vertex = source.vertex()[x as usize];
+                vertex.set_tangent(Vector4::zeros());
+                vertex
+            })
+            .collect();
+
+        let mut unindexed = Primitive::new(vertices, Vec::new(), 0, PrimitiveTopology::TriangleList);
+        generate_tangents(&mut unindexed);
+
+        for (vertex, index) in unindexed.vertex().iter().zip(source.indices().iter()) {
+            assert_eq!(vertex.tangent(), source.vertex()[index as usize].tangent());
+            // u runs along +X and v along +Z, while normal x tangent points to -Z
+            assert!((vertex.tangent() - Vector4::new(1.0, 0.0, 0.0, -1.0)).norm() < 1e-5);
+        }
+    }
+}
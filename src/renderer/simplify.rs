@@ -0,0 +1,657 @@
+use std::{cmp::Ordering, collections::{BinaryHeap, HashMap, HashSet}};
+use nalgebra::{Point3, Vector3};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{vertex::Vertex, Lod, Mesh, Primitive};
+
+// border planes are weighted so sliding along a border is much cheaper than pulling it inwards
+const BORDER_WEIGHT: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimplifyOptions {
+    pub target_triangles: usize,
+    // collapses stop once the square root of their quadric cost passes this, in model units
+    pub max_error: f32,
+    pub lock_borders: bool
+}
+
+pub struct Simplified {
+    pub primitive: Primitive,
+    // square root of the largest quadric cost among the collapses made
+    pub error: f32
+}
+
+impl Default for SimplifyOptions {
+    fn default() -> Self {
+        Self {
+            target_triangles: 0,
+            max_error: f32::MAX,
+            lock_borders: false
+        }
+    }
+}
+
+impl SimplifyOptions {
+    pub fn with_target_triangles(target_triangles: usize) -> Self {
+        Self {
+            target_triangles,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_max_error(max_error: f32) -> Self {
+        Self {
+            max_error,
+            ..Default::default()
+        }
+    }
+}
+
+// symmetric 4x4 matrix, upper triangle stored row by row
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: &Vector3<f64>, distance: f64, weight: f64) -> Self {
+        let (a, b, c, d) = (normal.x, normal.y, normal.z, distance);
+
+        Quadric([a * a * weight, a * b * weight, a * c * weight, a * d * weight,
+                 b * b * weight, b * c * weight, b * d * weight,
+                 c * c * weight, c * d * weight,
+                 d * d * weight])
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+    }
+
+    fn error(&self, point: &Point3<f32>) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (point.x as f64, point.y as f64, point.z as f64);
+
+        let error = q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x +
+            q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y +
+            q[7] * z * z + 2.0 * q[8] * z +
+            q[9];
+
+        error.max(0.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    version: u32
+}
+
+// ordered by cost alone, total_cmp keeps a NaN cost from breaking the heap order
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+// BinaryHeap is a max heap, the cheapest collapse has to come out first
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VertexKind {
+    Manifold,
+    Border,
+    // several attribute vertices share the position, or the topology around it is not manifold
+    Locked
+}
+
+struct Simplifier<'a> {
+    vertices: &'a [Vertex],
+    options: SimplifyOptions,
+    // attribute vertex to welded position
+    position_of: Vec<u32>,
+    positions: Vec<Point3<f32>>,
+    kinds: Vec<VertexKind>,
+    quadrics: Vec<Quadric>,
+    // attribute indices, None once collapsed away
+    triangles: Vec<Option<[u32; 3]>>,
+    incident: Vec<Vec<u32>>,
+    border_edges: HashSet<(u32, u32)>,
+    versions: Vec<u32>,
+    alive_triangles: usize
+}
+
+// half edge collapses keep the surviving vertex untouched, so no attribute is ever interpolated
+pub fn simplify(primitive: &Primitive, options: &SimplifyOptions) -> Simplified {
+    if primitive.mode != PrimitiveTopology::TriangleList || primitive.indices().len() < 3 {
+        return Simplified {
+            primitive: primitive.select_vertices(&(0..primitive.vertex().len() as u32).collect::<Vec<u32>>(), primitive.indices().to_vec()),
+            error: 0.0
+        };
+    }
+
+    let mut simplifier = Simplifier::new(primitive.vertex(), &primitive.indices().to_vec(), *options);
+    let error = simplifier.run();
+
+    let (sources, indices) = simplifier.output();
+
+    Simplified {
+        primitive: primitive.select_vertices(&sources, indices),
+        error
+    }
+}
+
+fn to_f64(point: &Point3<f32>) -> Vector3<f64> {
+    Vector3::new(point.x as f64, point.y as f64, point.z as f64)
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+impl<'a> Simplifier<'a> {
+    fn new(vertices: &'a [Vertex], indices: &[u32], options: SimplifyOptions) -> Self {
+        let mut welded: HashMap<[u32; 3], u32> = HashMap::new();
+        let mut positions: Vec<Point3<f32>> = Vec::new();
+        let mut attribute_count: Vec<u32> = Vec::new();
+
+        let position_of: Vec<u32> = vertices
+            .iter()
+            .map(|vertex| {
+                // adding zero turns -0.0 into 0.0 so both sides of a seam weld
+                let position = vertex.position().add_scalar(0.0);
+                let key = [position.x.to_bits(), position.y.to_bits(), position.z.to_bits()];
+
+                *welded.entry(key).or_insert_with(|| {
+                    positions.push(Point3::from(position));
+                    attribute_count.push(0);
+                    (positions.len() - 1) as u32
+                })
+            })
+            .collect();
+
+        let triangles: Vec<Option<[u32; 3]>> = indices
+            .chunks(3)
+            .filter(|x| x.len() == 3)
+            .map(|x| [x[0], x[1], x[2]])
+            .filter(|x| {
+                let p = [position_of[x[0] as usize], position_of[x[1] as usize], position_of[x[2] as usize]];
+                p[0] != p[1] && p[1] != p[2] && p[0] != p[2]
+            })
+            .map(Some)
+            .collect();
+
+        let mut simplifier = Self {
+            vertices,
+            options,
+            position_of,
+            kinds: vec![VertexKind::Manifold; positions.len()],
+            quadrics: vec![Quadric::default(); positions.len()],
+            incident: vec![Vec::new(); positions.len()],
+            versions: vec![0; positions.len()],
+            alive_triangles: triangles.len(),
+            border_edges: HashSet::new(),
+            positions,
+            triangles
+        };
+
+        simplifier.classify(&mut attribute_count);
+        simplifier.build_quadrics();
+
+        simplifier
+    }
+
+    fn triangle_positions(&self, triangle: &[u32; 3]) -> [u32; 3] {
+        [self.position_of[triangle[0] as usize], self.position_of[triangle[1] as usize], self.position_of[triangle[2] as usize]]
+    }
+
+    fn classify(&mut self, attribute_count: &mut [u32]) {
+        let mut edge_use: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut seen_attributes: HashSet<u32> = HashSet::new();
+
+        for (index, triangle) in self.triangles.iter().enumerate() {
+            let triangle = triangle.unwrap();
+            let p = self.triangle_positions(&triangle);
+
+            for corner in 0..3 {
+                self.incident[p[corner] as usize].push(index as u32);
+                *edge_use.entry(edge_key(p[corner], p[(corner + 1) % 3])).or_insert(0) += 1;
+
+                if seen_attributes.insert(triangle[corner]) {
+                    attribute_count[p[corner] as usize] += 1;
+                }
+            }
+        }
+
+        for ((a, b), count) in edge_use {
+            match count {
+                1 => {
+                    self.border_edges.insert((a, b));
+                    for vertex in [a, b].iter() {
+                        if self.kinds[*vertex as usize] == VertexKind::Manifold {
+                            self.kinds[*vertex as usize] = VertexKind::Border;
+                        }
+                    }
+                }
+                2 => {}
+                _ => {
+                    self.kinds[a as usize] = VertexKind::Locked;
+                    self.kinds[b as usize] = VertexKind::Locked;
+                }
+            }
+        }
+
+        for (position, count) in attribute_count.iter().enumerate() {
+            let border_locked = self.options.lock_borders && self.kinds[position] == VertexKind::Border;
+
+            if *count > 1 || border_locked {
+                self.kinds[position] = VertexKind::Locked;
+            }
+        }
+    }
+
+    fn build_quadrics(&mut self) {
+        for triangle in self.triangles.iter().flatten() {
+            let p = self.triangle_positions(triangle);
+            let a = to_f64(&self.positions[p[0] as usize]);
+            let b = to_f64(&self.positions[p[1] as usize]);
+            let c = to_f64(&self.positions[p[2] as usize]);
+
+            let normal = (b - a).cross(&(c - a));
+            let length = normal.norm();
+            if length <= f64::EPSILON {
+                continue;
+            }
+
+            let normal = normal / length;
+            let quadric = Quadric::from_plane(&normal, -normal.dot(&a), 1.0);
+
+            for corner in 0..3 {
+                self.quadrics[p[corner] as usize].add(&quadric);
+
+                let next = p[(corner + 1) % 3];
+                if !self.border_edges.contains(&edge_key(p[corner], next)) {
+                    continue;
+                }
+
+                // plane through the border edge, perpendicular to the triangle
+                let start = to_f64(&self.positions[p[corner] as usize]);
+                let end = to_f64(&self.positions[next as usize]);
+                let edge = end - start;
+                let edge_length = edge.norm();
+                if edge_length <= f64::EPSILON {
+                    continue;
+                }
+
+                let border_normal = edge.cross(&normal) / edge_length;
+                let border_quadric = Quadric::from_plane(&border_normal, -border_normal.dot(&start), BORDER_WEIGHT);
+                self.quadrics[p[corner] as usize].add(&border_quadric);
+                self.quadrics[next as usize].add(&border_quadric);
+            }
+        }
+    }
+
+    fn neighbours(&self, position: u32) -> HashSet<u32> {
+        self.incident[position as usize]
+            .iter()
+            .filter_map(|x| self.triangles[*x as usize])
+            .flat_map(|x| self.triangle_positions(&x).to_vec())
+            .filter(|x| *x != position)
+            .collect()
+    }
+
+    fn best_collapse(&self, from: u32) -> Option<Collapse> {
+        let kind = self.kinds[from as usize];
+        if kind == VertexKind::Locked {
+            return None;
+        }
+
+        self.neighbours(from)
+            .into_iter()
+            .filter(|to| kind != VertexKind::Border || self.border_edges.contains(&edge_key(from, *to)))
+            .map(|to| {
+                let mut quadric = self.quadrics[from as usize];
+                quadric.add(&self.quadrics[to as usize]);
+
+                Collapse {
+                    cost: quadric.error(&self.positions[to as usize]),
+                    from,
+                    to,
+                    version: self.versions[from as usize]
+                }
+            })
+            .min_by(|a, b| a.cost.total_cmp(&b.cost))
+    }
+
+    // the attribute vertex of `to` that replaces `from`, if the collapse stays on one side of any seam
+    fn target_attribute(&self, from: u32, to: u32) -> Option<u32> {
+        let mut target: Option<u32> = None;
+
+        for triangle in self.incident[from as usize].iter().filter_map(|x| self.triangles[*x as usize]) {
+            for attribute in triangle.iter() {
+                if self.position_of[*attribute as usize] != to {
+                    continue;
+                }
+
+                match target {
+                    Some(existing) if existing != *attribute => return None,
+                    _ => target = Some(*attribute)
+                }
+            }
+        }
+
+        target
+    }
+
+    fn is_valid(&self, from: u32, to: u32) -> bool {
+        let shared: Vec<[u32; 3]> = self.incident[from as usize]
+            .iter()
+            .filter_map(|x| self.triangles[*x as usize])
+            .filter(|x| self.triangle_positions(x).contains(&to))
+            .collect();
+
+        if shared.is_empty() {
+            return false;
+        }
+
+        // link condition, otherwise the collapse pinches the surface
+        let common = self.neighbours(from).intersection(&self.neighbours(to)).count();
+        if common != shared.len() {
+            return false;
+        }
+
+        let target = self.positions[to as usize];
+
+        for triangle in self.incident[from as usize].iter().filter_map(|x| self.triangles[*x as usize]) {
+            let p = self.triangle_positions(&triangle);
+            if p.contains(&to) {
+                continue;
+            }
+
+            let corners: Vec<Point3<f32>> = p.iter().map(|x| self.positions[*x as usize]).collect();
+            let moved: Vec<Point3<f32>> = p.iter().map(|x| if *x == from { target } else { self.positions[*x as usize] }).collect();
+
+            let before = (corners[1] - corners[0]).cross(&(corners[2] - corners[0]));
+            let after = (moved[1] - moved[0]).cross(&(moved[2] - moved[0]));
+
+            // flipped or collapsed to a sliver
+            if after.dot(&before) <= 0.0 || after.norm() <= before.norm() * 1e-3 {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn run(&mut self) -> f32 {
+        let mut heap: BinaryHeap<Collapse> = (0..self.positions.len() as u32)
+            .filter_map(|x| self.best_collapse(x))
+            .collect();
+
+        let max_cost = (self.options.max_error as f64).powi(2);
+        let mut error = 0f64;
+
+        while self.alive_triangles > self.options.target_triangles {
+            let collapse = match heap.pop() {
+                Some(collapse) => collapse,
+                None => break
+            };
+
+            if collapse.version != self.versions[collapse.from as usize] {
+                continue;
+            }
+
+            if collapse.cost > max_cost {
+                break;
+            }
+
+            let attribute = match self.target_attribute(collapse.from, collapse.to) {
+                Some(attribute) if self.is_valid(collapse.from, collapse.to) => attribute,
+                _ => continue
+            };
+
+            error = error.max(collapse.cost);
+            self.apply(collapse.from, collapse.to, attribute);
+
+            let mut touched = self.neighbours(collapse.to);
+            touched.insert(collapse.to);
+
+            for vertex in touched {
+                self.versions[vertex as usize] += 1;
+                if let Some(next) = self.best_collapse(vertex) {
+                    heap.push(next);
+                }
+            }
+        }
+
+        error.sqrt() as f32
+    }
+
+    fn apply(&mut self, from: u32, to: u32, attribute: u32) {
+        let incident = std::mem::take(&mut self.incident[from as usize]);
+
+        for index in incident {
+            let triangle = match self.triangles[index as usize] {
+                Some(triangle) => triangle,
+                None => continue
+            };
+
+            let p = self.triangle_positions(&triangle);
+
+            if p.contains(&to) {
+                self.triangles[index as usize] = None;
+                self.alive_triangles -= 1;
+
+                for position in p.iter().filter(|x| **x != from) {
+                    self.incident[*position as usize].retain(|x| *x != index);
+                }
+                continue;
+            }
+
+            let mut updated = triangle;
+            for corner in updated.iter_mut() {
+                if self.position_of[*corner as usize] == from {
+                    *corner = attribute;
+                }
+            }
+
+            self.triangles[index as usize] = Some(updated);
+            self.incident[to as usize].push(index);
+        }
+
+        let quadric = self.quadrics[from as usize];
+        self.quadrics[to as usize].add(&quadric);
+        self.versions[from as usize] += 1;
+
+        // the borders of the collapsed edge now end at the surviving vertex
+        let moved: Vec<(u32, u32)> = self.border_edges
+            .iter()
+            .filter(|(a, b)| *a == from || *b == from)
+            .copied()
+            .collect();
+
+        for (a, b) in moved {
+            self.border_edges.remove(&(a, b));
+            let other = if a == from { b } else { a };
+            if other != to {
+                self.border_edges.insert(edge_key(other, to));
+            }
+        }
+    }
+
+    // the surviving source vertices and the indices into them
+    fn output(&self) -> (Vec<u32>, Vec<u32>) {
+        let mut remap: Vec<Option<u32>> = vec![None; self.vertices.len()];
+        let mut sources: Vec<u32> = Vec::new();
+        let mut indices: Vec<u32> = Vec::with_capacity(self.alive_triangles * 3);
+
+        for triangle in self.triangles.iter().flatten() {
+            for attribute in triangle.iter() {
+                let index = *remap[*attribute as usize].get_or_insert_with(|| {
+                    sources.push(*attribute);
+                    (sources.len() - 1) as u32
+                });
+
+                indices.push(index);
+            }
+        }
+
+        (sources, indices)
+    }
+}
+
+impl Mesh {
+    // every level keeps `ratio` of the triangles of the one before, simplified from the full detail mesh,
+    // options.target_triangles acts as a floor for every primitive
+    pub fn generate_lods(&mut self, levels: usize, ratio: f32, options: &SimplifyOptions) {
+        self.lods.clear();
+
+        let mut fraction = 1.0;
+
+        for _ in 0..levels {
+            fraction *= ratio;
+            let mut error = 0f32;
+
+            let primitives = self.primitives
+                .iter()
+                .map(|primitive| {
+                    let target = (primitive.indices().len() as f32 / 3.0 * fraction) as usize;
+                    let simplified = simplify(primitive, &SimplifyOptions {
+                        target_triangles: target.max(options.target_triangles),
+                        ..*options
+                    });
+
+                    error = error.max(simplified.error);
+                    simplified.primitive
+                })
+                .collect();
+
+            self.lods.push(Lod::new(primitives, error));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::shapes;
+
+    fn key(position: &Vector3<f32>) -> [u32; 3] {
+        let position = position.add_scalar(0.0);
+        [position.x.to_bits(), position.y.to_bits(), position.z.to_bits()]
+    }
+
+    // directed edges over welded positions, a closed manifold uses every one once and its reverse once
+    fn is_closed(primitive: &Primitive) -> bool {
+        let mut edges: HashMap<([u32; 3], [u32; 3]), usize> = HashMap::new();
+        let indices = primitive.indices().to_vec();
+
+        for triangle in indices.chunks_exact(3) {
+            for corner in 0..3 {
+                let a = key(primitive.vertex()[triangle[corner] as usize].position());
+                let b = key(primitive.vertex()[triangle[(corner + 1) % 3] as usize].position());
+                *edges.entry((a, b)).or_default() += 1;
+            }
+        }
+
+        edges.iter().all(|((a, b), count)| *count == 1 && edges.get(&(*b, *a)) == Some(&1))
+    }
+
+    fn positions(primitive: &Primitive) -> HashSet<[u32; 3]> {
+        primitive.vertex().iter().map(|x| key(x.position())).collect()
+    }
+
+    #[test]
+    fn closed_mesh_stays_closed() {
+        let sphere = &shapes::icosphere(1.0, 3).primitives[0];
+        let triangles = sphere.indices().len() / 3;
+        assert!(is_closed(sphere));
+
+        let simplified = simplify(sphere, &SimplifyOptions::with_target_triangles(triangles / 4));
+
+        assert!(simplified.primitive.indices().len() / 3 <= triangles / 4);
+        assert!(is_closed(&simplified.primitive));
+    }
+
+    #[test]
+    fn seam_vertices_stay_in_place() {
+        // the cube's edges are seams, its vertices there have one wedge per face
+        let cube = &shapes::cube(2.0, 4).primitives[0];
+        let on_edge = |x: &[u32; 3]| x.iter().filter(|x| f32::from_bits(**x).abs() == 1.0).count() >= 2;
+        let seams: HashSet<[u32; 3]> = positions(cube).into_iter().filter(on_edge).collect();
+
+        let simplified = simplify(cube, &SimplifyOptions::with_target_triangles(12));
+        let kept = positions(&simplified.primitive);
+
+        assert!(simplified.primitive.indices().len() < cube.indices().len());
+        assert!(seams.is_subset(&kept));
+        assert!(is_closed(&simplified.primitive));
+    }
+
+    #[test]
+    fn locked_borders_stay_in_place() {
+        let plane = &shapes::plane(2.0, 2.0, 8, 8).primitives[0];
+        let on_border = |x: &[u32; 3]| f32::from_bits(x[0]).abs() == 1.0 || f32::from_bits(x[2]).abs() == 1.0;
+        let border: HashSet<[u32; 3]> = positions(plane).into_iter().filter(on_border).collect();
+
+        let options = SimplifyOptions {
+            target_triangles: 2,
+            lock_borders: true,
+            ..Default::default()
+        };
+        let simplified = simplify(plane, &options);
+        let kept = positions(&simplified.primitive);
+
+        assert!(simplified.primitive.indices().len() < plane.indices().len());
+        assert_eq!(kept.iter().filter(|x| on_border(x)).cloned().collect::<HashSet<[u32; 3]>>(), border);
+    }
+
+    #[test]
+    fn lod_errors_never_decrease() {
+        let mut mesh = shapes::icosphere(1.0, 4);
+        mesh.generate_lods(5, 0.5, &SimplifyOptions::default());
+
+        assert_eq!(mesh.lods.len(), 5);
+
+        let mut previous = (mesh.primitives[0].indices().len(), 0.0);
+
+        for lod in mesh.lods.iter() {
+            let triangles = lod.primitives[0].indices().len();
+
+            assert!(triangles < previous.0, "{} triangles after {}", triangles, previous.0);
+            assert!(lod.error >= previous.1, "error {} after {}", lod.error, previous.1);
+            previous = (triangles, lod.error);
+        }
+    }
+
+    #[test]
+    fn nan_costs_keep_the_heap_ordered() {
+        let costs = [3.0, f64::NAN, 1.0, 2.0, f64::NAN, 0.5];
+        let mut heap: BinaryHeap<Collapse> = costs
+            .iter()
+            .map(|x| Collapse { cost: *x, from: 0, to: 1, version: 0 })
+            .collect();
+
+        let mut popped: Vec<f64> = Vec::new();
+        while let Some(collapse) = heap.pop() {
+            popped.push(collapse.cost);
+        }
+
+        // finite costs come out cheapest first, NaN sorts after every one of them
+        assert_eq!(&popped[..4], &[0.5, 1.0, 2.0, 3.0]);
+        assert!(popped[4..].iter().all(|x| x.is_nan()));
+    }
+}
+
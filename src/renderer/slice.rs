@@ -0,0 +1,676 @@
+use std::collections::HashMap;
+use nalgebra::{Point2, Point3, Vector2, Vector3};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{layout::Semantic, shapes, vertex::Vertex, Primitive, RenderError};
+
+// points p with normal . p = distance, the normal points to the above side
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub distance: f32
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polyline {
+    pub points: Vec<Point3<f32>>,
+    // the last point connects back to the first
+    pub closed: bool
+}
+
+pub struct Slice {
+    pub above: Primitive,
+    pub below: Primitive,
+    // where the surface crosses the plane, loops of a closed surface come back closed
+    pub outlines: Vec<Polyline>,
+    // fill the closed outlines, facing away from the half they close
+    pub above_cap: Option<Primitive>,
+    pub below_cap: Option<Primitive>
+}
+
+// a corner of a clipped triangle, either a source vertex or a point on a crossing edge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Corner {
+    Vertex(u32),
+    // from the vertex below to the one above
+    Cut(u32, u32)
+}
+
+impl Plane {
+    pub fn new(normal: Vector3<f32>, distance: f32) -> Self {
+        let length = normal.norm();
+
+        Self {
+            normal: normal / length,
+            distance: distance / length
+        }
+    }
+
+    pub fn from_point_normal(point: &Point3<f32>, normal: &Vector3<f32>) -> Self {
+        let normal = normal.normalize();
+
+        Self {
+            normal,
+            distance: normal.dot(&point.coords)
+        }
+    }
+
+    pub fn signed_distance(&self, point: &Point3<f32>) -> f32 {
+        self.normal.dot(&point.coords) - self.distance
+    }
+
+    // orthonormal u and v with u x v = normal
+    fn basis(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let axis = if self.normal.x.abs() < 0.9 { Vector3::x() } else { Vector3::y() };
+        let u = (axis - self.normal * self.normal.dot(&axis)).normalize();
+
+        (u, self.normal.cross(&u))
+    }
+}
+
+// position bits with -0.0 folded into 0.0, so points cut from either side of a seam match
+fn point_key(point: &Point3<f32>) -> [u32; 3] {
+    let point = point.coords.add_scalar(0.0);
+    [point.x.to_bits(), point.y.to_bits(), point.z.to_bits()]
+}
+
+fn lerp(a: &[f32; 4], b: &[f32; 4], t: f32) -> [f32; 4] {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t, a[3] + (b[3] - a[3]) * t]
+}
+
+// splits a triangle list by the plane, every attribute is interpolated at the cuts
+// vertices on the plane end up in both halves, faces lying in it go above
+pub fn slice(primitive: &Primitive, plane: &Plane, cap: bool) -> Result<Slice, RenderError> {
+    if primitive.mode != PrimitiveTopology::TriangleList {
+        return Err(RenderError::Geometry(format!("slicing needs a triangle list, got {:?}", primitive.mode)));
+    }
+
+    let indices: Vec<u32> = if primitive.indices().is_empty() {
+        (0..primitive.vertex().len() as u32).collect()
+    } else {
+        primitive.indices().to_vec()
+    };
+
+    let triangles = indices.chunks_exact(3);
+    if !triangles.remainder().is_empty() {
+        return Err(RenderError::Geometry(format!("{} indices do not make whole triangles", indices.len())));
+    }
+
+    // distances this close to the plane snap onto it, relative to the size of the primitive
+    let bounds = primitive.bounds().aabb;
+    let snap = if bounds.is_empty() { 0.0 } else { bounds.size().norm() * 1e-6 };
+    let distances: Vec<f32> = primitive.vertex()
+        .iter()
+        .map(|x| plane.signed_distance(&Point3::from(*x.position())))
+        .map(|x| if x.abs() <= snap { 0.0 } else { x })
+        .collect();
+
+    // on the plane counts as above, so every crossing edge has one vertex strictly below
+    let is_above = |vertex: u32| distances[vertex as usize] >= 0.0;
+
+    let cut = |below: u32, above: u32| {
+        if distances[above as usize] == 0.0 { Corner::Vertex(above) } else { Corner::Cut(below, above) }
+    };
+
+    let mut above_faces: Vec<Vec<Corner>> = Vec::new();
+    let mut below_faces: Vec<Vec<Corner>> = Vec::new();
+    let mut segments: Vec<(Corner, Corner)> = Vec::new();
+
+    for triangle in triangles {
+        let mut above: Vec<Corner> = Vec::with_capacity(4);
+        let mut below: Vec<Corner> = Vec::with_capacity(4);
+        let mut enter: Option<Corner> = None;
+        let mut exit: Option<Corner> = None;
+
+        for corner in 0..3 {
+            let (from, to) = (triangle[corner], triangle[(corner + 1) % 3]);
+
+            if is_above(from) {
+                above.push(Corner::Vertex(from));
+            }
+
+            if !is_above(from) || distances[from as usize] == 0.0 {
+                below.push(Corner::Vertex(from));
+            }
+
+            match (is_above(from), is_above(to)) {
+                (true, false) => {
+                    let point = cut(to, from);
+                    above.push(point);
+                    below.push(point);
+                    exit = Some(point);
+                }
+                (false, true) => {
+                    let point = cut(from, to);
+                    above.push(point);
+                    below.push(point);
+                    enter = Some(point);
+                }
+                _ => {}
+            }
+        }
+
+        // nothing strictly below means the face lies above or in the plane
+        if triangle.iter().all(|x| is_above(*x)) {
+            below.clear();
+        }
+
+        above_faces.push(above);
+        below_faces.push(below);
+
+        if let (Some(enter), Some(exit)) = (enter, exit) {
+            segments.push((enter, exit));
+        }
+    }
+
+    let mut cuts: Vec<(u32, u32)> = Vec::new();
+    let mut cut_index: HashMap<(u32, u32), u32> = HashMap::new();
+
+    for corner in above_faces.iter().chain(below_faces.iter()).flatten() {
+        if let Corner::Cut(below, above) = corner {
+            cut_index.entry((*below, *above)).or_insert_with(|| {
+                cuts.push((*below, *above));
+                (cuts.len() - 1) as u32
+            });
+        }
+    }
+
+    let combined = with_cuts(primitive, &distances, &cuts);
+    let vertex_count = primitive.vertex().len() as u32;
+    let resolve = |corner: &Corner| match corner {
+        Corner::Vertex(vertex) => *vertex,
+        Corner::Cut(below, above) => vertex_count + cut_index[&(*below, *above)]
+    };
+
+    let above = half(&combined, &above_faces, &resolve);
+    let below = half(&combined, &below_faces, &resolve);
+
+    let position = |corner: &Corner| Point3::from(*combined.vertex()[resolve(corner) as usize].position());
+    let segments: Vec<(Point3<f32>, Point3<f32>)> = segments.iter().map(|(a, b)| (position(a), position(b))).collect();
+    let outlines = chain(&segments);
+
+    let (above_cap, below_cap) = if cap {
+        let closed: Vec<&Polyline> = outlines.iter().filter(|x| x.closed).collect();
+        match cap_primitive(&closed, plane, primitive.material_index) {
+            Some(below_cap) => {
+                let mut above_cap = below_cap.select_vertices(&(0..below_cap.vertex().len() as u32).collect::<Vec<u32>>(), Vec::new());
+                let flipped: Vec<u32> = below_cap.indices().to_vec().chunks_exact(3).flat_map(|x| vec![x[0], x[2], x[1]]).collect();
+                let normal = -plane.normal;
+
+                above_cap.set_indices(flipped);
+                above_cap.set_attribute(Semantic::Normal, vec![[normal.x, normal.y, normal.z, 0.0]; below_cap.vertex().len()]);
+                shapes::generate_tangents(&mut above_cap);
+
+                (Some(above_cap), Some(below_cap))
+            }
+            None => (None, None)
+        }
+    } else {
+        (None, None)
+    };
+
+    Ok(Slice {
+        above,
+        below,
+        outlines,
+        above_cap,
+        below_cap
+    })
+}
+
+// the source vertices followed by one vertex per cut edge
+fn with_cuts(primitive: &Primitive, distances: &[f32], cuts: &[(u32, u32)]) -> Primitive {
+    let count = primitive.vertex().len();
+    let mut sources: Vec<u32> = (0..count as u32).collect();
+    sources.extend(cuts.iter().map(|x| x.0));
+
+    let mut combined = primitive.select_vertices(&sources, Vec::new());
+
+    for (offset, (below, above)) in cuts.iter().enumerate() {
+        let (below, above) = (*below as usize, *above as usize);
+        // always measured from the vertex below, so both sides of a seam land on the same point
+        let t = distances[below] / (distances[below] - distances[above]);
+        let attribute = |semantic: Semantic| lerp(&primitive.attribute(semantic, below), &primitive.attribute(semantic, above), t);
+
+        let position = attribute(Semantic::Position);
+        let normal = attribute(Semantic::Normal);
+        let tangent = attribute(Semantic::Tangent);
+        let uv = attribute(Semantic::TexCoord0);
+
+        let normal = Vector3::new(normal[0], normal[1], normal[2]);
+        let tangent_direction = Vector3::new(tangent[0], tangent[1], tangent[2]);
+
+        combined.vertex_mut()[count + offset] = Vertex::new(Vector3::new(position[0], position[1], position[2]),
+                                                            normal.try_normalize(f32::EPSILON).unwrap_or(normal),
+                                                            tangent_direction
+                                                                .try_normalize(f32::EPSILON)
+                                                                .unwrap_or(tangent_direction)
+                                                                .push(primitive.vertex()[below].tangent().w),
+                                                            Vector2::new(uv[0], uv[1]));
+
+        for semantic in primitive.extra_semantics() {
+            combined.attributes.get_mut(&semantic).unwrap()[count + offset] = attribute(semantic);
+        }
+    }
+
+    combined
+}
+
+// fans the clipped polygons and keeps only the vertices they use
+fn half<F>(combined: &Primitive, faces: &[Vec<Corner>], resolve: &F) -> Primitive
+    where F: Fn(&Corner) -> u32 {
+    let mut local: HashMap<u32, u32> = HashMap::new();
+    let mut sources: Vec<u32> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+
+    for face in faces {
+        let mut polygon: Vec<u32> = face.iter().map(resolve).collect();
+        polygon.dedup();
+
+        while polygon.len() > 1 && polygon.first() == polygon.last() {
+            polygon.pop();
+        }
+
+        for corner in 1..polygon.len().saturating_sub(1) {
+            let triangle = [polygon[0], polygon[corner], polygon[corner + 1]];
+
+            if triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[0] == triangle[2] {
+                continue;
+            }
+
+            for vertex in triangle.iter() {
+                let index = *local.entry(*vertex).or_insert_with(|| {
+                    sources.push(*vertex);
+                    (sources.len() - 1) as u32
+                });
+
+                indices.push(index);
+            }
+        }
+    }
+
+    combined.select_vertices(&sources, indices)
+}
+
+// links segments head to tail, anything that does not come back around stays open
+fn chain(segments: &[(Point3<f32>, Point3<f32>)]) -> Vec<Polyline> {
+    let mut starting: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    let mut ending: HashMap<[u32; 3], usize> = HashMap::new();
+
+    for (index, (start, end)) in segments.iter().enumerate() {
+        if point_key(start) == point_key(end) {
+            continue;
+        }
+
+        starting.entry(point_key(start)).or_default().push(index);
+        ending.insert(point_key(end), index);
+    }
+
+    let mut used: Vec<bool> = segments.iter().map(|(a, b)| point_key(a) == point_key(b)).collect();
+    let mut polylines: Vec<Polyline> = Vec::new();
+
+    let next_unused = |key: &[u32; 3], used: &Vec<bool>| -> Option<usize> {
+        starting.get(key).and_then(|x| x.iter().copied().find(|x| !used[*x]))
+    };
+
+    for first in 0..segments.len() {
+        if used[first] {
+            continue;
+        }
+
+        // walk back to where an open chain begins
+        let mut start = first;
+        let mut steps = 0;
+
+        while let Some(previous) = ending.get(&point_key(&segments[start].0)).copied() {
+            if previous == first || used[previous] || steps > segments.len() {
+                break;
+            }
+
+            start = previous;
+            steps += 1;
+        }
+
+        let mut points = vec![segments[start].0];
+        let mut current = start;
+        let mut closed = false;
+
+        loop {
+            used[current] = true;
+            let end = segments[current].1;
+
+            if point_key(&end) == point_key(&points[0]) {
+                closed = true;
+                break;
+            }
+
+            points.push(end);
+
+            match next_unused(&point_key(&end), &used) {
+                Some(next) => current = next,
+                None => break
+            }
+        }
+
+        polylines.push(Polyline { points, closed });
+    }
+
+    polylines
+}
+
+fn cross(a: &Vector2<f32>, b: &Vector2<f32>) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+fn signed_area(points: &[Point2<f32>]) -> f32 {
+    (0..points.len())
+        .map(|x| cross(&points[x].coords, &points[(x + 1) % points.len()].coords))
+        .sum::<f32>() * 0.5
+}
+
+fn contains(polygon: &[Point2<f32>], point: &Point2<f32>) -> bool {
+    let mut inside = false;
+
+    for (i, a) in polygon.iter().enumerate() {
+        let b = &polygon[(i + 1) % polygon.len()];
+
+        if (a.y > point.y) != (b.y > point.y) && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+fn in_triangle(point: &Point2<f32>, a: &Point2<f32>, b: &Point2<f32>, c: &Point2<f32>) -> bool {
+    cross(&(b - a), &(point - a)) >= 0.0 && cross(&(c - b), &(point - b)) >= 0.0 && cross(&(a - c), &(point - c)) >= 0.0
+}
+
+// a cap facing along the plane normal, closed outlines nested inside others become holes
+fn cap_primitive(loops: &[&Polyline], plane: &Plane, material_index: usize) -> Option<Primitive> {
+    let (u, v) = plane.basis();
+    let mut points: Vec<Point2<f32>> = Vec::new();
+    let mut polygons: Vec<Vec<u32>> = Vec::new();
+
+    for polyline in loops.iter().filter(|x| x.points.len() >= 3) {
+        let first = points.len() as u32;
+        points.extend(polyline.points.iter().map(|x| Point2::new(x.coords.dot(&u), x.coords.dot(&v))));
+        polygons.push((first..points.len() as u32).collect());
+    }
+
+    if polygons.is_empty() {
+        return None;
+    }
+
+    let outline = |polygon: &Vec<u32>| -> Vec<Point2<f32>> { polygon.iter().map(|x| points[*x as usize]).collect() };
+
+    // an even number of surrounding loops makes an outer boundary, odd a hole
+    let depths: Vec<usize> = polygons
+        .iter()
+        .enumerate()
+        .map(|(i, polygon)| {
+            let point = points[polygon[0] as usize];
+            polygons.iter().enumerate().filter(|(j, other)| *j != i && contains(&outline(other), &point)).count()
+        })
+        .collect();
+
+    for (polygon, depth) in polygons.iter_mut().zip(depths.iter()) {
+        let counter_clockwise = signed_area(&outline(polygon)) > 0.0;
+
+        if counter_clockwise != (depth % 2 == 0) {
+            polygon.reverse();
+        }
+    }
+
+    let mut indices: Vec<u32> = Vec::new();
+
+    for (outer, depth) in depths.iter().enumerate().filter(|(_, x)| *x % 2 == 0) {
+        let area = signed_area(&outline(&polygons[outer]));
+
+        // holes directly inside this outer loop, rightmost first so earlier bridges never block later ones
+        let mut holes: Vec<usize> = (0..polygons.len())
+            .filter(|x| depths[*x] == depth + 1 && contains(&outline(&polygons[outer]), &points[polygons[*x][0] as usize]))
+            .filter(|x| {
+                // the tightest outer loop owns the hole
+                let point = points[polygons[*x][0] as usize];
+                !(0..polygons.len()).any(|y| {
+                    y != outer && depths[y] == *depth && contains(&outline(&polygons[y]), &point)
+                        && signed_area(&outline(&polygons[y])) < area
+                })
+            })
+            .collect();
+
+        let rightmost = |polygon: &Vec<u32>| polygon.iter().map(|x| points[*x as usize].x).fold(f32::MIN, f32::max);
+        holes.sort_by(|a, b| rightmost(&polygons[*b]).total_cmp(&rightmost(&polygons[*a])));
+
+        let mut merged = polygons[outer].clone();
+
+        for hole in holes {
+            merged = bridge(&points, &merged, &polygons[hole]);
+        }
+
+        indices.extend(ear_clip(&points, &merged));
+    }
+
+    let normal = plane.normal;
+    let vertices: Vec<Vertex> = loops
+        .iter()
+        .filter(|x| x.points.len() >= 3)
+        .flat_map(|x| x.points.iter())
+        .zip(points.iter())
+        .map(|(position, uv)| Vertex::new(position.coords, normal, u.push(1.0), uv.coords))
+        .collect();
+
+    let mut cap = Primitive::new(vertices, indices, material_index, PrimitiveTopology::TriangleList);
+    shapes::generate_tangents(&mut cap);
+
+    Some(cap)
+}
+
+// joins a clockwise hole into a counter clockwise polygon through a mutually visible pair of vertices
+fn bridge(points: &[Point2<f32>], polygon: &[u32], hole: &[u32]) -> Vec<u32> {
+    let (hole_start, m) = hole
+        .iter()
+        .enumerate()
+        .max_by(|a, b| points[*a.1 as usize].x.total_cmp(&points[*b.1 as usize].x))
+        .map(|(i, x)| (i, points[*x as usize]))
+        .unwrap();
+
+    // closest crossing of a ray from m towards +x
+    let mut best: Option<(f32, usize)> = None;
+
+    for i in 0..polygon.len() {
+        let a = points[polygon[i] as usize];
+        let b = points[polygon[(i + 1) % polygon.len()] as usize];
+
+        if (a.y > m.y) == (b.y > m.y) {
+            continue;
+        }
+
+        let x = a.x + (m.y - a.y) / (b.y - a.y) * (b.x - a.x);
+
+        if x >= m.x && !matches!(best, Some((best, _)) if x >= best) {
+            // the edge endpoint further along the ray is visible unless something pokes into the triangle
+            let candidate = if a.x > b.x { i } else { (i + 1) % polygon.len() };
+            best = Some((x, candidate));
+        }
+    }
+
+    let (x, mut connect) = match best {
+        Some(best) => best,
+        None => return polygon.to_vec()
+    };
+
+    let intersection = Point2::new(x, m.y);
+    let p = points[polygon[connect] as usize];
+    let mut best_angle = f32::MAX;
+
+    for (i, vertex) in polygon.iter().enumerate() {
+        let point = points[*vertex as usize];
+        let previous = points[polygon[(i + polygon.len() - 1) % polygon.len()] as usize];
+        let next = points[polygon[(i + 1) % polygon.len()] as usize];
+        let reflex = cross(&(point - previous), &(next - point)) <= 0.0;
+
+        if i == connect || !reflex {
+            continue;
+        }
+
+        let (a, b, c) = if cross(&(intersection - m), &(p - m)) >= 0.0 { (m, intersection, p) } else { (m, p, intersection) };
+
+        if in_triangle(&point, &a, &b, &c) {
+            let direction = point - m;
+            let angle = direction.y.abs().atan2(direction.x);
+
+            if angle < best_angle {
+                best_angle = angle;
+                connect = i;
+            }
+        }
+    }
+
+    let mut merged: Vec<u32> = Vec::with_capacity(polygon.len() + hole.len() + 2);
+    merged.extend_from_slice(&polygon[..=connect]);
+    merged.extend(hole[hole_start..].iter().chain(hole[..=hole_start].iter()));
+    merged.push(polygon[connect]);
+    merged.extend_from_slice(&polygon[connect + 1..]);
+
+    merged
+}
+
+// counter clockwise simple polygon, bridges may repeat vertices
+fn ear_clip(points: &[Point2<f32>], polygon: &[u32]) -> Vec<u32> {
+    let mut remaining = polygon.to_vec();
+    let mut triangles: Vec<u32> = Vec::with_capacity(polygon.len().saturating_sub(2) * 3);
+    let point = |x: u32| points[x as usize];
+
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let mut fallback: Option<usize> = None;
+        let mut ear: Option<usize> = None;
+
+        for i in 0..count {
+            let (a, b, c) = (remaining[(i + count - 1) % count], remaining[i], remaining[(i + 1) % count]);
+            let (pa, pb, pc) = (point(a), point(b), point(c));
+
+            if cross(&(pb - pa), &(pc - pb)) <= 0.0 {
+                continue;
+            }
+
+            fallback.get_or_insert(i);
+
+            // repeated bridge vertices sitting on the ear's corners do not block it
+            let blocked = remaining.iter().any(|x| {
+                let p = point(*x);
+                p != pa && p != pb && p != pc && in_triangle(&p, &pa, &pb, &pc)
+            });
+
+            if !blocked {
+                ear = Some(i);
+                break;
+            }
+        }
+
+        // rounding can leave no clean ear, clipping a convex corner keeps the fill going
+        let i = match ear.or(fallback) {
+            Some(i) => i,
+            None => break
+        };
+
+        triangles.extend_from_slice(&[remaining[(i + count - 1) % count], remaining[i], remaining[(i + 1) % count]]);
+        remaining.remove(i);
+    }
+
+    if remaining.len() == 3 {
+        triangles.extend_from_slice(&remaining);
+    }
+
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangles(primitive: &Primitive) -> Vec<[Point3<f32>; 3]> {
+        let position = |x: u32| Point3::from(*primitive.vertex()[x as usize].position());
+
+        primitive.indices()
+            .to_vec()
+            .chunks_exact(3)
+            .map(|x| [position(x[0]), position(x[1]), position(x[2])])
+            .collect()
+    }
+
+    // welded by position, every edge runs once each way when nothing is left open
+    fn is_closed(parts: &[&Primitive]) -> bool {
+        let mut edges: HashMap<([u32; 3], [u32; 3]), usize> = HashMap::new();
+
+        for triangle in parts.iter().flat_map(|x| triangles(x)) {
+            for corner in 0..3 {
+                let edge = (point_key(&triangle[corner]), point_key(&triangle[(corner + 1) % 3]));
+                *edges.entry(edge).or_default() += 1;
+            }
+        }
+
+        edges.iter().all(|((a, b), count)| *count == 1 && edges.get(&(*b, *a)) == Some(&1))
+    }
+
+    fn area(primitive: &Primitive) -> f32 {
+        triangles(primitive).iter().map(|[a, b, c]| (b - a).cross(&(c - a)).norm() * 0.5).sum()
+    }
+
+    #[test]
+    fn cube_halves_are_closed_once_capped() {
+        let cube = shapes::cube(1.0, 2).primitives.remove(0);
+        let plane = Plane::new(Vector3::new(0.3, 1.0, 0.2), 0.1);
+        let result = slice(&cube, &plane, true).unwrap();
+
+        assert_eq!(result.outlines.len(), 1);
+        assert!(result.outlines[0].closed);
+        assert!(!is_closed(&[&result.above]));
+        assert!(!is_closed(&[&result.below]));
+
+        let above_cap = result.above_cap.as_ref().unwrap();
+        let below_cap = result.below_cap.as_ref().unwrap();
+
+        assert!(is_closed(&[&result.above, above_cap]));
+        assert!(is_closed(&[&result.below, below_cap]));
+
+        for point in triangles(&result.above).iter().flatten() {
+            assert!(plane.signed_distance(point) >= -1e-5);
+        }
+
+        for point in triangles(&result.below).iter().flatten() {
+            assert!(plane.signed_distance(point) <= 1e-5);
+        }
+    }
+
+    #[test]
+    fn torus_cut_leaves_a_hole() {
+        let torus = shapes::torus(1.0, 0.25, 24, 12).primitives.remove(0);
+        let plane = Plane::new(Vector3::y(), 0.05);
+        let result = slice(&torus, &plane, true).unwrap();
+
+        assert_eq!(result.outlines.len(), 2);
+        assert!(result.outlines.iter().all(|x| x.closed));
+
+        let below_cap = result.below_cap.as_ref().unwrap();
+        assert!(is_closed(&[&result.above, result.above_cap.as_ref().unwrap()]));
+        assert!(is_closed(&[&result.below, below_cap]));
+
+        // the fill covers the ring between the loops and nothing of the hole
+        let (u, v) = plane.basis();
+        let enclosed = |x: &Polyline| {
+            let points: Vec<Point2<f32>> = x.points.iter().map(|x| Point2::new(x.coords.dot(&u), x.coords.dot(&v))).collect();
+            signed_area(&points).abs()
+        };
+        let mut areas: Vec<f32> = result.outlines.iter().map(enclosed).collect();
+        areas.sort_by(f32::total_cmp);
+
+        assert!((area(below_cap) - (areas[1] - areas[0])).abs() < 1e-4);
+
+        for point in triangles(below_cap).iter().flatten() {
+            let radius = Vector2::new(point.x, point.z).norm();
+            assert!(radius > 0.7 && radius < 1.3);
+        }
+    }
+}
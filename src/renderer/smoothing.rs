@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use nalgebra::Vector3;
+use crate::renderer::{halfedge::HalfEdgeMesh, layout::Semantic, shapes, Primitive, RenderError};
+
+fn find(parents: &mut [u32], corner: u32) -> u32 {
+    let mut root = corner;
+
+    while parents[root as usize] != root {
+        root = parents[root as usize];
+    }
+
+    let mut current = corner;
+
+    while parents[current as usize] != root {
+        let next = parents[current as usize];
+        parents[current as usize] = root;
+        current = next;
+    }
+
+    root
+}
+
+fn union(parents: &mut [u32], a: u32, b: u32) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    parents[a.max(b) as usize] = a.min(b);
+}
+
+// interior angle of the face at the corner the half edge leaves from
+fn corner_angle(mesh: &HalfEdgeMesh, half_edge: u32) -> f32 {
+    let corner = mesh.half_edge(half_edge);
+    let origin = mesh.position(corner.vertex);
+    let next = mesh.position(mesh.target(half_edge)) - origin;
+    let previous = mesh.position(mesh.half_edge(corner.prev).vertex) - origin;
+
+    match (next.try_normalize(f32::EPSILON), previous.try_normalize(f32::EPSILON)) {
+        (Some(a), Some(b)) => a.dot(&b).clamp(-1.0, 1.0).acos(),
+        _ => 0.0
+    }
+}
+
+// bits of everything but the normal and tangent, corners sharing them may share a vertex
+fn wedge_key(primitive: &Primitive, wedge: usize) -> Vec<u32> {
+    let uv = primitive.vertex()[wedge].uv();
+    let mut key = vec![uv.x.to_bits(), uv.y.to_bits()];
+
+    for semantic in primitive.extra_semantics() {
+        key.extend(primitive.attribute(semantic, wedge).iter().map(|x| x.to_bits()));
+    }
+
+    key
+}
+
+// splits vertices along edges sharper than the angle in radians and recomputes angle weighted normals
+// for every smoothing group, corners with different uvs or extra attributes keep separate vertices
+pub fn split_hard_edges(primitive: &Primitive, angle: f32) -> Result<Primitive, RenderError> {
+    let mesh = HalfEdgeMesh::from_primitive(primitive)?;
+    let corner_count = mesh.half_edges().len() as u32;
+
+    // corners around a welded position join when the edge between their faces is smooth
+    let mut parents: Vec<u32> = (0..corner_count).collect();
+
+    for index in 0..corner_count {
+        let half_edge = mesh.half_edge(index);
+
+        let twin = match half_edge.twin {
+            Some(twin) if twin > index => twin,
+            _ => continue
+        };
+
+        if !matches!(mesh.dihedral_angle(index), Some(x) if x <= angle) {
+            continue;
+        }
+
+        // the twin runs the other way, so the corner after it sits on this edge's origin
+        union(&mut parents, index, mesh.half_edge(twin).next);
+        union(&mut parents, half_edge.next, twin);
+    }
+
+    let mut normals: HashMap<u32, Vector3<f32>> = HashMap::new();
+
+    for index in 0..corner_count {
+        let group = find(&mut parents, index);
+        let weighted = mesh.face_normal(mesh.half_edge(index).face) * corner_angle(&mesh, index);
+        *normals.entry(group).or_insert_with(Vector3::zeros) += weighted;
+    }
+
+    let mut wedge_classes: HashMap<Vec<u32>, u32> = HashMap::new();
+    let wedge_class: Vec<u32> = (0..primitive.vertex().len())
+        .map(|x| {
+            let next = wedge_classes.len() as u32;
+            *wedge_classes.entry(wedge_key(primitive, x)).or_insert(next)
+        })
+        .collect();
+
+    let mut vertex_of: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut sources: Vec<u32> = Vec::new();
+    let mut vertex_normals: Vec<[f32; 4]> = Vec::new();
+    let mut indices: Vec<u32> = Vec::with_capacity(corner_count as usize);
+
+    for index in 0..corner_count {
+        let wedge = mesh.half_edge(index).wedge;
+        let group = find(&mut parents, index);
+
+        let vertex = *vertex_of.entry((group, wedge_class[wedge as usize])).or_insert_with(|| {
+            let normal = normals[&group].try_normalize(f32::EPSILON).unwrap_or_else(|| mesh.face_normal(mesh.half_edge(index).face));
+            sources.push(wedge);
+            vertex_normals.push([normal.x, normal.y, normal.z, 0.0]);
+            (sources.len() - 1) as u32
+        });
+
+        indices.push(vertex);
+    }
+
+    let mut split = primitive.select_vertices(&sources, indices);
+    split.set_attribute(Semantic::Normal, vertex_normals);
+    shapes::generate_tangents(&mut split);
+
+    Ok(split)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use nalgebra::Vector2;
+    use super::*;
+
+    // the unit cube with every uv at zero, so only the angle decides what gets split
+    fn plain_cube() -> Primitive {
+        let cube = shapes::cube(1.0, 1);
+        let source = &cube.primitives[0];
+        let vertices = source.vertex()
+            .iter()
+            .map(|x| {
+                let mut vertex = *x;
+                vertex.set_uv(Vector2::zeros());
+                vertex
+            })
+            .collect();
+
+        Primitive::new(vertices, source.indices().to_vec(), 0, source.mode)
+    }
+
+    #[test]
+    fn sharp_cube_keeps_a_vertex_per_face_corner() {
+        let split = split_hard_edges(&plain_cube(), PI / 6.0).unwrap();
+
+        assert_eq!(split.vertex().len(), 24);
+        assert_eq!(split.indices().len(), 36);
+
+        for vertex in split.vertex().iter() {
+            let normal = vertex.normal();
+            assert!((normal.amax() - 1.0).abs() < 1e-5 && (normal.norm() - 1.0).abs() < 1e-5);
+            assert!(normal.dot(vertex.position()) > 0.0);
+        }
+    }
+
+    #[test]
+    fn smooth_cube_shares_every_corner() {
+        let split = split_hard_edges(&plain_cube(), PI).unwrap();
+
+        assert_eq!(split.vertex().len(), 8);
+
+        // three faces meet at right angles in every corner, so the normal points along the diagonal
+        for vertex in split.vertex().iter() {
+            let diagonal = vertex.position().normalize();
+            assert!((vertex.normal() - diagonal).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn uv_seams_stay_split() {
+        let cube = shapes::cube(1.0, 1);
+        let split = split_hard_edges(&cube.primitives[0], PI).unwrap();
+
+        assert!(split.vertex().len() > 8);
+        assert!(HalfEdgeMesh::from_primitive(&split).unwrap().is_closed());
+    }
+}
@@ -0,0 +1,570 @@
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+use nalgebra::{Point3, Vector2, Vector3, Vector4};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{halfedge::{polygon_normal, HalfEdgeMesh}, layout::Semantic, shapes, vertex::Vertex, Primitive, RenderError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoundaryRule {
+    // boundaries follow the crease rules and stay smooth along their length
+    Crease,
+    // boundary vertices never move
+    Fixed
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubdivisionOptions {
+    pub levels: u32,
+    // dihedral angle in radians above which a cage edge stays sharp, None keeps every interior edge smooth
+    pub crease_angle: Option<f32>,
+    // sharp edges as pairs of vertex indices of the source primitive
+    pub creases: Vec<(u32, u32)>,
+    pub boundary: BoundaryRule
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scheme {
+    Loop,
+    CatmullClark
+}
+
+// area weighted face normal and the interpolated normal of one corner
+type CornerNormals = (Vector3<f32>, Vector3<f32>);
+
+// an undirected edge between welded vertices
+struct Edge {
+    vertices: (u32, u32),
+    half_edges: Vec<u32>,
+    sharp: bool
+}
+
+impl Default for SubdivisionOptions {
+    fn default() -> Self {
+        Self {
+            levels: 1,
+            crease_angle: None,
+            creases: Vec::new(),
+            boundary: BoundaryRule::Crease
+        }
+    }
+}
+
+impl SubdivisionOptions {
+    pub fn with_levels(levels: u32) -> Self {
+        Self {
+            levels,
+            ..Default::default()
+        }
+    }
+}
+
+// every level splits each triangle in four
+pub fn loop_subdivide(primitive: &Primitive, options: &SubdivisionOptions) -> Result<Primitive, RenderError> {
+    let mesh = HalfEdgeMesh::from_primitive(primitive)?;
+    let faces: Vec<Vec<u32>> = (0..mesh.face_count() as u32).map(|x| mesh.face_wedges(x)).collect();
+
+    subdivide(primitive, faces, options, Scheme::Loop)
+}
+
+// pairs of triangles making up a flat quad are merged first, the rest stay triangles
+pub fn catmull_clark(primitive: &Primitive, options: &SubdivisionOptions) -> Result<Primitive, RenderError> {
+    let mesh = HalfEdgeMesh::from_primitive(primitive)?;
+    catmull_clark_faces(primitive, recover_quads(&mesh), options)
+}
+
+// faces are counter clockwise polygons over the vertices of primitive, after one level every face is a quad
+pub fn catmull_clark_faces(primitive: &Primitive, faces: Vec<Vec<u32>>, options: &SubdivisionOptions) -> Result<Primitive, RenderError> {
+    subdivide(primitive, faces, options, Scheme::CatmullClark)
+}
+
+// merges triangles across their shared longest edge when they are nearly coplanar and agree on its attributes
+pub fn recover_quads(mesh: &HalfEdgeMesh) -> Vec<Vec<u32>> {
+    let flatness = (10.0f32).to_radians().cos();
+    let mut merged: Vec<bool> = vec![false; mesh.face_count()];
+    let mut faces: Vec<Vec<u32>> = Vec::with_capacity(mesh.face_count());
+
+    let longest = |face: u32| {
+        mesh.face_half_edges(face)
+            .into_iter()
+            .max_by(|a, b| edge_length(mesh, *a).total_cmp(&edge_length(mesh, *b)))
+            .unwrap()
+    };
+
+    for face in 0..mesh.face_count() as u32 {
+        if merged[face as usize] {
+            continue;
+        }
+
+        let corners = mesh.face_half_edges(face);
+
+        if corners.len() == 3 {
+            let diagonal = longest(face);
+            let half_edge = mesh.half_edge(diagonal);
+
+            if let Some(twin) = half_edge.twin {
+                let other = mesh.half_edge(twin);
+                let candidate = other.face;
+                let same_wedges = mesh.half_edge(other.next).wedge == half_edge.wedge &&
+                    mesh.half_edge(half_edge.next).wedge == other.wedge;
+
+                if !merged[candidate as usize] && candidate != face && mesh.face_half_edges(candidate).len() == 3 &&
+                    longest(candidate) == twin && same_wedges &&
+                    mesh.face_normal(face).dot(&mesh.face_normal(candidate)) >= flatness {
+                    // u -> v is the diagonal, x closes this triangle and y the other one
+                    let u = half_edge.wedge;
+                    let v = mesh.half_edge(half_edge.next).wedge;
+                    let x = mesh.half_edge(half_edge.prev).wedge;
+                    let y = mesh.half_edge(other.prev).wedge;
+
+                    merged[face as usize] = true;
+                    merged[candidate as usize] = true;
+                    faces.push(vec![u, y, v, x]);
+                    continue;
+                }
+            }
+        }
+
+        merged[face as usize] = true;
+        faces.push(mesh.face_wedges(face));
+    }
+
+    faces
+}
+
+fn edge_length(mesh: &HalfEdgeMesh, half_edge: u32) -> f32 {
+    nalgebra::distance(mesh.position(mesh.half_edge(half_edge).vertex), mesh.position(mesh.target(half_edge)))
+}
+
+fn subdivide(primitive: &Primitive, mut faces: Vec<Vec<u32>>, options: &SubdivisionOptions, scheme: Scheme) -> Result<Primitive, RenderError> {
+    if primitive.mode != PrimitiveTopology::TriangleList {
+        return Err(RenderError::Geometry(format!("subdivision needs a triangle list, got {:?}", primitive.mode)));
+    }
+
+    let mut source = primitive.select_vertices(&(0..primitive.vertex().len() as u32).collect::<Vec<u32>>(), Vec::new());
+    let mut creases = options.creases.clone();
+
+    for level in 0..options.levels {
+        let mesh = HalfEdgeMesh::from_faces(&source, &faces)?;
+
+        if scheme == Scheme::Loop && faces.iter().any(|x| x.len() != 3) {
+            return Err(RenderError::Geometry("Loop subdivision needs triangles only".to_string()));
+        }
+
+        let crease_angle = if level == 0 { options.crease_angle } else { None };
+        let (next_source, next_faces, next_creases) = subdivide_level(&mesh, &faces, &creases, crease_angle, options.boundary, scheme);
+
+        source = next_source;
+        faces = next_faces;
+        creases = next_creases;
+    }
+
+    Ok(finish(&source, &faces, primitive.material_index))
+}
+
+fn collect_edges(mesh: &HalfEdgeMesh,
+                 creases: &[(u32, u32)],
+                 crease_angle: Option<f32>) -> (Vec<Edge>, HashMap<(u32, u32), usize>) {
+    let mut edges: Vec<Edge> = Vec::new();
+    let mut edge_of: HashMap<(u32, u32), usize> = HashMap::new();
+
+    for index in 0..mesh.half_edges().len() as u32 {
+        let (a, b) = (mesh.half_edge(index).vertex, mesh.target(index));
+        let key = (a.min(b), a.max(b));
+
+        let edge = *edge_of.entry(key).or_insert_with(|| {
+            edges.push(Edge { vertices: key, half_edges: Vec::new(), sharp: false });
+            edges.len() - 1
+        });
+
+        edges[edge].half_edges.push(index);
+    }
+
+    let crease_set: HashSet<(u32, u32)> = creases
+        .iter()
+        .map(|(a, b)| {
+            let (a, b) = (mesh.welded_vertex(*a), mesh.welded_vertex(*b));
+            (a.min(b), a.max(b))
+        })
+        .collect();
+
+    for edge in edges.iter_mut() {
+        // boundaries, non manifold and badly oriented edges all take the crease rules
+        let paired = edge.half_edges.len() == 2 && mesh.half_edge(edge.half_edges[0]).twin == Some(edge.half_edges[1]);
+
+        edge.sharp = !paired || crease_set.contains(&edge.vertices) || match crease_angle {
+            Some(angle) => {
+                let first = mesh.face_normal(mesh.half_edge(edge.half_edges[0]).face);
+                let second = mesh.face_normal(mesh.half_edge(edge.half_edges[1]).face);
+                first.dot(&second).clamp(-1.0, 1.0).acos() > angle
+            }
+            None => false
+        };
+    }
+
+    (edges, edge_of)
+}
+
+// new source vertices, faces and creases, all in terms of the new wedges
+fn subdivide_level(mesh: &HalfEdgeMesh,
+                   faces: &[Vec<u32>],
+                   creases: &[(u32, u32)],
+                   crease_angle: Option<f32>,
+                   boundary: BoundaryRule,
+                   scheme: Scheme) -> (Primitive, Vec<Vec<u32>>, Vec<(u32, u32)>) {
+    let (edges, edge_of) = collect_edges(mesh, creases, crease_angle);
+    let position = |x: u32| mesh.position(x).coords;
+
+    let face_points: Vec<Vector3<f32>> = (0..mesh.face_count() as u32)
+        .map(|face| {
+            let vertices = mesh.face_vertices(face);
+            vertices.iter().map(|x| position(*x)).sum::<Vector3<f32>>() / vertices.len() as f32
+        })
+        .collect();
+
+    let edge_points: Vec<Vector3<f32>> = edges
+        .iter()
+        .map(|edge| {
+            let (a, b) = (position(edge.vertices.0), position(edge.vertices.1));
+
+            if edge.sharp {
+                return (a + b) * 0.5;
+            }
+
+            match scheme {
+                Scheme::Loop => {
+                    let opposite: Vector3<f32> = edge.half_edges
+                        .iter()
+                        .map(|x| position(mesh.half_edge(mesh.half_edge(*x).prev).vertex))
+                        .sum();
+                    (a + b) * 0.375 + opposite * 0.125
+                }
+                Scheme::CatmullClark => {
+                    let faces: Vector3<f32> = edge.half_edges.iter().map(|x| face_points[mesh.half_edge(*x).face as usize]).sum();
+                    (a + b + faces) * 0.25
+                }
+            }
+        })
+        .collect();
+
+    let mut incident: Vec<Vec<usize>> = vec![Vec::new(); mesh.vertex_count()];
+    for (index, edge) in edges.iter().enumerate() {
+        incident[edge.vertices.0 as usize].push(index);
+        if edge.vertices.1 != edge.vertices.0 {
+            incident[edge.vertices.1 as usize].push(index);
+        }
+    }
+
+    let vertex_points: Vec<Vector3<f32>> = (0..mesh.vertex_count() as u32)
+        .map(|vertex| {
+            let v = position(vertex);
+            let other = |edge: usize| {
+                let (a, b) = edges[edge].vertices;
+                if a == vertex { b } else { a }
+            };
+            let sharp: Vec<usize> = incident[vertex as usize].iter().copied().filter(|x| edges[*x].sharp).collect();
+
+            let corner = !mesh.is_manifold_vertex(vertex) ||
+                (boundary == BoundaryRule::Fixed && mesh.is_boundary_vertex(vertex)) ||
+                sharp.len() > 2;
+
+            if corner {
+                return v;
+            }
+
+            if sharp.len() == 2 {
+                return v * 0.75 + (position(other(sharp[0])) + position(other(sharp[1]))) * 0.125;
+            }
+
+            match scheme {
+                Scheme::Loop => {
+                    let ring = mesh.one_ring(vertex);
+                    let n = ring.len() as f32;
+                    let beta = (0.625 - (0.375 + 0.25 * (2.0 * PI / n).cos()).powi(2)) / n;
+
+                    v * (1.0 - n * beta) + ring.iter().map(|x| position(*x)).sum::<Vector3<f32>>() * beta
+                }
+                Scheme::CatmullClark => {
+                    let faces = mesh.vertex_faces(vertex);
+                    let n = faces.len() as f32;
+                    let face_average = faces.iter().map(|x| face_points[*x as usize]).sum::<Vector3<f32>>() / n;
+                    let edge_average = incident[vertex as usize]
+                        .iter()
+                        .map(|x| (v + position(other(*x))) * 0.5)
+                        .sum::<Vector3<f32>>() / incident[vertex as usize].len() as f32;
+
+                    (face_average + edge_average * 2.0 + v * (n - 3.0)) / n
+                }
+            }
+        })
+        .collect();
+
+    // every new wedge is a weighted blend of old ones, placed on a new welded point
+    let mut blends: Vec<Vec<(u32, f32)>> = Vec::new();
+    let mut points: Vec<Vector3<f32>> = Vec::new();
+    let mut corner_wedges: HashMap<u32, u32> = HashMap::new();
+    let mut edge_wedges: HashMap<(usize, u32, u32), u32> = HashMap::new();
+
+    let mut corner_wedge = |wedge: u32, blends: &mut Vec<Vec<(u32, f32)>>, points: &mut Vec<Vector3<f32>>| {
+        *corner_wedges.entry(wedge).or_insert_with(|| {
+            blends.push(vec![(wedge, 1.0)]);
+            points.push(vertex_points[mesh.welded_vertex(wedge) as usize]);
+            (blends.len() - 1) as u32
+        })
+    };
+
+    let mut edge_wedge = |a: u32, b: u32, blends: &mut Vec<Vec<(u32, f32)>>, points: &mut Vec<Vector3<f32>>| {
+        let (va, vb) = (mesh.welded_vertex(a), mesh.welded_vertex(b));
+        let edge = edge_of[&(va.min(vb), va.max(vb))];
+
+        *edge_wedges.entry((edge, a.min(b), a.max(b))).or_insert_with(|| {
+            blends.push(vec![(a, 0.5), (b, 0.5)]);
+            points.push(edge_points[edge]);
+            (blends.len() - 1) as u32
+        })
+    };
+
+    let mut new_faces: Vec<Vec<u32>> = Vec::with_capacity(faces.len() * 4);
+
+    for (face, wedges) in faces.iter().enumerate() {
+        let count = wedges.len();
+        let corners: Vec<u32> = wedges.iter().map(|x| corner_wedge(*x, &mut blends, &mut points)).collect();
+        let mids: Vec<u32> = (0..count)
+            .map(|x| edge_wedge(wedges[x], wedges[(x + 1) % count], &mut blends, &mut points))
+            .collect();
+
+        match scheme {
+            Scheme::Loop => {
+                new_faces.push(vec![corners[0], mids[0], mids[2]]);
+                new_faces.push(vec![mids[0], corners[1], mids[1]]);
+                new_faces.push(vec![mids[2], mids[1], corners[2]]);
+                new_faces.push(vec![mids[0], mids[1], mids[2]]);
+            }
+            Scheme::CatmullClark => {
+                blends.push(wedges.iter().map(|x| (*x, 1.0 / count as f32)).collect());
+                points.push(face_points[face]);
+                let center = (blends.len() - 1) as u32;
+
+                for corner in 0..count {
+                    new_faces.push(vec![corners[corner], mids[corner], center, mids[(corner + count - 1) % count]]);
+                }
+            }
+        }
+    }
+
+    // both halves of a sharp edge stay sharp, named by any wedge sitting on their ends
+    let mut representative: Vec<u32> = vec![0; mesh.vertex_count()];
+    for wedge in faces.iter().flatten() {
+        representative[mesh.welded_vertex(*wedge) as usize] = *wedge;
+    }
+
+    let middle: HashMap<usize, u32> = edge_wedges.iter().map(|(key, wedge)| (key.0, *wedge)).collect();
+
+    let mut new_creases: Vec<(u32, u32)> = Vec::new();
+    for (index, edge) in edges.iter().enumerate().filter(|(_, edge)| edge.sharp) {
+        let a = corner_wedges[&representative[edge.vertices.0 as usize]];
+        let b = corner_wedges[&representative[edge.vertices.1 as usize]];
+
+        new_creases.push((a, middle[&index]));
+        new_creases.push((middle[&index], b));
+    }
+
+    (blend_vertices(mesh.source(), &blends, &points), new_faces, new_creases)
+}
+
+fn blend_vertices(source: &Primitive, blends: &[Vec<(u32, f32)>], points: &[Vector3<f32>]) -> Primitive {
+    let extra = source.extra_semantics();
+    let blend = |semantic: Semantic, weights: &Vec<(u32, f32)>| {
+        let mut value = [0f32; 4];
+
+        for (wedge, weight) in weights {
+            for (component, x) in value.iter_mut().zip(source.attribute(semantic, *wedge as usize).iter()) {
+                *component += x * weight;
+            }
+        }
+
+        value
+    };
+
+    let vertices: Vec<Vertex> = blends
+        .iter()
+        .zip(points.iter())
+        .map(|(weights, point)| {
+            let normal = blend(Semantic::Normal, weights);
+            let tangent = blend(Semantic::Tangent, weights);
+            let uv = blend(Semantic::TexCoord0, weights);
+
+            Vertex::new(*point,
+                        Vector3::new(normal[0], normal[1], normal[2]),
+                        Vector4::from(tangent),
+                        Vector2::new(uv[0], uv[1]))
+        })
+        .collect();
+
+    let mut primitive = Primitive::new(vertices, Vec::new(), source.material_index, PrimitiveTopology::TriangleList);
+
+    for semantic in extra {
+        primitive.set_attribute(semantic, blends.iter().map(|x| blend(semantic, x)).collect());
+    }
+
+    primitive
+}
+
+// triangulates, then smooths normals over faces whose interpolated normals agree so hard edges of the cage survive
+fn finish(source: &Primitive, faces: &[Vec<u32>], material_index: usize) -> Primitive {
+    let indices: Vec<u32> = faces
+        .iter()
+        .flat_map(|face| (1..face.len() - 1).flat_map(move |x| vec![face[0], face[x], face[x + 1]]))
+        .collect();
+
+    let all: Vec<u32> = (0..source.vertex().len() as u32).collect();
+    let mut primitive = source.select_vertices(&all, indices);
+    primitive.material_index = material_index;
+
+    // faces around every position, with the interpolated normal of the corner they use there
+    let mut around: HashMap<[u32; 3], Vec<CornerNormals>> = HashMap::new();
+    let key = |vertex: &Vertex| {
+        let position = vertex.position().add_scalar(0.0);
+        [position.x.to_bits(), position.y.to_bits(), position.z.to_bits()]
+    };
+
+    for face in faces {
+        let points: Vec<Point3<f32>> = face.iter().map(|x| Point3::from(*source.vertex()[*x as usize].position())).collect();
+        let normal = polygon_normal(&points);
+
+        for wedge in face {
+            let vertex = &source.vertex()[*wedge as usize];
+            around.entry(key(vertex)).or_default().push((normal, *vertex.normal()));
+        }
+    }
+
+    for vertex in primitive.vertex_mut().iter_mut() {
+        let interpolated = vertex.normal().try_normalize(f32::EPSILON);
+        let normal: Vector3<f32> = around
+            .get(&key(vertex))
+            .map(|faces| faces
+                .iter()
+                .filter(|(_, corner)| match (interpolated, corner.try_normalize(f32::EPSILON)) {
+                    (Some(a), Some(b)) => a.dot(&b) > 0.5,
+                    _ => true
+                })
+                .map(|(normal, _)| *normal)
+                .sum())
+            .unwrap_or_else(Vector3::zeros);
+
+        if let Some(normal) = normal.try_normalize(f32::EPSILON) {
+            vertex.set_normal(normal);
+        }
+    }
+
+    shapes::generate_tangents(&mut primitive);
+    primitive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // welded vertices, undirected edges and faces
+    fn counts(primitive: &Primitive) -> (usize, usize, usize) {
+        let mesh = HalfEdgeMesh::from_primitive(primitive).unwrap();
+        let edges: HashSet<(u32, u32)> = mesh
+            .half_edges()
+            .iter()
+            .enumerate()
+            .map(|(index, x)| {
+                let target = mesh.target(index as u32);
+                (x.vertex.min(target), x.vertex.max(target))
+            })
+            .collect();
+
+        (mesh.vertex_count(), edges.len(), mesh.face_count())
+    }
+
+    fn boundary_positions(primitive: &Primitive) -> Vec<Point3<f32>> {
+        let mesh = HalfEdgeMesh::from_primitive(primitive).unwrap();
+
+        mesh.boundary_loops().iter().flatten().map(|x| *mesh.position(*x)).collect()
+    }
+
+    #[test]
+    fn loop_counts() {
+        let icosahedron = shapes::icosphere(1.0, 0).primitives.remove(0);
+        assert_eq!(counts(&icosahedron), (12, 30, 20));
+
+        let subdivided = loop_subdivide(&icosahedron, &SubdivisionOptions::with_levels(1)).unwrap();
+
+        // a vertex per edge, every edge split in two plus three inside every face, four triangles per face
+        assert_eq!(counts(&subdivided), (12 + 30, 2 * 30 + 3 * 20, 4 * 20));
+    }
+
+    #[test]
+    fn catmull_clark_counts() {
+        let cube = shapes::cube(2.0, 1).primitives.remove(0);
+        let mesh = HalfEdgeMesh::from_primitive(&cube).unwrap();
+        assert_eq!(recover_quads(&mesh).len(), 6);
+
+        let subdivided = catmull_clark(&cube, &SubdivisionOptions::with_levels(1)).unwrap();
+
+        // a vertex per face and per edge, every edge split in two plus four inside every face, the 24 quads
+        // come out as 48 triangles with a diagonal each
+        assert_eq!(counts(&subdivided), (8 + 12 + 6, 2 * 12 + 4 * 6 + 24, 2 * 24));
+    }
+
+    #[test]
+    fn creases_keep_the_cube() {
+        let cube = shapes::cube(2.0, 1).primitives.remove(0);
+        let options = SubdivisionOptions {
+            levels: 2,
+            crease_angle: Some(0.5),
+            ..Default::default()
+        };
+
+        let subdivided = catmull_clark(&cube, &options).unwrap();
+        let on_edge = |x: &Vector3<f32>| x.iter().filter(|x| (x.abs() - 1.0).abs() < 1e-5).count() >= 2;
+
+        for vertex in subdivided.vertex() {
+            assert!((vertex.position().amax() - 1.0).abs() < 1e-5, "{} left the cube", vertex.position());
+        }
+
+        // corners, plus three new points on every edge after two levels
+        let mesh = HalfEdgeMesh::from_primitive(&subdivided).unwrap();
+        let edge_points = (0..mesh.vertex_count() as u32).filter(|x| on_edge(&mesh.position(*x).coords)).count();
+        assert_eq!(edge_points, 8 + 12 * 3);
+    }
+
+    #[test]
+    fn fixed_boundaries_stay_on_the_cage() {
+        let plane = shapes::plane(2.0, 2.0, 4, 4).primitives.remove(0);
+        let cage = boundary_positions(&plane);
+        let options = SubdivisionOptions {
+            boundary: BoundaryRule::Fixed,
+            ..Default::default()
+        };
+
+        for subdivided in [loop_subdivide(&plane, &options).unwrap(), catmull_clark(&plane, &options).unwrap()].iter() {
+            let boundary = boundary_positions(subdivided);
+
+            assert!(cage.iter().all(|x| boundary.contains(x)));
+            assert!(boundary.iter().all(|x| x.y == 0.0 && (x.x.abs() == 1.0 || x.z.abs() == 1.0)));
+            assert_eq!(boundary.len(), 2 * cage.len());
+        }
+    }
+
+    #[test]
+    fn crease_boundaries_follow_the_boundary_curve() {
+        let plane = shapes::plane(2.0, 2.0, 4, 4).primitives.remove(0);
+        let subdivided = loop_subdivide(&plane, &SubdivisionOptions::default()).unwrap();
+        let boundary = boundary_positions(&subdivided);
+
+        assert_eq!(boundary.len(), 32);
+
+        // the straight sides stay straight, only the corners get cut, within one cage edge of them
+        for point in boundary.iter() {
+            let on_side = point.x.abs() == 1.0 || point.z.abs() == 1.0;
+            let near_corner = (1.0 - point.x.abs()).max(1.0 - point.z.abs()) <= 0.5;
+
+            assert_eq!(point.y, 0.0);
+            assert!(on_side || near_corner, "{} left the boundary", point);
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::path::Path;
+use image::DynamicImage;
+use nalgebra::{Vector2, Vector3, Vector4};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{vertex::Vertex, Lod, Mesh, Primitive, RenderError};
+
+// heights between zero and one, rows running along +Z
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightmap {
+    width: u32,
+    height: u32,
+    heights: Vec<f32>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainOptions {
+    // distance between neighbouring samples on X and Z
+    pub spacing: f32,
+    // height of a white sample
+    pub height_scale: f32,
+    // quads along each side of a tile at full detail
+    pub tile_size: u32,
+    // full detail included, every level halves the samples along each side
+    pub lod_count: u32,
+    // how far the skirts hang below the tile borders, zero leaves them out
+    pub skirt_depth: f32
+}
+
+// mesh primitives are the full detail, lods the coarser levels with their largest height error
+pub struct TerrainTile {
+    pub x: u32,
+    pub y: u32,
+    pub mesh: Mesh
+}
+
+impl Default for TerrainOptions {
+    fn default() -> Self {
+        Self {
+            spacing: 1.0,
+            height_scale: 32.0,
+            tile_size: 64,
+            lod_count: 4,
+            skirt_depth: 1.0
+        }
+    }
+}
+
+impl Heightmap {
+    pub fn new(width: u32, height: u32, heights: Vec<f32>) -> Result<Self, RenderError> {
+        if Some(heights.len()) != (width as usize).checked_mul(height as usize) {
+            return Err(RenderError::Geometry(format!("{} heights do not fill {}x{} samples", heights.len(), width, height)));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            heights
+        })
+    }
+
+    // 16 bit images keep their precision, everything else goes through 8 bit luma
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let (width, height, heights) = match image.as_luma16() {
+            Some(luma) => (luma.width(), luma.height(), luma.pixels().map(|x| x[0] as f32 / u16::MAX as f32).collect()),
+            None => {
+                let luma = image.to_luma8();
+                (luma.width(), luma.height(), luma.pixels().map(|x| x[0] as f32 / u8::MAX as f32).collect())
+            }
+        };
+
+        // one height per pixel, so the sizes always match
+        Self {
+            width,
+            height,
+            heights
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, RenderError> {
+        Ok(Self::from_image(&image::open(path)?))
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // samples outside the map read the closest border sample
+    pub fn get(&self, x: i64, y: i64) -> f32 {
+        let x = x.clamp(0, self.width as i64 - 1);
+        let y = y.clamp(0, self.height as i64 - 1);
+
+        self.heights[(x + y * self.width as i64) as usize]
+    }
+}
+
+// splits the map into tiles sharing their border samples, the terrain is centered on the origin with +Y up
+pub fn generate_terrain(heightmap: &Heightmap, options: &TerrainOptions) -> Result<Vec<TerrainTile>, RenderError> {
+    if heightmap.width < 2 || heightmap.height < 2 || options.tile_size == 0 {
+        return Err(RenderError::Geometry("a terrain needs at least 2x2 samples and non empty tiles".to_string()));
+    }
+
+    let quads = [heightmap.width - 1, heightmap.height - 1];
+    let tiles = [quads[0].div_ceil(options.tile_size), quads[1].div_ceil(options.tile_size)];
+    let mut terrain: Vec<TerrainTile> = Vec::with_capacity((tiles[0] * tiles[1]) as usize);
+
+    for y in 0..tiles[1] {
+        for x in 0..tiles[0] {
+            let start = [x * options.tile_size, y * options.tile_size];
+            let end = [(start[0] + options.tile_size).min(quads[0]), (start[1] + options.tile_size).min(quads[1])];
+
+            let mut mesh = Mesh::new(vec![tile_primitive(heightmap, options, start, end, 1)]);
+
+            for level in 1..options.lod_count.max(1) {
+                let step = 1 << level;
+
+                // nothing left to drop once a level is down to a single quad
+                if step >= options.tile_size * 2 {
+                    break;
+                }
+
+                let error = lod_error(heightmap, start, end, step) * options.height_scale;
+                mesh.lods.push(Lod::new(vec![tile_primitive(heightmap, options, start, end, step)], error));
+            }
+
+            terrain.push(TerrainTile {
+                x,
+                y,
+                mesh
+            });
+        }
+    }
+
+    Ok(terrain)
+}
+
+// every step-th sample between start and end, the end always included so neighbours meet at any level
+fn samples(start: u32, end: u32, step: u32) -> Vec<u32> {
+    let mut samples: Vec<u32> = (start..end).step_by(step as usize).collect();
+    samples.push(end);
+
+    samples
+}
+
+// normals and tangents come from the full detail map, so every level and every tile shades the same
+fn terrain_vertex(heightmap: &Heightmap, options: &TerrainOptions, x: u32, y: u32) -> Vertex {
+    let (x, y) = (x as i64, y as i64);
+    let center = Vector2::new((heightmap.width - 1) as f32, (heightmap.height - 1) as f32) * 0.5;
+
+    let position = Vector3::new((x as f32 - center.x) * options.spacing,
+                                heightmap.get(x, y) * options.height_scale,
+                                (y as f32 - center.y) * options.spacing);
+
+    // one sided on the border, where get clamps
+    let slope = |a: f32, b: f32, distance: i64| (a - b) * options.height_scale / (distance as f32 * options.spacing);
+    let dx = slope(heightmap.get(x + 1, y), heightmap.get(x - 1, y), (x + 1).min(heightmap.width as i64 - 1) - (x - 1).max(0));
+    let dz = slope(heightmap.get(x, y + 1), heightmap.get(x, y - 1), (y + 1).min(heightmap.height as i64 - 1) - (y - 1).max(0));
+
+    let normal = Vector3::new(-dx, 1.0, -dz).normalize();
+    let along_x = Vector3::new(1.0, dx, 0.0);
+    let tangent = (along_x - normal * normal.dot(&along_x)).normalize();
+    let handedness = if normal.cross(&tangent).dot(&Vector3::new(0.0, dz, 1.0)) < 0.0 { -1.0 } else { 1.0 };
+
+    let uv = Vector2::new(x as f32 / (heightmap.width - 1) as f32, y as f32 / (heightmap.height - 1) as f32);
+
+    Vertex::new(position, normal, Vector4::new(tangent.x, tangent.y, tangent.z, handedness), uv)
+}
+
+fn tile_primitive(heightmap: &Heightmap, options: &TerrainOptions, start: [u32; 2], end: [u32; 2], step: u32) -> Primitive {
+    let xs = samples(start[0], end[0], step);
+    let ys = samples(start[1], end[1], step);
+    let columns = xs.len() as u32;
+
+    let mut vertices: Vec<Vertex> = Vec::with_capacity(xs.len() * ys.len());
+    let mut indices: Vec<u32> = Vec::with_capacity((xs.len() - 1) * (ys.len() - 1) * 6);
+
+    for y in ys.iter() {
+        for x in xs.iter() {
+            vertices.push(terrain_vertex(heightmap, options, *x, *y));
+        }
+    }
+
+    for row in 0..ys.len() as u32 - 1 {
+        for column in 0..columns - 1 {
+            let corner = |dx: u32, dy: u32| column + dx + (row + dy) * columns;
+            indices.extend_from_slice(&[corner(0, 0), corner(0, 1), corner(1, 1), corner(0, 0), corner(1, 1), corner(1, 0)]);
+        }
+    }
+
+    if options.skirt_depth > 0.0 {
+        // around the border, +X along the first row, so the skirt triangles face away from the tile
+        let rows = ys.len() as u32;
+        let mut border: Vec<u32> = Vec::new();
+        border.extend(0..columns);
+        border.extend((1..rows).map(|y| columns - 1 + y * columns));
+        border.extend((0..columns - 1).rev().map(|x| x + (rows - 1) * columns));
+        border.extend((0..rows - 1).rev().map(|y| y * columns));
+
+        let first = vertices.len() as u32;
+
+        for corner in border.iter() {
+            let mut skirt = vertices[*corner as usize];
+            skirt.set_position(skirt.position() - Vector3::y() * options.skirt_depth);
+            vertices.push(skirt);
+        }
+
+        for (index, pair) in border.windows(2).enumerate() {
+            let (a, b) = (pair[0], pair[1]);
+            let (lower_a, lower_b) = (first + index as u32, first + index as u32 + 1);
+            indices.extend_from_slice(&[a, b, lower_b, a, lower_b, lower_a]);
+        }
+    }
+
+    Primitive::new(vertices, indices, 0, PrimitiveTopology::TriangleList)
+}
+
+// largest difference between the full detail heights and the triangles of a coarser level
+fn lod_error(heightmap: &Heightmap, start: [u32; 2], end: [u32; 2], step: u32) -> f32 {
+    let xs = samples(start[0], end[0], step);
+    let ys = samples(start[1], end[1], step);
+    let mut error = 0f32;
+
+    for cell_y in ys.windows(2) {
+        for cell_x in xs.windows(2) {
+            let height = |x: u32, y: u32| heightmap.get(x as i64, y as i64);
+            let [h00, h10, h01, h11] = [height(cell_x[0], cell_y[0]), height(cell_x[1], cell_y[0]),
+                                        height(cell_x[0], cell_y[1]), height(cell_x[1], cell_y[1])];
+
+            for y in cell_y[0]..=cell_y[1] {
+                for x in cell_x[0]..=cell_x[1] {
+                    let fx = (x - cell_x[0]) as f32 / (cell_x[1] - cell_x[0]) as f32;
+                    let fy = (y - cell_y[0]) as f32 / (cell_y[1] - cell_y[0]) as f32;
+
+                    // the diagonal runs from the first corner to the last
+                    let interpolated = if fy >= fx {
+                        h00 + fx * (h11 - h01) + fy * (h01 - h00)
+                    } else {
+                        h00 + fx * (h10 - h00) + fy * (h11 - h10)
+                    };
+
+                    error = error.max((height(x, y) - interpolated).abs());
+                }
+            }
+        }
+    }
+
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn random_heightmap(width: u32, height: u32) -> Heightmap {
+        let mut rng = StdRng::seed_from_u64(48);
+        Heightmap::new(width, height, (0..width * height).map(|_| rng.gen_range(0.0, 1.0)).collect()).unwrap()
+    }
+
+    fn options(tile_size: u32, lod_count: u32, skirt_depth: f32) -> TerrainOptions {
+        TerrainOptions {
+            tile_size,
+            lod_count,
+            skirt_depth,
+            ..TerrainOptions::default()
+        }
+    }
+
+    #[test]
+    fn mismatched_heights_are_rejected() {
+        assert!(Heightmap::new(3, 3, vec![0.0; 8]).is_err());
+        assert!(Heightmap::new(u32::MAX, u32::MAX, Vec::new()).is_err());
+        assert!(generate_terrain(&Heightmap::new(1, 4, vec![0.0; 4]).unwrap(), &TerrainOptions::default()).is_err());
+    }
+
+    #[test]
+    fn tiles_cover_the_map_and_share_their_borders() {
+        let heightmap = random_heightmap(9, 6);
+        let terrain = generate_terrain(&heightmap, &options(3, 1, 0.0)).unwrap();
+
+        // 8x5 quads in tiles of 3, the last column and row narrower
+        let coordinates: Vec<(u32, u32)> = terrain.iter().map(|x| (x.x, x.y)).collect();
+        assert_eq!(coordinates, vec![(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]);
+        assert_eq!(terrain[2].mesh.primitives[0].vertex().len(), 3 * 4);
+        assert_eq!(terrain[5].mesh.primitives[0].vertex().len(), 3 * 3);
+
+        let samples_at = |tile: &TerrainTile, on_x: bool, at: f32| {
+            let mut samples: Vec<[u32; 3]> = tile.mesh.primitives[0].vertex()
+                .iter()
+                .map(|x| *x.position())
+                .filter(|x| (if on_x { x.x } else { x.z }) == at)
+                .map(|x| [x.x.to_bits(), x.y.to_bits(), x.z.to_bits()])
+                .collect();
+            samples.sort_unstable();
+            samples
+        };
+
+        // centered on the origin, sample 3 along X sits at -1 and sample 3 along Z at 0.5
+        let shared = samples_at(&terrain[0], true, -1.0);
+        assert_eq!(shared.len(), 4);
+        assert_eq!(shared, samples_at(&terrain[1], true, -1.0));
+
+        let shared = samples_at(&terrain[1], false, 0.5);
+        assert_eq!(shared.len(), 4);
+        assert_eq!(shared, samples_at(&terrain[4], false, 0.5));
+    }
+
+    #[test]
+    fn levels_stop_at_a_single_quad() {
+        let heightmap = random_heightmap(9, 9);
+        let terrain = generate_terrain(&heightmap, &options(4, 10, 0.0)).unwrap();
+        let mesh = &terrain[0].mesh;
+
+        // steps of 2 and 4, a step of 8 would be past the tile
+        assert_eq!(mesh.lods.len(), 2);
+        assert_eq!(mesh.lods[0].primitives[0].vertex().len(), 3 * 3);
+        assert_eq!(mesh.lods[1].primitives[0].vertex().len(), 2 * 2);
+        assert_eq!(mesh.lods[1].primitives[0].indices().len(), 6);
+        assert!(mesh.lods.iter().all(|x| x.error > 0.0));
+
+        let terrain = generate_terrain(&heightmap, &options(4, 1, 0.0)).unwrap();
+        assert!(terrain[0].mesh.lods.is_empty());
+    }
+
+    #[test]
+    fn skirts_hang_below_the_whole_border() {
+        let heightmap = random_heightmap(5, 4);
+        let terrain = generate_terrain(&heightmap, &options(8, 1, 2.0)).unwrap();
+        let primitive = &terrain[0].mesh.primitives[0];
+
+        // a 4x3 quad tile has 20 samples, 14 border edges with the loop closing on its first sample
+        assert_eq!(primitive.vertex().len(), 20 + 15);
+        assert_eq!(primitive.indices().len(), 4 * 3 * 6 + 14 * 6);
+
+        for skirt in primitive.vertex()[20..].iter() {
+            assert!(primitive.vertex()[..20].iter().any(|x| x.position() - Vector3::y() * 2.0 == *skirt.position()));
+        }
+    }
+
+    #[test]
+    fn planar_heightmaps_have_no_lod_error() {
+        let heights = (0..17 * 13).map(|x| (x % 17) as f32 * 0.02 + (x / 17) as f32 * 0.03).collect();
+        let heightmap = Heightmap::new(17, 13, heights).unwrap();
+
+        for tile in generate_terrain(&heightmap, &options(8, 4, 0.0)).unwrap() {
+            assert!(!tile.mesh.lods.is_empty());
+            assert!(tile.mesh.lods.iter().all(|x| x.error < 1e-4));
+        }
+    }
+
+    #[test]
+    fn triangles_face_up() {
+        let heightmap = random_heightmap(7, 7);
+
+        for tile in generate_terrain(&heightmap, &options(4, 3, 0.0)).unwrap() {
+            let levels = std::iter::once(&tile.mesh.primitives[0]).chain(tile.mesh.lods.iter().map(|x| &x.primitives[0]));
+
+            for primitive in levels {
+                for triangle in primitive.indices().to_vec().chunks_exact(3) {
+                    let corner = |x: usize| *primitive.vertex()[triangle[x] as usize].position();
+                    assert!((corner(1) - corner(0)).cross(&(corner(2) - corner(0))).y > 0.0);
+                }
+
+                assert!(primitive.vertex().iter().all(|x| x.normal().y > 0.0));
+            }
+        }
+    }
+}
@@ -0,0 +1,499 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, rc::Rc};
+use image::{Rgba, RgbaImage};
+use wgpu::{BufferCopyView,
+           BufferUsage,
+           CommandEncoder,
+           Device,
+           Extent3d,
+           Origin3d,
+           TextureCopyView,
+           TextureDescriptor,
+           TextureDimension,
+           TextureAspect,
+           TextureFormat,
+           TextureUsage,
+           TextureView,
+           TextureViewDescriptor,
+           TextureViewDimension};
+use crate::renderer::RenderError;
+
+// buffer to texture copies need rows padded to this many bytes
+const BYTES_PER_ROW_ALIGNMENT: u32 = 256;
+const MISSING_TEXTURE_SIZE: u32 = 64;
+const MISSING_TEXTURE_CHECKER: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureSlot {
+    BaseColor,
+    Normal,
+    MetallicRoughness
+}
+
+// generated in memory, never read from disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuiltinTexture {
+    Default(TextureSlot),
+    Missing(TextureSlot)
+}
+
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>
+}
+
+// every layer holds its own mip chain, cubemaps use six of them
+pub struct TextureData {
+    format: TextureFormat,
+    bytes_per_pixel: u32,
+    layers: Vec<Vec<MipLevel>>
+}
+
+pub struct TextureLoader {
+    base_path: PathBuf,
+    cache: HashMap<(PathBuf, TextureFormat), Rc<wgpu::Texture>>,
+    builtin: HashMap<BuiltinTexture, Rc<wgpu::Texture>>
+}
+
+impl TextureSlot {
+    pub fn format(self) -> TextureFormat {
+        match self {
+            TextureSlot::BaseColor => TextureFormat::Rgba8UnormSrgb,
+            TextureSlot::Normal => TextureFormat::Rgba8Unorm,
+            TextureSlot::MetallicRoughness => TextureFormat::Rgba8Unorm
+        }
+    }
+
+    pub fn is_srgb(self) -> bool {
+        self.format() == TextureFormat::Rgba8UnormSrgb
+    }
+
+    // white albedo, flat (0.5, 0.5, 1) normal and white metal roughness so factors pass through untouched
+    pub fn default_color(self) -> Rgba<u8> {
+        match self {
+            TextureSlot::BaseColor => Rgba([255, 255, 255, 255]),
+            TextureSlot::Normal => Rgba([128, 128, 255, 255]),
+            TextureSlot::MetallicRoughness => Rgba([255, 255, 255, 255])
+        }
+    }
+}
+
+impl BuiltinTexture {
+    pub fn slot(self) -> TextureSlot {
+        match self {
+            BuiltinTexture::Default(slot) => slot,
+            BuiltinTexture::Missing(slot) => slot
+        }
+    }
+
+    pub fn create_image(self) -> RgbaImage {
+        match self {
+            BuiltinTexture::Default(slot) => RgbaImage::from_pixel(1, 1, slot.default_color()),
+            // a checkerboard on a normal or metal roughness map would only break the shading
+            BuiltinTexture::Missing(TextureSlot::BaseColor) => checkerboard(MISSING_TEXTURE_SIZE, MISSING_TEXTURE_CHECKER),
+            BuiltinTexture::Missing(slot) => RgbaImage::from_pixel(1, 1, slot.default_color())
+        }
+    }
+
+    pub fn create_data(self) -> TextureData {
+        TextureData::from_rgba(&self.create_image(), self.slot())
+    }
+}
+
+impl TextureData {
+    pub fn new(format: TextureFormat, bytes_per_pixel: u32, mips: Vec<MipLevel>) -> Self {
+        Self::new_layered(format, bytes_per_pixel, vec![mips])
+    }
+
+    pub fn new_layered(format: TextureFormat, bytes_per_pixel: u32, layers: Vec<Vec<MipLevel>>) -> Self {
+        Self {
+            format,
+            bytes_per_pixel,
+            layers
+        }
+    }
+
+    pub fn from_file<P>(path: P, slot: TextureSlot) -> Result<Self, RenderError>
+        where P: AsRef<Path> {
+        let image = image::open(path)?.into_rgba8();
+
+        Ok(Self::from_rgba(&image, slot))
+    }
+
+    pub fn from_memory(bytes: &[u8], slot: TextureSlot) -> Result<Self, RenderError> {
+        let image = image::load_from_memory(bytes)?.into_rgba8();
+
+        Ok(Self::from_rgba(&image, slot))
+    }
+
+    pub fn from_rgba(image: &RgbaImage, slot: TextureSlot) -> Self {
+        let (width, height) = image.dimensions();
+        let base = MipLevel {
+            width,
+            height,
+            data: image.clone().into_raw()
+        };
+
+        Self::new(slot.format(), 4, generate_mips(base, slot))
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    pub fn width(&self) -> u32 {
+        self.layers[0][0].width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.layers[0][0].height
+    }
+
+    pub fn mips(&self) -> &Vec<MipLevel> {
+        &self.layers[0]
+    }
+
+    pub fn layers(&self) -> &Vec<Vec<MipLevel>> {
+        &self.layers
+    }
+
+    pub fn create_view(&self, texture: &wgpu::Texture, dimension: TextureViewDimension) -> TextureView {
+        texture.create_view(&TextureViewDescriptor {
+            format: self.format,
+            dimension,
+            aspect: TextureAspect::All,
+            base_mip_level: 0,
+            level_count: self.mips().len() as u32,
+            base_array_layer: 0,
+            array_layer_count: self.layers.len() as u32
+        })
+    }
+
+    // the copies are recorded into the encoder, the caller is responsible for submitting it
+    pub fn create_texture(&self, device: &Device, encoder: &mut CommandEncoder) -> wgpu::Texture {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: self.width(),
+                height: self.height(),
+                depth: 1
+            },
+            array_layer_count: self.layers.len() as u32,
+            mip_level_count: self.mips().len() as u32,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: self.format,
+            usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST
+        });
+
+        for (layer, level, mip) in self.layers
+            .iter()
+            .enumerate()
+            .flat_map(|(layer, mips)| mips.iter().enumerate().map(move |(level, mip)| (layer, level, mip))) {
+            let row_size = mip.width * self.bytes_per_pixel;
+            let padded_row_size = row_size.div_ceil(BYTES_PER_ROW_ALIGNMENT) * BYTES_PER_ROW_ALIGNMENT;
+
+            let mut padded: Vec<u8> = vec![0; (padded_row_size * mip.height) as usize];
+            for (row, chunk) in mip.data.chunks(row_size as usize).enumerate() {
+                let start = row * padded_row_size as usize;
+                padded[start..start + chunk.len()].copy_from_slice(chunk);
+            }
+
+            let staging = device.create_buffer_with_data(&padded, BufferUsage::COPY_SRC);
+
+            encoder.copy_buffer_to_texture(
+                BufferCopyView {
+                    buffer: &staging,
+                    offset: 0,
+                    bytes_per_row: padded_row_size,
+                    rows_per_image: 0
+                },
+                TextureCopyView {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    array_layer: layer as u32,
+                    origin: Origin3d::ZERO
+                },
+                Extent3d {
+                    width: mip.width,
+                    height: mip.height,
+                    depth: 1
+                });
+        }
+
+        texture
+    }
+}
+
+impl TextureLoader {
+    pub fn new<P>(base_path: P) -> Self
+        where P: Into<PathBuf> {
+        Self {
+            base_path: base_path.into(),
+            cache: HashMap::new(),
+            builtin: HashMap::new()
+        }
+    }
+
+    pub fn for_gltf<P>(gltf_path: P) -> Self
+        where P: AsRef<Path> {
+        let base_path = gltf_path.as_ref().parent().map(Path::to_path_buf).unwrap_or_default();
+
+        Self::new(base_path)
+    }
+
+    pub fn resolve(&self, file_name: &str) -> PathBuf {
+        let path = self.base_path.join(file_name);
+
+        path.canonicalize().unwrap_or(path)
+    }
+
+    pub fn load(&mut self,
+                device: &Device,
+                encoder: &mut CommandEncoder,
+                file_name: &str,
+                slot: TextureSlot) -> Result<Rc<wgpu::Texture>, RenderError> {
+        let key = (self.resolve(file_name), slot.format());
+
+        if let Some(texture) = self.cache.get(&key) {
+            return Ok(texture.clone());
+        }
+
+        let data = TextureData::from_file(&key.0, slot)?;
+
+        Ok(self.upload(device, encoder, key, &data))
+    }
+
+    // never fails, no file gives the slot default and an unreadable one the missing texture
+    pub fn load_or_default(&mut self,
+                           device: &Device,
+                           encoder: &mut CommandEncoder,
+                           file_name: Option<&str>,
+                           slot: TextureSlot) -> Rc<wgpu::Texture> {
+        let key = match file_name {
+            Some(name) => (self.resolve(name), slot.format()),
+            None => return self.builtin(device, encoder, BuiltinTexture::Default(slot))
+        };
+
+        if let Some(texture) = self.cache.get(&key) {
+            return texture.clone();
+        }
+
+        match self.read(file_name, slot) {
+            Ok(data) => self.upload(device, encoder, key, &data),
+            Err(builtin) => self.builtin(device, encoder, builtin)
+        }
+    }
+
+    // what load_or_default uploads, or the builtin standing in for it, without touching the cache
+    pub fn read(&self, file_name: Option<&str>, slot: TextureSlot) -> Result<TextureData, BuiltinTexture> {
+        match file_name {
+            Some(name) => TextureData::from_file(self.resolve(name), slot).map_err(|_| BuiltinTexture::Missing(slot)),
+            None => Err(BuiltinTexture::Default(slot))
+        }
+    }
+
+    pub fn builtin(&mut self,
+                   device: &Device,
+                   encoder: &mut CommandEncoder,
+                   builtin: BuiltinTexture) -> Rc<wgpu::Texture> {
+        self.builtin
+            .entry(builtin)
+            .or_insert_with(|| Rc::new(builtin.create_data().create_texture(device, encoder)))
+            .clone()
+    }
+
+    fn upload(&mut self,
+              device: &Device,
+              encoder: &mut CommandEncoder,
+              key: (PathBuf, TextureFormat),
+              data: &TextureData) -> Rc<wgpu::Texture> {
+        let texture = Rc::new(data.create_texture(device, encoder));
+        self.cache.insert(key, texture.clone());
+
+        texture
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.builtin.clear();
+    }
+}
+
+// magenta and black, hard to miss on screen
+fn checkerboard(size: u32, checker: u32) -> RgbaImage {
+    RgbaImage::from_fn(size, size, |x, y| {
+        if ((x / checker) ^ (y / checker)) & 1 == 0 {
+            Rgba([255, 0, 255, 255])
+        } else {
+            Rgba([0, 0, 0, 255])
+        }
+    })
+}
+
+fn mip_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// box filters every level from the previous one, down to 1x1
+fn generate_mips(base: MipLevel, slot: TextureSlot) -> Vec<MipLevel> {
+    let count = mip_count(base.width, base.height);
+    let decode: Vec<f32> = (0..256u32)
+        .map(|x| {
+            let value = x as f32 / 255.0;
+            if slot.is_srgb() { srgb_to_linear(value) } else { value }
+        })
+        .collect();
+
+    let mut mips: Vec<MipLevel> = Vec::with_capacity(count as usize);
+    mips.push(base);
+
+    for _ in 1..count {
+        let previous = mips.last().unwrap();
+        let width = (previous.width / 2).max(1);
+        let height = (previous.height / 2).max(1);
+        let mut data: Vec<u8> = Vec::with_capacity((width * height * 4) as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0f32; 4];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                    let sx = (x * 2 + dx).min(previous.width - 1);
+                    let sy = (y * 2 + dy).min(previous.height - 1);
+                    let offset = ((sy * previous.width + sx) * 4) as usize;
+                    for c in 0..3 {
+                        sum[c] += decode[previous.data[offset + c] as usize];
+                    }
+                    // alpha is always linear
+                    sum[3] += previous.data[offset + 3] as f32 / 255.0;
+                }
+
+                let mut texel = [sum[0] / 4.0, sum[1] / 4.0, sum[2] / 4.0, sum[3] / 4.0];
+
+                if slot == TextureSlot::Normal {
+                    texel = renormalize(texel);
+                }
+
+                for (c, value) in texel.iter().enumerate() {
+                    let value = if c < 3 && slot.is_srgb() { linear_to_srgb(*value) } else { *value };
+                    data.push((value.clamp(0.0, 1.0) * 255.0).round() as u8);
+                }
+            }
+        }
+
+        mips.push(MipLevel {
+            width,
+            height,
+            data
+        });
+    }
+
+    mips
+}
+
+// averaged normals get shorter, bring them back to unit length
+fn renormalize(texel: [f32; 4]) -> [f32; 4] {
+    let x = texel[0] * 2.0 - 1.0;
+    let y = texel[1] * 2.0 - 1.0;
+    let z = texel[2] * 2.0 - 1.0;
+    let length = (x * x + y * y + z * z).sqrt();
+
+    if length <= f32::EPSILON {
+        return [0.5, 0.5, 1.0, texel[3]];
+    }
+
+    [(x / length) * 0.5 + 0.5, (y / length) * 0.5 + 0.5, (z / length) * 0.5 + 0.5, texel[3]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(width: u32, height: u32, pixels: &[[u8; 4]]) -> MipLevel {
+        MipLevel {
+            width,
+            height,
+            data: pixels.iter().flatten().copied().collect()
+        }
+    }
+
+    #[test]
+    fn mip_counts_reach_one_texel() {
+        assert_eq!(mip_count(0, 0), 1);
+        assert_eq!(mip_count(1, 1), 1);
+        assert_eq!(mip_count(2, 1), 2);
+        assert_eq!(mip_count(256, 256), 9);
+        assert_eq!(mip_count(300, 5), 9);
+    }
+
+    #[test]
+    fn mips_halve_down_to_one_texel() {
+        let mips = generate_mips(level(5, 3, &[[0, 0, 0, 255]; 15]), TextureSlot::MetallicRoughness);
+        let sizes: Vec<(u32, u32)> = mips.iter().map(|x| (x.width, x.height)).collect();
+
+        assert_eq!(sizes, vec![(5, 3), (2, 1), (1, 1)]);
+        assert!(mips.iter().all(|x| x.data.len() == (x.width * x.height * 4) as usize));
+    }
+
+    #[test]
+    fn mips_average_in_linear_space() {
+        let pixels = [[0, 0, 0, 0], [100, 10, 20, 100], [200, 20, 40, 200], [100, 10, 20, 100]];
+        let mips = generate_mips(level(2, 2, &pixels), TextureSlot::MetallicRoughness);
+        assert_eq!(mips[1].data, vec![100, 10, 20, 100]);
+
+        // black and white meet at half the light, not at half the srgb value
+        let pixels = [[0, 0, 0, 0], [255, 255, 255, 255], [0, 0, 0, 0], [255, 255, 255, 255]];
+        let mips = generate_mips(level(2, 2, &pixels), TextureSlot::BaseColor);
+        assert_eq!(mips[1].data, vec![188, 188, 188, 128]);
+    }
+
+    #[test]
+    fn normal_mips_stay_unit_length() {
+        let pixels = [[255, 128, 128, 255], [128, 128, 255, 255]];
+        let mips = generate_mips(level(2, 1, &pixels), TextureSlot::Normal);
+        let normal: Vec<f32> = mips[1].data[..3].iter().map(|x| *x as f32 / 255.0 * 2.0 - 1.0).collect();
+        let length = normal.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+        assert!((length - 1.0).abs() < 0.02, "length {}", length);
+        assert!((normal[0] - normal[2]).abs() < 0.02);
+        assert_eq!(renormalize([0.5, 0.5, 0.5, 0.25]), [0.5, 0.5, 1.0, 0.25]);
+    }
+
+    #[test]
+    fn unreadable_files_fall_back_to_builtins() {
+        let directory = std::env::temp_dir().join(format!("texture-loader-{}-fallback", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        RgbaImage::from_pixel(4, 2, Rgba([10, 20, 30, 255])).save(directory.join("red.png")).unwrap();
+
+        let loader = TextureLoader::for_gltf(directory.join("scene.gltf"));
+        let slot = TextureSlot::BaseColor;
+
+        let data = loader.read(Some("red.png"), slot).ok().unwrap();
+        assert_eq!((data.width(), data.height(), data.mips().len()), (4, 2, 3));
+        assert_eq!(data.mips()[0].data[..4], [10, 20, 30, 255]);
+
+        assert_eq!(loader.read(Some("missing.png"), slot).err(), Some(BuiltinTexture::Missing(slot)));
+        assert_eq!(loader.read(None, TextureSlot::Normal).err(), Some(BuiltinTexture::Default(TextureSlot::Normal)));
+
+        assert_eq!(BuiltinTexture::Missing(slot).create_image().dimensions(), (MISSING_TEXTURE_SIZE, MISSING_TEXTURE_SIZE));
+        assert_eq!(BuiltinTexture::Missing(TextureSlot::Normal).create_image().into_raw(), vec![128, 128, 255, 255]);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
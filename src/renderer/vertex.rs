@@ -1,20 +1,13 @@
 use nalgebra::{Vector3,
                Vector4,
                Vector2};
-use wgpu::{ BindGroupLayoutDescriptor,
-            BindGroupLayoutEntry,
-            BindingType,
-            IndexFormat,
-            ShaderStage,
-            VertexAttributeDescriptor,
-            VertexBufferDescriptor,
-            VertexFormat,
-            VertexStateDescriptor,
-            InputStepMode,
-            TextureViewDimension,
-            TextureComponentType};
+use wgpu::{IndexFormat,
+           VertexBufferDescriptor,
+           VertexStateDescriptor};
 use bytemuck::{Zeroable,
                Pod};
+use std::sync::OnceLock;
+use crate::renderer::layout::VertexLayout;
 
 unsafe impl Zeroable for Vertex {}
 unsafe impl Pod for Vertex {}
@@ -68,35 +61,31 @@ impl Vertex {
         self.tangent = tangent;
     }
 
-    pub fn get_state_descriptor<'a>() -> VertexStateDescriptor<'a> {
+    pub fn position(&self) -> &Vector3<f32> {
+        &self.position
+    }
+
+    pub fn normal(&self) -> &Vector3<f32> {
+        &self.normal
+    }
+
+    pub fn uv(&self) -> &Vector2<f32> {
+        &self.uv
+    }
+
+    pub fn tangent(&self) -> &Vector4<f32> {
+        &self.tangent
+    }
+
+    // built from VertexLayout::standard(), the one description of this struct, kept around so it can be
+    // borrowed for any lifetime
+    pub fn get_state_descriptor<'a>(index_format: IndexFormat) -> VertexStateDescriptor<'a> {
+        static LAYOUT: OnceLock<VertexLayout> = OnceLock::new();
+        static BUFFERS: OnceLock<Vec<VertexBufferDescriptor<'static>>> = OnceLock::new();
+
         VertexStateDescriptor {
-            index_format: IndexFormat::Uint32,
-            vertex_buffers: &[VertexBufferDescriptor {
-                stride: std::mem::size_of::<Vertex>() as u64,
-                step_mode: InputStepMode::Vertex,
-                attributes: &[
-                    VertexAttributeDescriptor {
-                        format: VertexFormat::Float3,
-                        shader_location: 0,
-                        offset: 0,
-                    },
-                    VertexAttributeDescriptor {
-                        format: VertexFormat::Float3,
-                        shader_location: 1,
-                        offset: 3 * 4,
-                    },
-                    VertexAttributeDescriptor {
-                        format: VertexFormat::Float4,
-                        shader_location: 2,
-                        offset: 6 * 4,
-                    },
-                    VertexAttributeDescriptor {
-                        format: VertexFormat::Float2,
-                        shader_location: 3,
-                        offset: 10 * 4,
-                    },
-                ],
-            }],
+            index_format,
+            vertex_buffers: BUFFERS.get_or_init(|| LAYOUT.get_or_init(VertexLayout::standard).buffer_descriptors())
         }
     }
 }
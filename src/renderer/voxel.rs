@@ -0,0 +1,344 @@
+use std::collections::{BTreeMap, HashMap};
+use nalgebra::{Vector2, Vector3, Vector4};
+use wgpu::PrimitiveTopology;
+use crate::renderer::{shapes, vertex::Vertex, Mesh, Primitive};
+
+pub const CHUNK_SIZE: u32 = 16;
+pub const AIR: Block = 0;
+
+// block type, zero is empty space
+pub type Block = u16;
+pub type ChunkCoordinate = [i32; 3];
+
+// blocks with x changing fastest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    blocks: Vec<Block>,
+    solid: u32
+}
+
+#[derive(Debug, Clone)]
+pub struct VoxelWorld {
+    voxel_size: f32,
+    chunks: HashMap<ChunkCoordinate, Chunk>
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::filled(AIR)
+    }
+
+    pub fn filled(block: Block) -> Self {
+        let count = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+        Self {
+            blocks: vec![block; count as usize],
+            solid: if block == AIR { 0 } else { count }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.solid == 0
+    }
+
+    pub fn blocks(&self) -> &Vec<Block> {
+        &self.blocks
+    }
+
+    pub fn index(x: u32, y: u32, z: u32) -> usize {
+        (x + CHUNK_SIZE * (y + CHUNK_SIZE * z)) as usize
+    }
+
+    pub fn get(&self, x: u32, y: u32, z: u32) -> Block {
+        self.blocks[Self::index(x, y, z)]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, z: u32, block: Block) {
+        let previous = std::mem::replace(&mut self.blocks[Self::index(x, y, z)], block);
+
+        match (previous == AIR, block == AIR) {
+            (true, false) => self.solid += 1,
+            (false, true) => self.solid -= 1,
+            _ => {}
+        }
+    }
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VoxelWorld {
+    pub fn new(voxel_size: f32) -> Self {
+        Self {
+            voxel_size,
+            chunks: HashMap::new()
+        }
+    }
+
+    pub fn voxel_size(&self) -> f32 {
+        self.voxel_size
+    }
+
+    pub fn chunk(&self, coordinate: ChunkCoordinate) -> Option<&Chunk> {
+        self.chunks.get(&coordinate)
+    }
+
+    pub fn chunk_mut(&mut self, coordinate: ChunkCoordinate) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&coordinate)
+    }
+
+    pub fn insert_chunk(&mut self, coordinate: ChunkCoordinate, chunk: Chunk) -> Option<Chunk> {
+        self.chunks.insert(coordinate, chunk)
+    }
+
+    pub fn remove_chunk(&mut self, coordinate: ChunkCoordinate) -> Option<Chunk> {
+        self.chunks.remove(&coordinate)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (&ChunkCoordinate, &Chunk)> {
+        self.chunks.iter()
+    }
+
+    // chunk holding a block and the block's position inside it
+    pub fn locate(x: i32, y: i32, z: i32) -> (ChunkCoordinate, [u32; 3]) {
+        let size = CHUNK_SIZE as i32;
+        let split = |x: i32| (x.div_euclid(size), x.rem_euclid(size) as u32);
+        let ((cx, lx), (cy, ly), (cz, lz)) = (split(x), split(y), split(z));
+
+        ([cx, cy, cz], [lx, ly, lz])
+    }
+
+    // blocks in chunks that were never loaded are air
+    pub fn block(&self, x: i32, y: i32, z: i32) -> Block {
+        let (coordinate, [lx, ly, lz]) = Self::locate(x, y, z);
+        self.chunks.get(&coordinate).map_or(AIR, |chunk| chunk.get(lx, ly, lz))
+    }
+
+    // creates the chunk when a block lands outside the loaded ones
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, block: Block) {
+        let (coordinate, [lx, ly, lz]) = Self::locate(x, y, z);
+
+        if block == AIR && !self.chunks.contains_key(&coordinate) {
+            return;
+        }
+
+        self.chunks.entry(coordinate).or_default().set(lx, ly, lz, block);
+    }
+
+    // greedy meshes one chunk in world space, faces touching a solid block are culled, across chunk borders too,
+    // one primitive per material with uvs tiling once per block
+    pub fn mesh_chunk<F>(&self, coordinate: ChunkCoordinate, material: F) -> Mesh
+        where F: Fn(Block) -> usize {
+        let chunk = match self.chunks.get(&coordinate) {
+            Some(chunk) if !chunk.is_empty() => chunk,
+            _ => return Mesh::new(Vec::new())
+        };
+
+        let size = CHUNK_SIZE as i32;
+        let base = [coordinate[0] * size, coordinate[1] * size, coordinate[2] * size];
+        let mut geometry: BTreeMap<usize, (Vec<Vertex>, Vec<u32>)> = BTreeMap::new();
+        let mut mask: Vec<Block> = vec![AIR; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+            for &positive in [false, true].iter() {
+                let step = if positive { 1 } else { -1 };
+
+                for depth in 0..size {
+                    for (cell, face) in mask.iter_mut().enumerate() {
+                        let mut local = [0i32; 3];
+                        local[axis] = depth;
+                        local[u] = cell as i32 % size;
+                        local[v] = cell as i32 / size;
+
+                        let block = chunk.get(local[0] as u32, local[1] as u32, local[2] as u32);
+                        let mut neighbour = local;
+                        neighbour[axis] += step;
+
+                        let covered = if neighbour[axis] >= 0 && neighbour[axis] < size {
+                            chunk.get(neighbour[0] as u32, neighbour[1] as u32, neighbour[2] as u32) != AIR
+                        } else {
+                            self.block(base[0] + neighbour[0], base[1] + neighbour[1], base[2] + neighbour[2]) != AIR
+                        };
+
+                        *face = if covered { AIR } else { block };
+                    }
+
+                    // widest run along u first, then as many matching rows along v as possible
+                    for start_v in 0..size {
+                        let mut start_u = 0;
+
+                        while start_u < size {
+                            let block = mask[(start_u + start_v * size) as usize];
+
+                            if block == AIR {
+                                start_u += 1;
+                                continue;
+                            }
+
+                            let width = (start_u..size).take_while(|x| mask[(x + start_v * size) as usize] == block).count() as i32;
+                            let height = (start_v..size)
+                                .take_while(|y| (start_u..start_u + width).all(|x| mask[(x + y * size) as usize] == block))
+                                .count() as i32;
+
+                            for y in start_v..start_v + height {
+                                for x in start_u..start_u + width {
+                                    mask[(x + y * size) as usize] = AIR;
+                                }
+                            }
+
+                            let mut origin = [0i32; 3];
+                            origin[axis] = base[axis] + depth + if positive { 1 } else { 0 };
+                            origin[u] = base[u] + start_u;
+                            origin[v] = base[v] + start_v;
+
+                            let (vertices, indices) = geometry.entry(material(block)).or_default();
+                            self.quad(vertices, indices, axis, positive, origin, [width, height]);
+                            start_u += width;
+                        }
+                    }
+                }
+            }
+        }
+
+        let primitives = geometry
+            .into_iter()
+            .map(|(material, (vertices, indices))| {
+                let mut primitive = Primitive::new(vertices, indices, material, PrimitiveTopology::TriangleList);
+                shapes::generate_tangents(&mut primitive);
+                primitive
+            })
+            .collect();
+
+        Mesh::new(primitives)
+    }
+
+    fn quad(&self, vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, axis: usize, positive: bool, origin: [i32; 3], extent: [i32; 2]) {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let sign = if positive { 1.0 } else { -1.0 };
+        let mut normal = Vector3::zeros();
+        normal[axis] = sign;
+
+        // textures stay upright on the sides and unmirrored from outside, up is +Y or -Z looking down
+        let up = if axis == 1 { Vector3::z() * -sign } else { Vector3::y() };
+        let right = up.cross(&normal);
+
+        let [width, height] = extent;
+        let mut corners = [(0, 0), (width, 0), (width, height), (0, height)];
+
+        // u cross v is the axis, so this order faces the positive side
+        if !positive {
+            corners.reverse();
+        }
+
+        let first = vertices.len() as u32;
+
+        for (du, dv) in corners.iter() {
+            let mut block = origin;
+            block[u] += du;
+            block[v] += dv;
+
+            let position = Vector3::new(block[0] as f32, block[1] as f32, block[2] as f32);
+            let uv = Vector2::new(position.dot(&right), -position.dot(&up));
+
+            vertices.push(Vertex::new(position * self.voxel_size, normal, Vector4::zeros(), uv));
+        }
+
+        indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_coordinates_land_in_negative_chunks() {
+        assert_eq!(VoxelWorld::locate(0, 15, 16), ([0, 0, 1], [0, 15, 0]));
+        assert_eq!(VoxelWorld::locate(-1, -16, -17), ([-1, -1, -2], [15, 0, 15]));
+
+        let mut world = VoxelWorld::new(1.0);
+        world.set_block(-1, -1, -1, 3);
+
+        assert_eq!(world.block(-1, -1, -1), 3);
+        assert_eq!(world.chunk([-1, -1, -1]).unwrap().get(15, 15, 15), 3);
+        assert!(world.chunk([0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn solid_count_follows_set_and_clear() {
+        let mut chunk = Chunk::new();
+        assert!(chunk.is_empty());
+
+        chunk.set(1, 2, 3, 1);
+        chunk.set(1, 2, 3, 2);
+        chunk.set(4, 5, 6, 1);
+        assert_eq!(chunk.solid, 2);
+
+        chunk.set(1, 2, 3, AIR);
+        chunk.set(1, 2, 3, AIR);
+        assert_eq!(chunk.solid, 1);
+
+        chunk.set(4, 5, 6, AIR);
+        assert!(chunk.is_empty());
+        assert_eq!(Chunk::filled(1).solid, CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE);
+    }
+
+    #[test]
+    fn solid_chunk_merges_into_six_quads() {
+        let mut world = VoxelWorld::new(0.5);
+        world.insert_chunk([0, 0, 0], Chunk::filled(1));
+
+        let mesh = world.mesh_chunk([0, 0, 0], |_| 0);
+        assert_eq!(mesh.primitives.len(), 1);
+        assert_eq!(mesh.primitives[0].vertex().len(), 6 * 4);
+        assert_eq!(mesh.primitives[0].indices().len(), 6 * 6);
+
+        let bounds = mesh.bounds();
+        let extent = CHUNK_SIZE as f32 * 0.5;
+        assert_eq!([bounds.aabb.min.x, bounds.aabb.min.y, bounds.aabb.min.z], [0.0; 3]);
+        assert_eq!([bounds.aabb.max.x, bounds.aabb.max.y, bounds.aabb.max.z], [extent; 3]);
+
+        // each quad is wound to face along its normal
+        let primitive = &mesh.primitives[0];
+        for triangle in primitive.indices().to_vec().chunks_exact(3) {
+            let corner = |x: usize| *primitive.vertex()[triangle[x] as usize].position();
+            let normal = (corner(1) - corner(0)).cross(&(corner(2) - corner(0)));
+            assert!(normal.dot(primitive.vertex()[triangle[0] as usize].normal()) > 0.0);
+        }
+    }
+
+    #[test]
+    fn faces_against_a_filled_neighbour_are_culled() {
+        let mut world = VoxelWorld::new(1.0);
+        world.insert_chunk([0, 0, 0], Chunk::filled(1));
+        world.insert_chunk([1, 0, 0], Chunk::filled(1));
+
+        let mesh = world.mesh_chunk([0, 0, 0], |_| 0);
+        let primitive = &mesh.primitives[0];
+
+        assert_eq!(primitive.vertex().len(), 5 * 4);
+        assert!(primitive.vertex().iter().all(|x| x.normal().x < 0.5));
+
+        assert!(world.mesh_chunk([2, 0, 0], |_| 0).primitives.is_empty());
+    }
+
+    #[test]
+    fn one_primitive_per_material() {
+        let mut world = VoxelWorld::new(1.0);
+        world.set_block(0, 0, 0, 1);
+        world.set_block(5, 5, 5, 2);
+        world.set_block(9, 9, 9, 3);
+
+        let mesh = world.mesh_chunk([0, 0, 0], |x| if x == 3 { 1 } else { x as usize * 2 });
+        let materials: Vec<usize> = mesh.primitives.iter().map(|x| x.material_index).collect();
+        let quads: Vec<usize> = mesh.primitives.iter().map(|x| x.vertex().len() / 4).collect();
+
+        assert_eq!(materials, vec![1, 2, 4]);
+        assert_eq!(quads, vec![6, 6, 6]);
+    }
+}
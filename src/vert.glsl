@@ -1,11 +1,21 @@
 #version 450
 
 layout(location = 0) in vec4 in_position;
+layout(location = 3) in vec2 in_uv;
+
+layout(location = 4) in vec4 in_model_0;
+layout(location = 5) in vec4 in_model_1;
+layout(location = 6) in vec4 in_model_2;
+layout(location = 7) in vec4 in_model_3;
+
+layout(location = 0) out vec2 v_uv;
 
 layout(set = 0, binding = 0) uniform Locals {
     mat4 u_Transform;
 };
 
 void main() {
-    gl_Position = u_Transform * in_position;
-}
\ No newline at end of file
+    mat4 model = mat4(in_model_0, in_model_1, in_model_2, in_model_3);
+    v_uv = in_uv;
+    gl_Position = u_Transform * model * in_position;
+}